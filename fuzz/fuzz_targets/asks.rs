@@ -0,0 +1,31 @@
+//! Handles arbitrary sequences of Asks against a new hackstead, by one of a few sets of rules,
+//! making sure that failed Asks never change anything.
+#![no_main]
+use backend::fuzzing::{rules, settle, Cooldowns};
+use hcor::{wormhole::AskMessage, Hackstead};
+use libfuzzer_sys::fuzz_target;
+use std::time::Instant;
+
+fuzz_target!(|data: &[u8]| {
+    let (config, data) = match data.split_first() {
+        Some((&which, data)) => (rules(which), data),
+        None => return,
+    };
+    // Bincode does a fine job of turning arbitrary bytes into arbitrary Asks.
+    let asks: Vec<AskMessage> = match bincode::deserialize(data) {
+        Ok(asks) => asks,
+        Err(_) => return,
+    };
+
+    let mut hs = Hackstead::new_user(None);
+    let mut cooldowns =
+        Cooldowns::restore(config.cooldowns.clone(), Default::default(), Instant::now());
+    for ask in asks {
+        let settled = settle(&hs, &config, &mut cooldowns, ask);
+        if settled.note.map_or(false, |n| n.err().is_some()) {
+            assert_eq!(settled.hackstead, hs, "failed ask changed hackstead");
+            assert!(settled.timers.is_empty(), "failed ask left timers behind");
+        }
+        hs = settled.hackstead;
+    }
+});
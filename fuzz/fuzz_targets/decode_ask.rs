@@ -0,0 +1,12 @@
+//! Feeds arbitrary bytes to the code that reads AskMessages and their wrappers out of the
+//! wormhole.
+#![no_main]
+use backend::fuzzing::{decode_ask, decode_inbound, Orifice, Version};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for &orifice in &Orifice::ALL {
+        drop(decode_ask(orifice, data));
+        drop(decode_inbound(orifice, Version::V3, data));
+    }
+});
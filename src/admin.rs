@@ -0,0 +1,459 @@
+//! Routes for operators to inspect and manage the server while it's running.
+//!
+//! These are only served if an `admin_token` is configured, and each request must bear that
+//! token in an `Authorization: Bearer <token>` header. Anything that changes a user's hackstead
+//! is written down in their audit log.
+use crate::{
+    audit::{self, Cause},
+    hackstead::{fs_get_stead, fs_put_stead},
+    wormhole::{
+        server::{self, BroadcastNote, GetSession, ListSessions},
+        session::{self, ChangeStead, SessSendSubmit},
+    },
+    Config, ServiceError,
+};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use hcor::{
+    config::ArchetypeHandle, item, plant, Hackstead, IdentifiesSteader, Item, Note, SteaderId,
+    UserId,
+};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+type Srv = web::Data<actix::Addr<server::Server>>;
+
+/// Makes sure a request bears the configured admin token.
+fn authorize(req: &HttpRequest, config: &Config) -> Result<(), ServiceError> {
+    let expected = match &config.admin_token {
+        Some(token) => token.0.as_bytes(),
+        None => return Err(ServiceError::NoData),
+    };
+
+    let supplied = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or(ServiceError::Unauthorized)?
+        .as_bytes();
+
+    // compare every byte, so that how long this takes doesn't give away how close you got
+    if supplied.len() == expected.len()
+        && supplied
+            .iter()
+            .zip(expected)
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+    {
+        Ok(())
+    } else {
+        warn!("refused admin request to {} with bad token", req.path());
+        Err(ServiceError::Unauthorized)
+    }
+}
+
+#[get("/sessions")]
+/// Lists the `SteaderId` of everyone with a session open.
+pub async fn sessions(
+    req: HttpRequest,
+    srv: Srv,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ServiceError> {
+    authorize(&req, &config)?;
+
+    Ok(HttpResponse::Ok().json(srv.send(ListSessions).await?))
+}
+
+#[derive(Serialize)]
+struct SteadReport {
+    /// Whether this hackstead came from a live session, rather than from disk.
+    live: bool,
+    hackstead: Hackstead,
+}
+
+#[post("/stead")]
+/// Returns a user's hackstead, straight from their session if they have one.
+pub async fn stead(
+    req: HttpRequest,
+    user: web::Json<UserId>,
+    srv: Srv,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ServiceError> {
+    authorize(&req, &config)?;
+
+    let hackstead = fs_get_stead(&config.data_dir, &*user)?;
+    Ok(
+        HttpResponse::Ok().json(match srv.send(GetSession::new(&hackstead)).await? {
+            Some(ses) => SteadReport {
+                live: true,
+                hackstead: ses.send(session::GetStead).await?,
+            },
+            None => SteadReport {
+                live: false,
+                hackstead,
+            },
+        }),
+    )
+}
+
+async fn live_session(
+    srv: &Srv,
+    steader_id: SteaderId,
+) -> Result<actix::Addr<session::Session>, ServiceError> {
+    srv.send(GetSession(steader_id)).await?.ok_or_else(|| {
+        ServiceError::BadRequest(format!("{} has no active wormhole connection!", steader_id))
+    })
+}
+
+#[post("/persist")]
+/// Saves a user's hackstead to disk right away.
+pub async fn persist(
+    req: HttpRequest,
+    steader_id: web::Json<SteaderId>,
+    srv: Srv,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ServiceError> {
+    authorize(&req, &config)?;
+
+    live_session(&srv, *steader_id)
+        .await?
+        .send(session::Persist)
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize)]
+pub struct KickRequest {
+    steader_id: SteaderId,
+    reason: String,
+}
+
+#[post("/kick")]
+/// Hangs up on a user; their hackstead is saved as their session ends.
+pub async fn kick(
+    req: HttpRequest,
+    kick: web::Json<KickRequest>,
+    srv: Srv,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ServiceError> {
+    authorize(&req, &config)?;
+
+    let KickRequest { steader_id, reason } = kick.into_inner();
+    live_session(&srv, steader_id)
+        .await?
+        .send(session::Kick(reason.clone()))
+        .await?;
+    audit::record(
+        &config,
+        steader_id,
+        audit::Event::Admin {
+            action: "kick".to_string(),
+            detail: serde_json::json!({ "reason": reason }),
+        },
+    );
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[post("/broadcast")]
+/// Sends a Note to everyone with a session open.
+pub async fn broadcast(
+    req: HttpRequest,
+    note: web::Json<Note>,
+    srv: Srv,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ServiceError> {
+    authorize(&req, &config)?;
+
+    srv.send(BroadcastNote(note.into_inner())).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ItemGrant {
+    archetype_handle: ArchetypeHandle,
+    amount: usize,
+}
+
+#[derive(Deserialize)]
+pub struct GrantRequest {
+    steader_id: SteaderId,
+    #[serde(default)]
+    items: Vec<ItemGrant>,
+    #[serde(default)]
+    xp: usize,
+    /// Why these were granted, for the audit log.
+    reason: String,
+}
+
+#[post("/grant")]
+/// Gives a user items and/or xp, returning the items they were given.
+pub async fn grant(
+    req: HttpRequest,
+    grant: web::Json<GrantRequest>,
+    srv: Srv,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ServiceError> {
+    authorize(&req, &config)?;
+
+    let GrantRequest {
+        steader_id,
+        items: item_grants,
+        xp,
+        reason,
+    } = grant.into_inner();
+
+    let items = item_grants
+        .iter()
+        .flat_map(|g| {
+            (0..g.amount).map(move |_| {
+                Item::from_archetype_handle(
+                    g.archetype_handle,
+                    steader_id,
+                    item::Acquisition::spawned(),
+                )
+            })
+        })
+        .collect::<hcor::ConfigResult<Vec<Item>>>()?;
+
+    fn give(hs: &mut Hackstead, items: &[Item], xp: usize) {
+        hs.inventory.extend(items.iter().cloned());
+        hs.profile.xp += xp;
+    }
+
+    match srv.send(GetSession(steader_id)).await? {
+        Some(ses) => {
+            let given = items.clone();
+            ses.send(ChangeStead(move |ss| {
+                ss.cause = Cause::Admin {
+                    action: "grant".to_string(),
+                };
+                give(ss, &given, xp);
+                SessSendSubmit::Submit
+            }))
+            .await?
+            .map_err(|()| ServiceError::InternalServerError)?;
+        }
+        // recorded just like a session would have, so that the grant gets a version of its own
+        None => {
+            use hcor::serde_diff::Diff;
+
+            let mut hs = fs_get_stead(&config.data_dir, UserId::Uuid(steader_id))?;
+            let old = hs.clone();
+            give(&mut hs, &items, xp);
+            let diff = serde_json::to_value(&Diff::serializable(&old, &hs))
+                .map_err(|_| ServiceError::InternalServerError)?;
+            hs.local_version += 1;
+            fs_put_stead(&config.data_dir, &hs)?;
+            audit::record(
+                &config,
+                steader_id,
+                audit::Event::Edit {
+                    cause: Cause::Admin {
+                        action: "grant".to_string(),
+                    },
+                    diff,
+                    local_version: hs.local_version,
+                },
+            );
+        }
+    }
+
+    audit::record(
+        &config,
+        steader_id,
+        audit::Event::Admin {
+            action: "grant".to_string(),
+            detail: serde_json::json!({ "items": item_grants, "xp": xp, "reason": reason }),
+        },
+    );
+    Ok(HttpResponse::Ok().json(items))
+}
+
+#[derive(Deserialize)]
+pub struct ReconstructRequest {
+    steader_id: SteaderId,
+    point: audit::Point,
+}
+
+#[post("/reconstruct")]
+/// Rebuilds a user's hackstead as it was at some `local_version` or time, from their audit log.
+pub async fn reconstruct(
+    req: HttpRequest,
+    rr: web::Json<ReconstructRequest>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ServiceError> {
+    authorize(&req, &config)?;
+
+    let hs = audit::reconstruct(&config.data_dir, rr.steader_id, rr.point)
+        .map_err(|e| ServiceError::bad_request(&e))?;
+    Ok(HttpResponse::Ok().json(hs))
+}
+
+/// Makes sure a hackstead's timers only refer to plants and effects it actually has,
+/// and that every plant that yields has a timer to yield by, as quickly as its level allows.
+fn reconcile_timers(hs: &mut Hackstead, config: &Config) {
+    use plant::TimerKind;
+
+    let timers = std::mem::take(&mut hs.timers);
+    hs.timers = timers
+        .into_iter()
+        .filter(|t| match hs.plant(t.tile_id) {
+            Err(_) => false,
+            Ok(plant) => match t.kind {
+                TimerKind::Yield => true,
+                TimerKind::Craft { .. } => plant.craft.is_some(),
+                TimerKind::Rub { effect_id } => {
+                    plant.effects.iter().any(|e| e.effect_id == effect_id)
+                }
+            },
+        })
+        .collect();
+
+    let missing_yields: Vec<plant::Timer> = hs
+        .land
+        .iter()
+        .filter_map(|tile| {
+            let plant = tile.plant.as_ref()?;
+            Some((tile.tile_id, session::level::yield_duration(config, plant)?))
+        })
+        .filter(|(tile_id, _)| {
+            !hs.timers
+                .iter()
+                .any(|t| t.tile_id == *tile_id && matches!(t.kind, TimerKind::Yield))
+        })
+        .map(|(tile_id, until_finish)| plant::Timer {
+            until_finish,
+            tile_id,
+            lifecycle: plant::timer::Lifecycle::Perennial {
+                duration: until_finish,
+            },
+            kind: TimerKind::Yield,
+        })
+        .collect();
+    hs.timers.extend(missing_yields);
+}
+
+#[derive(Deserialize)]
+pub struct RollbackRequest {
+    steader_id: SteaderId,
+    point: audit::Point,
+    /// Why this rollback is necessary, for the audit log.
+    reason: String,
+}
+
+#[post("/rollback")]
+/// Restores a user's hackstead to how it was at some `local_version` or time, returning it.
+///
+/// If they're online, their session carries on with the restored hackstead, and their client
+/// is sent an Edit to bring it up to speed. Either way, the restored hackstead gets a fresh
+/// `local_version`, so that it can't be confused with the one it replaced.
+pub async fn rollback(
+    req: HttpRequest,
+    rr: web::Json<RollbackRequest>,
+    srv: Srv,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ServiceError> {
+    authorize(&req, &config)?;
+
+    let RollbackRequest {
+        steader_id,
+        point,
+        reason,
+    } = rr.into_inner();
+
+    let mut target = audit::reconstruct(&config.data_dir, steader_id, point)
+        .map_err(|e| ServiceError::bad_request(&e))?;
+    reconcile_timers(&mut target, &config);
+    let cause = Cause::Admin {
+        action: "rollback".to_string(),
+    };
+
+    let restored = match srv.send(GetSession(steader_id)).await? {
+        Some(ses) => ses.send(session::Restore(target, cause)).await?,
+        None => {
+            let current = fs_get_stead(&config.data_dir, UserId::Uuid(steader_id))?;
+            target.local_version = current.local_version + 1;
+            fs_put_stead(&config.data_dir, &target)?;
+            audit::record(
+                &config,
+                target.steader_id(),
+                audit::Event::Snapshot {
+                    hackstead: Box::new(target.clone()),
+                },
+            );
+            target
+        }
+    };
+
+    audit::record(
+        &config,
+        steader_id,
+        audit::Event::Admin {
+            action: "rollback".to_string(),
+            detail: serde_json::json!({
+                "point": point,
+                "reason": reason,
+                "local_version": restored.local_version,
+            }),
+        },
+    );
+    Ok(HttpResponse::Ok().json(restored))
+}
+
+#[cfg(test)]
+mod test {
+    #[actix_rt::test]
+    async fn offline_grants_get_a_version_of_their_own() {
+        use crate::{config::Secret, harness::Harness, World};
+        use hcor::{Hackstead, IdentifiesSteader};
+        use serde_json::json;
+
+        const TOKEN: &str = "just for this test, really";
+
+        let h = Harness::start_with(World::real(), |c| {
+            c.admin_token = Some(Secret(TOKEN.to_string()));
+        })
+        .await;
+        let bobstead = h.summon().await;
+        let admin = |path: &str, body: serde_json::Value| {
+            awc::Client::new()
+                .post(format!("{}/admin/{}", h.url, path))
+                .header("Authorization", format!("Bearer {}", TOKEN))
+                .send_json(&body)
+        };
+
+        // bob has no session, so the grant is made to the hackstead on disk
+        let granted = admin(
+            "grant",
+            json!({ "steader_id": bobstead.steader_id(), "xp": 10, "reason": "testing" }),
+        )
+        .await
+        .unwrap();
+        assert!(granted.status().is_success());
+
+        let stored = h.spy(&bobstead).await.unwrap();
+        assert_eq!(stored.profile.xp, bobstead.profile.xp + 10);
+        assert_eq!(stored.local_version, bobstead.local_version + 1);
+
+        // both the version before the grant and the one it made can be rebuilt
+        for (hs, xp) in &[
+            (&bobstead, bobstead.profile.xp),
+            (&stored, stored.profile.xp),
+        ] {
+            let rebuilt: Hackstead = admin(
+                "reconstruct",
+                json!({
+                    "steader_id": bobstead.steader_id(),
+                    "point": { "Version": hs.local_version },
+                }),
+            )
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+            assert_eq!(rebuilt.profile.xp, *xp);
+        }
+        h.stop().await;
+    }
+}
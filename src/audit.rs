@@ -0,0 +1,374 @@
+//! An append-only record of everything that happens to each hackstead, so that when someone
+//! reports that their items went missing, there's something to check.
+//!
+//! Each steader gets their own log in the `audit` folder of the data directory, with one JSON
+//! `Record` per line. Once a log grows past `audit_rotate_bytes`, it's set aside under a name
+//! including the time it was rotated and a fresh one is started; nothing is ever deleted.
+//! The `history` binary prints these logs back out.
+//!
+//! Along with a `Snapshot` of each hackstead whenever a session begins, the diffs recorded with
+//! each `Edit` and `Timer` make these logs an event stream, from which a hackstead can be
+//! `reconstruct`ed as it was at any `local_version` or point in time since.
+//! Note that timers are only as fresh as the last `Snapshot`, as the diffs don't cover them.
+use crate::Config;
+use chrono::{DateTime, Utc};
+use hcor::{plant::Timer, wormhole::RudeNote, Ask, Hackstead, ItemId, SteaderId};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Json(PathBuf, usize, serde_json::Error),
+    /// A diff recorded at some `local_version` couldn't be applied.
+    BadDiff(usize, serde_json::Error),
+    /// There's no `Snapshot` before the requested point to build a hackstead up from.
+    NoSnapshot(SteaderId),
+    /// The requested `local_version` was never reached; the latest one we know of is included.
+    Unreached(usize, usize),
+}
+use Error::*;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "couldn't use audit log: ")?;
+        match self {
+            Io(path, e) => write!(f, "couldn't access {}: {}", path.display(), e),
+            Json(path, line, e) => {
+                write!(f, "{}:{} isn't a valid record: {}", path.display(), line, e)
+            }
+            BadDiff(version, e) => write!(
+                f,
+                "couldn't apply the diff recorded at version {}: {}",
+                version, e
+            ),
+            NoSnapshot(steader_id) => write!(
+                f,
+                "no snapshot of {}'s hackstead was taken early enough to rebuild it from",
+                steader_id
+            ),
+            Unreached(wanted, latest) => write!(
+                f,
+                "version {} was never reached, the latest version recorded is {}",
+                wanted, latest
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Why a hackstead was changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Cause {
+    /// The steader asked for it through the wormhole.
+    Ask { ask_id: usize, ask: Ask },
+    /// The steader asked for several things at once, with a `BatchMessage`.
+    Batch { ask_id: usize, asks: Vec<Ask> },
+    /// Items were thrown from one steader to another.
+    Throw {
+        sender_id: SteaderId,
+        receiver_id: SteaderId,
+    },
+    /// An admin did it through the `/admin` routes.
+    Admin { action: String },
+    /// Nobody told us.
+    Unspecified,
+}
+
+impl Default for Cause {
+    fn default() -> Self {
+        Cause::Unspecified
+    }
+}
+
+/// Something that happened to a hackstead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event {
+    /// Changes were made to a hackstead, bringing it up to `local_version`.
+    /// `diff` is the `serde_diff` sent down the wormhole to describe those changes.
+    Edit {
+        cause: Cause,
+        diff: serde_json::Value,
+        local_version: usize,
+    },
+    /// The entire hackstead, as it was when a session began or it was changed without one.
+    Snapshot { hackstead: Box<Hackstead> },
+    /// Changes to a hackstead were refused, so nothing happened.
+    Refused { cause: Cause, error: String },
+    /// A timer went off. If it was successful, `diff` describes what it did to the hackstead.
+    Timer {
+        timer: Timer,
+        outcome: Result<RudeNote, String>,
+        local_version: usize,
+        diff: Option<serde_json::Value>,
+    },
+    /// Someone tried to throw items from one steader to another.
+    Throw {
+        sender_id: SteaderId,
+        receiver_id: SteaderId,
+        item_ids: Vec<ItemId>,
+        outcome: Result<(), String>,
+    },
+    /// An admin did something that isn't captured by an `Edit`, like kicking someone.
+    Admin {
+        action: String,
+        detail: serde_json::Value,
+    },
+}
+
+impl Event {
+    /// A short name for this kind of Event, i.e. "Edit", for filtering.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Edit { .. } => "Edit",
+            Event::Snapshot { .. } => "Snapshot",
+            Event::Refused { .. } => "Refused",
+            Event::Timer { .. } => "Timer",
+            Event::Throw { .. } => "Throw",
+            Event::Admin { .. } => "Admin",
+        }
+    }
+}
+
+/// One line of an audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub at: DateTime<Utc>,
+    pub steader_id: SteaderId,
+    pub event: Event,
+}
+
+fn audit_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("audit")
+}
+
+/// Where the events currently happening to this steader are written.
+fn log_path(data_dir: &Path, steader_id: SteaderId) -> PathBuf {
+    audit_dir(data_dir).join(format!("{}.log", steader_id))
+}
+
+/// Appends an Event to a steader's audit log, rotating it first if it's gotten too long.
+///
+/// This is called in the middle of handling asks, which shouldn't fail because of an audit log,
+/// so any errors are logged rather than returned.
+pub fn record(config: &Config, steader_id: SteaderId, event: Event) {
+    let path = log_path(&config.data_dir, steader_id);
+    let record = Record {
+        at: Utc::now(),
+        steader_id,
+        event,
+    };
+
+    if let Err(e) = append(&path, config.audit_rotate_bytes, &record) {
+        error!(
+            "couldn't write {} event for {} to {}: {}",
+            record.event.kind(),
+            steader_id,
+            path.display(),
+            e
+        );
+    }
+}
+
+fn append(path: &Path, rotate_bytes: usize, record: &Record) -> io::Result<()> {
+    let line = serde_json::to_string(record)?;
+
+    let len = match fs::metadata(path) {
+        Ok(m) => m.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e),
+    };
+    if len > 0 && len + line.len() as u64 >= rotate_bytes as u64 {
+        // padded so that older logs sort before newer ones, and all of them before the current
+        let rotated = path.with_extension(format!("{:020}.log", record.at.timestamp_millis()));
+        fs::rename(path, &rotated)?;
+        info!("rotated audit log to {}", rotated.display());
+    }
+
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(f, "{}", line)
+}
+
+/// Every Record in a steader's audit logs, oldest first.
+pub fn history(data_dir: &Path, steader_id: SteaderId) -> Result<Vec<Record>, Error> {
+    let dir = audit_dir(data_dir);
+    let prefix = format!("{}.", steader_id);
+
+    let mut paths = fs::read_dir(&dir)
+        .map_err(|e| Io(dir.clone(), e))?
+        .map(|entry| entry.map(|e| e.path()).map_err(|e| Io(dir.clone(), e)))
+        .collect::<Result<Vec<PathBuf>, Error>>()?;
+    paths.retain(|p| {
+        p.file_name()
+            .and_then(|n| n.to_str())
+            .map_or(false, |n| n.starts_with(&prefix) && n.ends_with(".log"))
+    });
+    paths.sort();
+
+    let mut records = vec![];
+    for path in paths {
+        let f = fs::File::open(&path).map_err(|e| Io(path.clone(), e))?;
+        for (i, line) in io::BufReader::new(f).lines().enumerate() {
+            let line = line.map_err(|e| Io(path.clone(), e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            records.push(serde_json::from_str(&line).map_err(|e| Json(path.clone(), i + 1, e))?);
+        }
+    }
+
+    Ok(records)
+}
+
+/// A point in a hackstead's history.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Point {
+    /// Just after the `Edit` bringing a hackstead up to this `local_version`.
+    Version(usize),
+    /// Whatever the hackstead looked like at this moment.
+    Time(DateTime<Utc>),
+}
+
+/// Rebuilds a steader's hackstead as it was at some point in the past,
+/// from the most recent `Snapshot` before that point and the diffs recorded after it.
+pub fn reconstruct(
+    data_dir: &Path,
+    steader_id: SteaderId,
+    point: Point,
+) -> Result<Hackstead, Error> {
+    use hcor::serde_diff::Apply;
+
+    let passed = |r: &Record| match (point, &r.event) {
+        (Point::Time(t), _) => r.at > t,
+        (Point::Version(v), Event::Edit { local_version, .. }) => *local_version > v,
+        (Point::Version(v), Event::Snapshot { hackstead }) => hackstead.local_version > v,
+        // timers don't bump the version, so any going off at v did so after v was reached
+        (Point::Version(v), Event::Timer { local_version, .. }) => *local_version >= v,
+        (Point::Version(_), _) => false,
+    };
+
+    let mut stead: Option<Hackstead> = None;
+    for r in history(data_dir, steader_id)? {
+        if passed(&r) {
+            break;
+        }
+
+        match r.event {
+            Event::Snapshot { hackstead } => stead = Some(*hackstead),
+            Event::Edit {
+                diff,
+                local_version,
+                ..
+            } => {
+                if let Some(hs) = &mut stead {
+                    Apply::apply(diff, hs).map_err(|e| BadDiff(local_version, e))?;
+                    hs.local_version = local_version;
+                }
+            }
+            Event::Timer {
+                diff: Some(diff),
+                local_version,
+                ..
+            } => {
+                if let Some(hs) = &mut stead {
+                    Apply::apply(diff, hs).map_err(|e| BadDiff(local_version, e))?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match (stead, point) {
+        (None, _) => Err(NoSnapshot(steader_id)),
+        (Some(hs), Point::Version(v)) if hs.local_version != v => {
+            Err(Unreached(v, hs.local_version))
+        }
+        (Some(hs), _) => Ok(hs),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{reconstruct, record, Cause, Event, Point};
+    use crate::Config;
+    use hcor::{plant, serde_diff::Diff, Hackstead, IdentifiesSteader};
+
+    #[test]
+    fn reconstruct_from_diffs() {
+        let config = Config {
+            data_dir: std::env::temp_dir().join(format!("hackagotchi-{}", uuid::Uuid::new_v4())),
+            ..Config::default()
+        };
+        config.create_data_dirs().unwrap();
+
+        let mut hs = Hackstead::new_user(None);
+        let steader_id = hs.steader_id();
+        record(
+            &config,
+            steader_id,
+            Event::Snapshot {
+                hackstead: Box::new(hs.clone()),
+            },
+        );
+
+        let mut versions = vec![hs.clone()];
+        for xp in &[10, 25] {
+            let old = hs.clone();
+            hs.profile.xp += xp;
+            let diff = serde_json::to_value(&Diff::serializable(&old, &hs)).unwrap();
+            hs.local_version += 1;
+            record(
+                &config,
+                steader_id,
+                Event::Edit {
+                    cause: Cause::Unspecified,
+                    diff,
+                    local_version: hs.local_version,
+                },
+            );
+            versions.push(hs.clone());
+
+            // a yield going off between edits, which is part of the next version but not this one
+            let old = hs.clone();
+            hs.profile.xp += 5;
+            record(
+                &config,
+                steader_id,
+                Event::Timer {
+                    timer: plant::Timer {
+                        until_finish: 0.0,
+                        tile_id: hs.land[0].tile_id,
+                        lifecycle: plant::timer::Lifecycle::Annual,
+                        kind: plant::TimerKind::Yield,
+                    },
+                    outcome: Err("just for the diff".to_string()),
+                    local_version: hs.local_version,
+                    diff: Some(serde_json::to_value(&Diff::serializable(&old, &hs)).unwrap()),
+                },
+            );
+        }
+
+        for expected in &versions {
+            let got = reconstruct(
+                &config.data_dir,
+                steader_id,
+                Point::Version(expected.local_version),
+            )
+            .unwrap();
+            assert_eq!(got.profile.xp, expected.profile.xp);
+            assert_eq!(got.local_version, expected.local_version);
+        }
+        assert!(reconstruct(&config.data_dir, steader_id, Point::Version(1000)).is_err());
+
+        std::fs::remove_dir_all(&config.data_dir).unwrap();
+    }
+}
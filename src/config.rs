@@ -0,0 +1,1470 @@
+//! Settings for the server binary.
+//!
+//! These are read from a TOML file (`backend.toml` in the working directory, unless the
+//! `--config` flag or the `HACKAGOTCHI_CONFIG` environment variable point somewhere else),
+//! then overridden by `HACKAGOTCHI_*` environment variables, then by command line flags.
+//! Every setting has a sensible default, so none of these sources are required.
+//!
+//! Note that this is entirely separate from [`hcor::CONFIG`](hcor::CONFIG), which describes the
+//! game itself rather than the server running it.
+use serde::Deserialize;
+use std::{
+    fmt,
+    net::ToSocketAddrs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Where we look for a config file if we aren't told where to find one.
+pub const DEFAULT_PATH: &str = "backend.toml";
+
+/// Printed when `--help` is supplied.
+pub const USAGE: &str = "\
+usage: server [flags]
+
+flags:
+    --config <path>              TOML file to read settings from [default: backend.toml]
+    --bind <addr>                address to listen on; may be repeated [default: 127.0.0.1:8000]
+    --data-dir <path>            where hacksteads are stored [default: .]
+    --workers <n>                number of HTTP worker threads [default: one per core]
+    --heartbeat-interval <secs>  how often clients are pinged
+    --client-timeout <secs>      how long clients may go without responding to pings
+    --autoclose                  end the process when the last user logs off
+    --no-autoclose               don't end the process when the last user logs off
+    --autoclose-after <secs>     how long to wait for new users before autoclosing
+    --max-sessions <n>           refuse wormhole connections beyond this many
+    --admin-token <token>        enables the /admin routes, for requests bearing this token
+    --audit-rotate-bytes <n>     start a new audit log once one grows this large [default: 16MiB]
+    --max-strikes <n>            malformed messages a client may send before it's hung up on [default: 10]
+    --headless-idle <secs>       how long sessions started for /beg last without asks [default: 60]
+    --ask-retention <secs>       how long what came of an ask_id is remembered, for resends [default: 300]
+    --rate-limits <rates>        how often each steader may make each kind of ask [default: *=off]
+    --conn-rate-limits <rates>   how often each ask may be made through one connection [default: *=off]
+    --cooldowns <cooldowns>      how many of some asks each steader may make in a while [default: none]
+    --plant-levels <levels>      how much xp each kind of plant needs for each level [default: none, plants don't level up]
+    --yield-xp <n>               xp a plant earns each time it yields [default: 10]
+    --craft-xp <n>               xp a plant earns each time it finishes crafting [default: 25]
+    --level-yield-speedup <f>    how much faster a plant yields with each level [default: 0.1]
+    --rub-modifiers <modifiers>  what the effects each item imparts when rubbed do to plants [default: none]
+    --modifier-stacking <how>    multiply, add or highest: how modifiers on one plant combine [default: multiply]
+    --effect-caps <caps>         how many effects each kind of plant may have at once [default: *=off]
+    --rub-stacking <policies>    what rubbing an item onto a plant that already has its effects does [default: *=stack]
+    --cleansers <items>          items used up to cleanse effects off of plants [default: none, cleansing is free]
+    -h, --help                   print this message
+
+Rates are written like *=20/60,Item.Throw=1/5: for each kind of ask, or * for those not named,
+how many may be made per second, then how many at once. A rate of \"off\" means no limit.
+
+Cooldowns are written like Item.Hatch=1/60,Plant.Rub@plant=3/86400: for TileSummon, Plant.Rub,
+Item.Hatch or Item.Throw, how many may be made, then in how many seconds. With @plant, each
+plant keeps count of the asks made on it separately.
+
+Plant levels are written like *=100/300/700,Bractus=50/150: for each kind of plant, or * for those
+not named, how much xp it takes to reach each level after the first. Kinds of plant that aren't
+covered never level up.
+
+Rub modifiers are written like Warp Powder#0=yield_speed*1.5&xp*2: for an effect, named by the
+item that imparts it and its place among that item's effects, what to multiply the yield_speed,
+xp or craft_speed of the plant it's on by.
+
+Effect caps are written like *=10,Bractus=3: for each kind of plant, or * for those not named,
+how many effects it may have at once, or \"off\" for no limit. Rub stacking is written like
+*=stack,Warp Powder=refresh: for each item, or * for those not named, whether rubbing it onto a
+plant that already has its effects adds another of each (stack), starts the ones it has over
+(refresh), or isn't allowed (reject). If any cleansers are named, like Warp Powder,Nest Egg,
+cleansing an effect off of a plant uses one of them up; otherwise, it takes no item at all.
+
+Each setting may also be supplied as an environment variable, i.e. HACKAGOTCHI_DATA_DIR,
+or as a key in the config file, i.e. data_dir = \"/var/lib/hackagotchi\".";
+
+/// Every setting that may be supplied in a config file, environment variable, or flag.
+const KEYS: &[&str] = &[
+    "bind",
+    "data_dir",
+    "workers",
+    "heartbeat_interval",
+    "client_timeout",
+    "autoclose",
+    "autoclose_after",
+    "max_sessions",
+    "admin_token",
+    "audit_rotate_bytes",
+    "max_strikes",
+    "headless_idle",
+    "ask_retention",
+    "rate_limits",
+    "conn_rate_limits",
+    "cooldowns",
+    "plant_levels",
+    "yield_xp",
+    "craft_xp",
+    "level_yield_speedup",
+    "rub_modifiers",
+    "modifier_stacking",
+    "effect_caps",
+    "rub_stacking",
+    "cleansers",
+];
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, std::io::Error),
+    Toml(PathBuf, toml::de::Error),
+    BadValue {
+        origin: String,
+        value: String,
+        expected: &'static str,
+    },
+    MissingValue(String),
+    UnknownFlag(String),
+    Invalid(String),
+    /// Not really an error; the user asked for `USAGE` and should get it instead of a server.
+    Help,
+}
+use Error::*;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "couldn't configure server: ")?;
+        match self {
+            Io(path, e) => write!(f, "couldn't access {}: {}", path.display(), e),
+            Toml(path, e) => write!(f, "{} is not a valid config file: {}", path.display(), e),
+            BadValue {
+                origin,
+                value,
+                expected,
+            } => write!(
+                f,
+                "{} was set to '{}', expected {}",
+                origin, value, expected
+            ),
+            MissingValue(flag) => write!(f, "the {} flag requires a value", flag),
+            UnknownFlag(flag) => write!(f, "unknown flag {}, try --help", flag),
+            Invalid(why) => write!(f, "{}", why),
+            Help => write!(f, "{}", USAGE),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Everything the server binary needs to know about how it should run.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Addresses the HTTP server listens on.
+    pub bind: Vec<String>,
+    /// Folder under which the `stead`, `slack` and `audit` folders are kept.
+    pub data_dir: PathBuf,
+    /// How many HTTP worker threads to run; if `None`, actix picks one per core.
+    pub workers: Option<usize>,
+    /// How often the server pings clients connected through the wormhole.
+    pub heartbeat_interval: Duration,
+    /// How long a client may go without responding before its session is dropped.
+    pub client_timeout: Duration,
+    /// If `Some`, the process ends this long after the last user logs off.
+    pub autoclose: Option<Duration>,
+    /// If `Some`, wormhole connections beyond this many are refused.
+    pub max_sessions: Option<usize>,
+    /// If `Some`, the `/admin` routes serve requests bearing this token; otherwise, they're off.
+    pub admin_token: Option<Secret>,
+    /// How many bytes an audit log may grow to before a new one is started.
+    pub audit_rotate_bytes: usize,
+    /// How many malformed messages a client may send before its connection is closed.
+    pub max_strikes: usize,
+    /// How long a session started without a wormhole, i.e. for `/beg`, lasts without any asks.
+    pub headless_idle: Duration,
+    /// How long what came of an Ask is remembered, so that it's answered again, rather than done
+    /// again, should a client send the same `ask_id` twice.
+    pub ask_retention: Duration,
+    /// How often each steader may make each kind of Ask, across all of their sessions.
+    pub rate_limits: RateLimits,
+    /// How often each kind of Ask may be made through a single wormhole or relay.
+    pub conn_rate_limits: RateLimits,
+    /// How many of some kinds of Ask each steader may make in a while, however slowly.
+    pub cooldowns: Cooldowns,
+    /// How much xp each kind of plant needs to reach each level.
+    pub plant_levels: PlantLevels,
+    /// How much xp a plant earns each time it yields.
+    pub yield_xp: usize,
+    /// How much xp a plant earns each time it finishes crafting something.
+    pub craft_xp: usize,
+    /// The fraction by which each level a plant reaches shortens the time it takes to yield.
+    pub level_yield_speedup: f64,
+    /// What the effects imparted by rubbing each item on a plant do to that plant.
+    pub rub_modifiers: RubModifiers,
+    /// How the modifiers of every effect on a plant combine.
+    pub modifier_stacking: Stacking,
+    /// How many effects each kind of plant may have at once.
+    pub effect_caps: EffectCaps,
+    /// What rubbing each item onto a plant that already has its effects does.
+    pub rub_stacking: RubStackings,
+    /// The names of the items that may be used up to cleanse an effect off of a plant. If there
+    /// are none, effects are cleansed without using up any item.
+    pub cleansers: Vec<String>,
+}
+
+/// A string that shouldn't end up in the logs.
+#[derive(Clone, PartialEq)]
+pub struct Secret(pub String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret(<redacted>)")
+    }
+}
+
+/// The names each kind of Ask goes by in `RateLimits`, as given by `session::ask_kind`.
+pub const ASK_KINDS: &[&str] = &[
+    "KnowledgeSnort",
+    "TileSummon",
+    "Plant.Summon",
+    "Plant.Slaughter",
+    "Plant.Craft",
+    "Plant.Rub",
+    "Plant.Cleanse",
+    "Item.Spawn",
+    "Item.Throw",
+    "Item.Hatch",
+];
+
+/// How often Asks of some kind may be made: `burst` of them at once, then `per_second`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+/// A `Rate` for each kind of Ask, if it's limited at all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimits {
+    /// By name, as found in `ASK_KINDS`, or `*` for every kind not named.
+    rates: Vec<(String, Option<Rate>)>,
+}
+
+impl RateLimits {
+    /// Asks may be made as often as anyone likes.
+    pub fn none() -> Self {
+        Self {
+            rates: vec![("*".to_string(), None)],
+        }
+    }
+
+    /// Every kind of Ask may be made this often.
+    pub fn everything(per_second: f64, burst: f64) -> Self {
+        Self {
+            rates: vec![("*".to_string(), Some(Rate { per_second, burst }))],
+        }
+    }
+
+    /// How often the kind of Ask with this name may be made, if it's limited at all.
+    pub fn rate(&self, kind: &str) -> Option<Rate> {
+        let find = |k: &str| self.rates.iter().find(|(name, _)| name == k);
+        find(kind).or_else(|| find("*")).and_then(|(_, rate)| *rate)
+    }
+
+    /// Reads rates written like `*=20/60` or `Item.Throw=off`; see `USAGE`.
+    fn parse(specs: &[String]) -> Result<Self, String> {
+        let rates = specs
+            .iter()
+            .map(|spec| {
+                let eq = spec
+                    .find('=')
+                    .ok_or_else(|| format!("'{}' should look like <ask>=<rate>", spec))?;
+                let (kind, rate) = (spec[..eq].trim(), spec[eq + 1..].trim());
+                if kind != "*" && !ASK_KINDS.contains(&kind) {
+                    return Err(format!(
+                        "there's no kind of ask called '{}', try one of {}",
+                        kind,
+                        ASK_KINDS.join(", ")
+                    ));
+                }
+                if rate == "off" {
+                    return Ok((kind.to_string(), None));
+                }
+
+                let slash = rate
+                    .find('/')
+                    .ok_or_else(|| format!("'{}' should look like <per second>/<at once>", rate))?;
+                let number = |s: &str| match s.trim().parse::<f64>() {
+                    Ok(n) if n.is_finite() && n > 0.0 => Ok(n),
+                    _ => Err(format!("'{}' in '{}' should be a positive number", s, spec)),
+                };
+                let per_second = number(&rate[..slash])?;
+                let burst = number(&rate[slash + 1..])?;
+                if burst < 1.0 {
+                    return Err(format!("'{}' would never allow a single ask", spec));
+                }
+                Ok((kind.to_string(), Some(Rate { per_second, burst })))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { rates })
+    }
+}
+
+/// The kinds of Ask, as named in `ASK_KINDS`, whose handlers check for a `Cooldown`.
+pub const COOLDOWN_KINDS: &[&str] = &["TileSummon", "Plant.Rub", "Item.Hatch", "Item.Throw"];
+
+/// `uses` Asks of some kind may be made within any stretch of time `per` long.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cooldown {
+    pub uses: usize,
+    pub per: Duration,
+    /// If set, each plant keeps count of the Asks made on it, rather than the steader.
+    pub per_plant: bool,
+}
+
+/// A `Cooldown` for each kind of Ask that has one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cooldowns {
+    /// By name, as found in `COOLDOWN_KINDS`.
+    rules: Vec<(String, Cooldown)>,
+}
+
+impl Cooldowns {
+    /// The cooldown on the kind of Ask with this name, if it has one.
+    pub fn rule(&self, kind: &str) -> Option<Cooldown> {
+        self.rules
+            .iter()
+            .find(|(name, _)| name == kind)
+            .map(|(_, cooldown)| *cooldown)
+    }
+
+    /// Reads cooldowns written like `Item.Hatch=1/60` or `Plant.Rub@plant=3/86400`; see `USAGE`.
+    pub(crate) fn parse(specs: &[String]) -> Result<Self, String> {
+        let rules = specs
+            .iter()
+            .map(|spec| {
+                let eq = spec
+                    .find('=')
+                    .ok_or_else(|| format!("'{}' should look like <ask>=<cooldown>", spec))?;
+                let (kind, cooldown) = (spec[..eq].trim(), spec[eq + 1..].trim());
+                let (kind, per_plant) = match kind.strip_suffix("@plant") {
+                    Some(kind) => (kind.trim(), true),
+                    None => (kind, false),
+                };
+                if !COOLDOWN_KINDS.contains(&kind) {
+                    return Err(format!(
+                        "asks called '{}' can't have cooldowns, try one of {}",
+                        kind,
+                        COOLDOWN_KINDS.join(", ")
+                    ));
+                }
+                if per_plant && kind != "Plant.Rub" {
+                    return Err(format!("{} asks aren't made on plants", kind));
+                }
+
+                let slash = cooldown
+                    .find('/')
+                    .ok_or_else(|| format!("'{}' should look like <uses>/<seconds>", cooldown))?;
+                let uses = match cooldown[..slash].trim().parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("'{}' should allow at least one ask", spec)),
+                };
+                let per = match cooldown[slash + 1..].trim().parse::<f64>() {
+                    Ok(s) if s.is_finite() && s > 0.0 => Duration::from_secs_f64(s),
+                    _ => {
+                        return Err(format!(
+                            "'{}' should end in a positive number of seconds",
+                            spec
+                        ))
+                    }
+                };
+                Ok((
+                    kind.to_string(),
+                    Cooldown {
+                        uses,
+                        per,
+                        per_plant,
+                    },
+                ))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { rules })
+    }
+}
+
+/// How much xp plants need to level up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlantLevels {
+    /// By the name of the plant archetype, or `*` for every kind not named, the xp at which each
+    /// level after the first is reached, from lowest to highest.
+    thresholds: Vec<(String, Vec<usize>)>,
+}
+
+impl PlantLevels {
+    /// No plant ever levels up.
+    pub fn none() -> Self {
+        Self { thresholds: vec![] }
+    }
+
+    /// Every kind of plant levels up at the same xp.
+    pub fn everything(thresholds: Vec<usize>) -> Self {
+        Self {
+            thresholds: vec![("*".to_string(), thresholds)],
+        }
+    }
+
+    /// What level a plant of this kind is at with this much xp, starting from 0.
+    pub fn level(&self, plant: &str, xp: usize) -> usize {
+        let find = |k: &str| self.thresholds.iter().find(|(name, _)| name == k);
+        find(plant)
+            .or_else(|| find("*"))
+            .map_or(0, |(_, thresholds)| {
+                thresholds.iter().take_while(|t| **t <= xp).count()
+            })
+    }
+
+    /// Reads levels written like `*=100/300/700` or `Bractus=50/150`; see `USAGE`.
+    fn parse(specs: &[String]) -> Result<Self, String> {
+        let thresholds = specs
+            .iter()
+            .map(|spec| {
+                let eq = spec
+                    .find('=')
+                    .ok_or_else(|| format!("'{}' should look like <plant>=<xp>/<xp>/...", spec))?;
+                let plant = spec[..eq].trim();
+                let thresholds = spec[eq + 1..]
+                    .split('/')
+                    .map(|xp| match xp.trim().parse::<usize>() {
+                        Ok(n) if n > 0 => Ok(n),
+                        _ => Err(format!(
+                            "'{}' in '{}' should be a positive whole number",
+                            xp, spec
+                        )),
+                    })
+                    .collect::<Result<Vec<usize>, String>>()?;
+                if thresholds.windows(2).any(|w| w[0] >= w[1]) {
+                    return Err(format!("the xp for each level in '{}' should go up", spec));
+                }
+                Ok((plant.to_string(), thresholds))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { thresholds })
+    }
+}
+
+/// Something about a plant that the effects rubbed onto it may change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Modifier {
+    /// How quickly its yield timer runs.
+    YieldSpeed,
+    /// How much xp it earns from yields and crafts.
+    Xp,
+    /// How quickly its craft timer runs.
+    CraftSpeed,
+}
+
+impl Modifier {
+    pub const ALL: [Modifier; 3] = [Modifier::YieldSpeed, Modifier::Xp, Modifier::CraftSpeed];
+
+    /// What this modifier is called in `RubModifiers`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Modifier::YieldSpeed => "yield_speed",
+            Modifier::Xp => "xp",
+            Modifier::CraftSpeed => "craft_speed",
+        }
+    }
+}
+
+/// What each effect archetype does to the plant it's on.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RubModifiers {
+    /// By the name of the item archetype imparting the effect and the effect archetype's handle
+    /// among that item's effects, what each `Modifier` is multiplied by.
+    by_effect: Vec<((String, usize), Vec<(Modifier, f64)>)>,
+}
+
+impl RubModifiers {
+    /// What this effect archetype, the `effect`th of those rubbing `item` onto a plant imparts,
+    /// multiplies each `Modifier` by, leaving out those it doesn't change.
+    pub fn of(&self, item: &str, effect: usize) -> &[(Modifier, f64)] {
+        self.by_effect
+            .iter()
+            .find(|((name, e), _)| name == item && *e == effect)
+            .map_or(&[], |(_, modifiers)| modifiers)
+    }
+
+    /// Reads modifiers written like `Warp Powder#0=yield_speed*1.5&xp*2`; see `USAGE`.
+    pub(crate) fn parse(specs: &[String]) -> Result<Self, String> {
+        let by_effect = specs
+            .iter()
+            .map(|spec| {
+                let malformed = || {
+                    format!(
+                        "'{}' should look like <item>#<effect>=<modifier>*<factor>&...",
+                        spec
+                    )
+                };
+                let eq = spec.find('=').ok_or_else(malformed)?;
+                let hash = spec[..eq].rfind('#').ok_or_else(malformed)?;
+                let item = spec[..hash].trim();
+                let effect = spec[hash + 1..eq]
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| malformed())?;
+                let modifiers = spec[eq + 1..]
+                    .split('&')
+                    .map(|m| {
+                        let star = m.find('*').ok_or_else(|| {
+                            format!("'{}' in '{}' should look like <modifier>*<factor>", m, spec)
+                        })?;
+                        let name = m[..star].trim();
+                        let modifier = Modifier::ALL
+                            .iter()
+                            .copied()
+                            .find(|m| m.name() == name)
+                            .ok_or_else(|| {
+                                format!(
+                                    "there's no modifier called '{}', try one of {}",
+                                    name,
+                                    Modifier::ALL
+                                        .iter()
+                                        .map(|m| m.name())
+                                        .collect::<Vec<_>>()
+                                        .join(", ")
+                                )
+                            })?;
+                        match m[star + 1..].trim().parse::<f64>() {
+                            Ok(f) if f.is_finite() && f >= 0.0 => Ok((modifier, f)),
+                            _ => Err(format!(
+                                "'{}' in '{}' should end in a number that isn't negative",
+                                m, spec
+                            )),
+                        }
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                Ok(((item.to_string(), effect), modifiers))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { by_effect })
+    }
+}
+
+/// How the factors every effect on a plant multiplies a `Modifier` by are combined.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stacking {
+    /// Each effect multiplies what the others left it at, so two 1.5x effects make 2.25x.
+    Multiply,
+    /// The bonuses (or penalties) of each effect are added up, so two 1.5x effects make 2x.
+    Add,
+    /// Only the largest factor counts, so two 1.5x effects make 1.5x.
+    Highest,
+}
+
+impl Stacking {
+    /// Combines the factors of every effect on a plant into one; with no effects, that's 1.
+    pub fn combine(self, factors: impl Iterator<Item = f64>) -> f64 {
+        match self {
+            Stacking::Multiply => factors.product(),
+            Stacking::Add => (1.0 + factors.map(|f| f - 1.0).sum::<f64>()).max(0.0),
+            Stacking::Highest => factors
+                .fold(None, |max: Option<f64>, f| {
+                    Some(max.map_or(f, |max| max.max(f)))
+                })
+                .unwrap_or(1.0),
+        }
+    }
+}
+
+/// How many effects each kind of plant may have at once, if there's a limit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EffectCaps {
+    /// By the name of the plant archetype, or `*` for every kind not named.
+    caps: Vec<(String, Option<usize>)>,
+}
+
+impl EffectCaps {
+    /// Plants may have as many effects as they like.
+    pub fn none() -> Self {
+        Self {
+            caps: vec![("*".to_string(), None)],
+        }
+    }
+
+    /// Every kind of plant may have this many effects at once.
+    pub fn everything(cap: usize) -> Self {
+        Self {
+            caps: vec![("*".to_string(), Some(cap))],
+        }
+    }
+
+    /// How many effects a plant of this kind may have at once, if there's a limit.
+    pub fn cap(&self, plant: &str) -> Option<usize> {
+        let find = |k: &str| self.caps.iter().find(|(name, _)| name == k);
+        find(plant).or_else(|| find("*")).and_then(|(_, cap)| *cap)
+    }
+
+    /// Reads caps written like `*=10` or `Bractus=off`; see `USAGE`.
+    pub(crate) fn parse(specs: &[String]) -> Result<Self, String> {
+        let caps = specs
+            .iter()
+            .map(|spec| {
+                let eq = spec
+                    .find('=')
+                    .ok_or_else(|| format!("'{}' should look like <plant>=<cap>", spec))?;
+                let (plant, cap) = (spec[..eq].trim(), spec[eq + 1..].trim());
+                match cap {
+                    "off" => Ok((plant.to_string(), None)),
+                    cap => match cap.parse::<usize>() {
+                        Ok(n) => Ok((plant.to_string(), Some(n))),
+                        Err(_) => Err(format!(
+                            "'{}' in '{}' should be a whole number or \"off\"",
+                            cap, spec
+                        )),
+                    },
+                }
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { caps })
+    }
+}
+
+/// What rubbing an item onto a plant that already has the effects it imparts does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RubStacking {
+    /// Another of each effect is added alongside those the plant already has.
+    Stack,
+    /// The effects the plant already has start over, lasting as long as new ones would.
+    Refresh,
+    /// The item can't be rubbed onto the plant until they've worn off.
+    Reject,
+}
+
+/// A `RubStacking` for each item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RubStackings {
+    /// By the name of the item archetype, or `*` for every item not named.
+    policies: Vec<(String, RubStacking)>,
+}
+
+impl RubStackings {
+    /// Every item is rubbed onto plants this way.
+    pub fn everything(policy: RubStacking) -> Self {
+        Self {
+            policies: vec![("*".to_string(), policy)],
+        }
+    }
+
+    /// What rubbing this item onto a plant that already has its effects does.
+    pub fn policy(&self, item: &str) -> RubStacking {
+        let find = |k: &str| self.policies.iter().find(|(name, _)| name == k);
+        find(item)
+            .or_else(|| find("*"))
+            .map_or(RubStacking::Stack, |(_, policy)| *policy)
+    }
+
+    /// Reads policies written like `*=stack` or `Warp Powder=refresh`; see `USAGE`.
+    pub(crate) fn parse(specs: &[String]) -> Result<Self, String> {
+        let policies = specs
+            .iter()
+            .map(|spec| {
+                let eq = spec
+                    .find('=')
+                    .ok_or_else(|| format!("'{}' should look like <item>=<policy>", spec))?;
+                let item = spec[..eq].trim().to_string();
+                match spec[eq + 1..].trim() {
+                    "stack" => Ok((item, RubStacking::Stack)),
+                    "refresh" => Ok((item, RubStacking::Refresh)),
+                    "reject" => Ok((item, RubStacking::Reject)),
+                    other => Err(format!(
+                        "'{}' in '{}' should be stack, refresh or reject",
+                        other, spec
+                    )),
+                }
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { policies })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: vec!["127.0.0.1:8000".to_string()],
+            data_dir: PathBuf::from("."),
+            workers: None,
+            heartbeat_interval: hcor::wormhole::HEARTBEAT_INTERVAL,
+            client_timeout: hcor::wormhole::CLIENT_TIMEOUT,
+            autoclose: if cfg!(feature = "autoclose") {
+                Some(Duration::from_secs(30))
+            } else {
+                None
+            },
+            max_sessions: None,
+            admin_token: None,
+            audit_rotate_bytes: 16 * 1024 * 1024,
+            max_strikes: 10,
+            headless_idle: Duration::from_secs(60),
+            ask_retention: Duration::from_secs(300),
+            rate_limits: RateLimits::none(),
+            conn_rate_limits: RateLimits::none(),
+            cooldowns: Cooldowns::default(),
+            plant_levels: PlantLevels::none(),
+            yield_xp: 10,
+            craft_xp: 25,
+            level_yield_speedup: 0.1,
+            rub_modifiers: RubModifiers::default(),
+            modifier_stacking: Stacking::Multiply,
+            effect_caps: EffectCaps::none(),
+            rub_stacking: RubStackings::everything(RubStacking::Stack),
+            cleansers: vec![],
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file, environment and command line flags of this process.
+    pub fn load() -> Result<Self, Error> {
+        Self::from_sources(std::env::args().skip(1), |var| std::env::var(var).ok())
+    }
+
+    /// Like `load`, but with the command line flags and environment supplied by the caller.
+    pub fn from_sources(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, Error> {
+        Self::from_sources_or(args, env, Path::new(DEFAULT_PATH))
+    }
+
+    /// Like `from_sources`, but reading `default_path` when no config file is named, if it exists.
+    fn from_sources_or(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+        default_path: &Path,
+    ) -> Result<Self, Error> {
+        let flags = Flags::parse(args)?;
+
+        let mut partial = match flags
+            .config
+            .or_else(|| env("HACKAGOTCHI_CONFIG").map(PathBuf::from))
+        {
+            Some(path) => Partial::read(&path)?,
+            None if default_path.exists() => Partial::read(default_path)?,
+            None => Partial::default(),
+        };
+
+        for key in KEYS {
+            let var = format!("HACKAGOTCHI_{}", key.to_uppercase());
+            if let Some(value) = env(&var) {
+                partial.set(key, &value, || format!("environment variable {}", var))?;
+            }
+        }
+
+        for (key, value) in flags.settings {
+            partial.set(&key, &value, || format!("--{} flag", key.replace('_', "-")))?;
+        }
+        if !flags.bind.is_empty() {
+            partial.bind = Some(flags.bind);
+        }
+
+        partial.finish()
+    }
+
+    /// Makes sure the folders hacksteads and their audit logs are stored in exist.
+    pub fn create_data_dirs(&self) -> Result<(), Error> {
+        for dir in &["stead", "slack", "audit"] {
+            let path = self.data_dir.join(dir);
+            std::fs::create_dir_all(&path).map_err(|e| Io(path, e))?;
+        }
+        Ok(())
+    }
+
+    fn validate(self) -> Result<Self, Error> {
+        if self.bind.is_empty() {
+            return Err(Invalid("at least one bind address is required".to_string()));
+        }
+        for addr in &self.bind {
+            if let Err(e) = addr.to_socket_addrs() {
+                return Err(Invalid(format!("can't bind to '{}': {}", addr, e)));
+            }
+        }
+        if self.workers == Some(0) {
+            return Err(Invalid("workers must be at least 1".to_string()));
+        }
+        if self.max_strikes == 0 {
+            return Err(Invalid("max_strikes must be at least 1".to_string()));
+        }
+        if self.audit_rotate_bytes == 0 {
+            return Err(Invalid("audit_rotate_bytes must be at least 1".to_string()));
+        }
+        if self.max_sessions == Some(0) {
+            return Err(Invalid("max_sessions must be at least 1".to_string()));
+        }
+        if let Some(Secret(token)) = &self.admin_token {
+            if token.len() < 16 {
+                return Err(Invalid(
+                    "admin_token must be at least 16 characters long".to_string(),
+                ));
+            }
+        }
+        // the game config is the one that decides what plants and items there are
+        let plants = self
+            .plant_levels
+            .thresholds
+            .iter()
+            .map(|(plant, _)| ("plant_levels", plant))
+            .chain(
+                self.effect_caps
+                    .caps
+                    .iter()
+                    .map(|(plant, _)| ("effect_caps", plant)),
+            );
+        for (setting, plant) in plants {
+            if plant != "*" && hcor::CONFIG.find_plant(plant).is_err() {
+                return Err(Invalid(format!(
+                    "{}: there's no plant called '{}'",
+                    setting, plant
+                )));
+            }
+        }
+        let item = |name: &str| {
+            hcor::CONFIG
+                .possession_archetypes
+                .iter()
+                .find(|a| a.name == name)
+        };
+        let items = self
+            .rub_stacking
+            .policies
+            .iter()
+            .map(|(item, _)| ("rub_stacking", item))
+            .chain(self.cleansers.iter().map(|item| ("cleansers", item)));
+        for (setting, name) in items {
+            if name != "*" && item(name).is_none() {
+                return Err(Invalid(format!(
+                    "{}: there's no item called '{}'",
+                    setting, name
+                )));
+            }
+        }
+        for ((name, effect), _) in &self.rub_modifiers.by_effect {
+            match item(name) {
+                None => {
+                    return Err(Invalid(format!(
+                        "rub_modifiers: there's no item called '{}'",
+                        name
+                    )))
+                }
+                Some(a) if *effect >= a.plant_rub_effects.len() => {
+                    return Err(Invalid(format!(
+                        "rub_modifiers: {} only imparts {} effects, so it has no effect #{}",
+                        name,
+                        a.plant_rub_effects.len(),
+                        effect
+                    )))
+                }
+                Some(_) => {}
+            }
+        }
+        if !(0.0..1.0).contains(&self.level_yield_speedup) {
+            return Err(Invalid(format!(
+                "level_yield_speedup must be at least 0 and less than 1, got {}",
+                self.level_yield_speedup
+            )));
+        }
+        if self.heartbeat_interval >= self.client_timeout {
+            return Err(Invalid(format!(
+                "heartbeat_interval ({:?}) must be shorter than client_timeout ({:?}), \
+                    or every client would be dropped",
+                self.heartbeat_interval, self.client_timeout
+            )));
+        }
+        Ok(self)
+    }
+}
+
+/// A config that may have holes in it, either straight from a config file or
+/// with overrides applied.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Partial {
+    bind: Option<Vec<String>>,
+    data_dir: Option<PathBuf>,
+    workers: Option<usize>,
+    heartbeat_interval: Option<f64>,
+    client_timeout: Option<f64>,
+    autoclose: Option<bool>,
+    autoclose_after: Option<f64>,
+    max_sessions: Option<usize>,
+    admin_token: Option<String>,
+    audit_rotate_bytes: Option<usize>,
+    max_strikes: Option<usize>,
+    headless_idle: Option<f64>,
+    ask_retention: Option<f64>,
+    rate_limits: Option<Vec<String>>,
+    conn_rate_limits: Option<Vec<String>>,
+    cooldowns: Option<Vec<String>>,
+    plant_levels: Option<Vec<String>>,
+    yield_xp: Option<usize>,
+    craft_xp: Option<usize>,
+    level_yield_speedup: Option<f64>,
+    rub_modifiers: Option<Vec<String>>,
+    modifier_stacking: Option<String>,
+    effect_caps: Option<Vec<String>>,
+    rub_stacking: Option<Vec<String>>,
+    cleansers: Option<Vec<String>>,
+}
+
+impl Partial {
+    fn read(path: &Path) -> Result<Self, Error> {
+        let raw = std::fs::read_to_string(path).map_err(|e| Io(path.to_path_buf(), e))?;
+        toml::from_str(&raw).map_err(|e| Toml(path.to_path_buf(), e))
+    }
+
+    /// Overrides a setting with a value from the environment or command line,
+    /// the origin of which is described by `origin` should the value be unusable.
+    fn set(&mut self, key: &str, value: &str, origin: impl Fn() -> String) -> Result<(), Error> {
+        let bad = |expected| BadValue {
+            origin: origin(),
+            value: value.to_string(),
+            expected,
+        };
+        let secs = || value.parse::<f64>().map_err(|_| bad("a number of seconds"));
+        let count = || value.parse::<usize>().map_err(|_| bad("a whole number"));
+        let list = || {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+
+        match key {
+            "bind" => self.bind = Some(list()),
+            "data_dir" => self.data_dir = Some(PathBuf::from(value)),
+            "workers" => self.workers = Some(count()?),
+            "heartbeat_interval" => self.heartbeat_interval = Some(secs()?),
+            "client_timeout" => self.client_timeout = Some(secs()?),
+            "autoclose" => self.autoclose = Some(value.parse().map_err(|_| bad("true or false"))?),
+            "autoclose_after" => self.autoclose_after = Some(secs()?),
+            "max_sessions" => self.max_sessions = Some(count()?),
+            "admin_token" => self.admin_token = Some(value.to_string()),
+            "audit_rotate_bytes" => self.audit_rotate_bytes = Some(count()?),
+            "max_strikes" => self.max_strikes = Some(count()?),
+            "headless_idle" => self.headless_idle = Some(secs()?),
+            "ask_retention" => self.ask_retention = Some(secs()?),
+            "rate_limits" => self.rate_limits = Some(list()),
+            "conn_rate_limits" => self.conn_rate_limits = Some(list()),
+            "cooldowns" => self.cooldowns = Some(list()),
+            "plant_levels" => self.plant_levels = Some(list()),
+            "yield_xp" => self.yield_xp = Some(count()?),
+            "craft_xp" => self.craft_xp = Some(count()?),
+            "level_yield_speedup" => {
+                self.level_yield_speedup =
+                    Some(value.parse().map_err(|_| bad("a fraction, i.e. 0.1"))?)
+            }
+            "rub_modifiers" => self.rub_modifiers = Some(list()),
+            "modifier_stacking" => self.modifier_stacking = Some(value.to_string()),
+            "effect_caps" => self.effect_caps = Some(list()),
+            "rub_stacking" => self.rub_stacking = Some(list()),
+            "cleansers" => self.cleansers = Some(list()),
+            _ => return Err(UnknownFlag(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Config, Error> {
+        let default = Config::default();
+
+        let duration = |name: &str, secs: Option<f64>, default: Duration| match secs {
+            None => Ok(default),
+            Some(s) if s.is_finite() && s > 0.0 => Ok(Duration::from_secs_f64(s)),
+            Some(s) => Err(Invalid(format!(
+                "{} must be a positive number of seconds, got {}",
+                name, s
+            ))),
+        };
+
+        let rate_limits = |name: &str, specs: Option<Vec<String>>, default: RateLimits| {
+            specs.map_or(Ok(default), |specs| {
+                RateLimits::parse(&specs).map_err(|e| Invalid(format!("{}: {}", name, e)))
+            })
+        };
+
+        let autoclose_after = duration(
+            "autoclose_after",
+            self.autoclose_after,
+            Duration::from_secs(30),
+        )?;
+
+        Config {
+            bind: self.bind.unwrap_or(default.bind),
+            data_dir: self.data_dir.unwrap_or(default.data_dir),
+            workers: self.workers.or(default.workers),
+            heartbeat_interval: duration(
+                "heartbeat_interval",
+                self.heartbeat_interval,
+                default.heartbeat_interval,
+            )?,
+            client_timeout: duration(
+                "client_timeout",
+                self.client_timeout,
+                default.client_timeout,
+            )?,
+            autoclose: match self.autoclose {
+                Some(true) => Some(autoclose_after),
+                Some(false) => None,
+                None => default.autoclose.map(|_| autoclose_after),
+            },
+            max_sessions: self.max_sessions.or(default.max_sessions),
+            admin_token: self.admin_token.map(Secret),
+            audit_rotate_bytes: self
+                .audit_rotate_bytes
+                .unwrap_or(default.audit_rotate_bytes),
+            max_strikes: self.max_strikes.unwrap_or(default.max_strikes),
+            headless_idle: duration("headless_idle", self.headless_idle, default.headless_idle)?,
+            ask_retention: duration("ask_retention", self.ask_retention, default.ask_retention)?,
+            rate_limits: rate_limits("rate_limits", self.rate_limits, default.rate_limits)?,
+            conn_rate_limits: rate_limits(
+                "conn_rate_limits",
+                self.conn_rate_limits,
+                default.conn_rate_limits,
+            )?,
+            cooldowns: match self.cooldowns {
+                None => default.cooldowns,
+                Some(specs) => {
+                    Cooldowns::parse(&specs).map_err(|e| Invalid(format!("cooldowns: {}", e)))?
+                }
+            },
+            plant_levels: match self.plant_levels {
+                None => default.plant_levels,
+                Some(specs) => PlantLevels::parse(&specs)
+                    .map_err(|e| Invalid(format!("plant_levels: {}", e)))?,
+            },
+            yield_xp: self.yield_xp.unwrap_or(default.yield_xp),
+            craft_xp: self.craft_xp.unwrap_or(default.craft_xp),
+            level_yield_speedup: self
+                .level_yield_speedup
+                .unwrap_or(default.level_yield_speedup),
+            rub_modifiers: match self.rub_modifiers {
+                None => default.rub_modifiers,
+                Some(specs) => RubModifiers::parse(&specs)
+                    .map_err(|e| Invalid(format!("rub_modifiers: {}", e)))?,
+            },
+            modifier_stacking: match self.modifier_stacking.as_deref() {
+                None => default.modifier_stacking,
+                Some("multiply") => Stacking::Multiply,
+                Some("add") => Stacking::Add,
+                Some("highest") => Stacking::Highest,
+                Some(other) => {
+                    return Err(Invalid(format!(
+                        "modifier_stacking must be multiply, add or highest, got '{}'",
+                        other
+                    )))
+                }
+            },
+            effect_caps: match self.effect_caps {
+                None => default.effect_caps,
+                Some(specs) => {
+                    EffectCaps::parse(&specs).map_err(|e| Invalid(format!("effect_caps: {}", e)))?
+                }
+            },
+            rub_stacking: match self.rub_stacking {
+                None => default.rub_stacking,
+                Some(specs) => RubStackings::parse(&specs)
+                    .map_err(|e| Invalid(format!("rub_stacking: {}", e)))?,
+            },
+            cleansers: self.cleansers.unwrap_or(default.cleansers),
+        }
+        .validate()
+    }
+}
+
+/// What we gleaned from the command line.
+#[derive(Default)]
+struct Flags {
+    config: Option<PathBuf>,
+    bind: Vec<String>,
+    settings: Vec<(String, String)>,
+}
+
+impl Flags {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Error> {
+        let mut flags = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(Help);
+            }
+
+            let (name, inline_value) = match arg.find('=') {
+                Some(i) => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+                None => (arg.clone(), None),
+            };
+            let key = match name.strip_prefix("--") {
+                Some(key) => key.replace('-', "_"),
+                None => return Err(UnknownFlag(arg)),
+            };
+
+            match key.as_str() {
+                "autoclose" | "no_autoclose" if inline_value.is_none() => flags
+                    .settings
+                    .push(("autoclose".to_string(), (key == "autoclose").to_string())),
+                "config" | "bind" => {
+                    let value = inline_value
+                        .or_else(|| args.next())
+                        .ok_or_else(|| MissingValue(name.clone()))?;
+                    if key == "config" {
+                        flags.config = Some(PathBuf::from(value));
+                    } else {
+                        flags.bind.push(value);
+                    }
+                }
+                k if KEYS.contains(&k) => {
+                    let value = inline_value
+                        .or_else(|| args.next())
+                        .ok_or_else(|| MissingValue(name.clone()))?;
+                    flags.settings.push((key, value));
+                }
+                _ => return Err(UnknownFlag(name)),
+            }
+        }
+
+        Ok(flags)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, Error};
+    use std::{collections::HashMap, path::Path, time::Duration};
+
+    fn load(args: &[&str], env: &[(&str, &str)]) -> Result<Config, Error> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        // an empty path never exists, so whatever's in the working directory can't leak in
+        Config::from_sources_or(
+            args.iter().map(|s| s.to_string()),
+            |var| env.get(var).cloned(),
+            Path::new(""),
+        )
+    }
+
+    /// Two kinds of plant from the game's config, so these tests don't depend on which it has.
+    fn plants() -> (String, String) {
+        let mut plants: Vec<String> = hcor::CONFIG
+            .seeds()
+            .map(|(seed, _)| seed.grows_into.clone())
+            .collect();
+        plants.sort();
+        plants.dedup();
+        match plants.as_slice() {
+            [a, b, ..] => (a.clone(), b.clone()),
+            _ => panic!("these tests need a game config with at least two kinds of plant"),
+        }
+    }
+
+    /// An item from the game's config that can be rubbed onto plants, and two others.
+    fn items() -> (String, String, String) {
+        let arches = &hcor::CONFIG.possession_archetypes;
+        let rubbable = arches
+            .iter()
+            .find(|a| !a.plant_rub_effects.is_empty())
+            .expect("these tests need a game config with an item that can be rubbed onto plants");
+        let mut others = arches.iter().filter(|a| a.name != rubbable.name);
+        match (others.next(), others.next()) {
+            (Some(b), Some(c)) => (rubbable.name.clone(), b.name.clone(), c.name.clone()),
+            _ => panic!("these tests need a game config with at least three items"),
+        }
+    }
+
+    #[test]
+    fn flags_override_environment() {
+        let config = load(
+            &[
+                "--bind",
+                "0.0.0.0:80",
+                "--bind=0.0.0.0:81",
+                "--max-sessions",
+                "3",
+            ],
+            &[
+                ("HACKAGOTCHI_BIND", "127.0.0.1:9000"),
+                ("HACKAGOTCHI_MAX_SESSIONS", "5"),
+                ("HACKAGOTCHI_DATA_DIR", "/tmp/hackagotchi"),
+                ("HACKAGOTCHI_AUTOCLOSE", "true"),
+                ("HACKAGOTCHI_AUTOCLOSE_AFTER", "2.5"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.bind, vec!["0.0.0.0:80", "0.0.0.0:81"]);
+        assert_eq!(config.max_sessions, Some(3));
+        assert_eq!(config.data_dir.to_str(), Some("/tmp/hackagotchi"));
+        assert_eq!(config.autoclose, Some(Duration::from_millis(2500)));
+    }
+
+    #[test]
+    fn rate_limits() {
+        let config = load(
+            &[
+                "--rate-limits",
+                "*=2/4, Item.Throw=0.5/1, KnowledgeSnort=off",
+            ],
+            &[("HACKAGOTCHI_CONN_RATE_LIMITS", "Plant.Rub=1/2")],
+        )
+        .unwrap();
+
+        let rate = |kind| {
+            config
+                .rate_limits
+                .rate(kind)
+                .map(|r| (r.per_second, r.burst))
+        };
+        assert_eq!(rate("Item.Hatch"), Some((2.0, 4.0)));
+        assert_eq!(rate("Item.Throw"), Some((0.5, 1.0)));
+        assert_eq!(rate("KnowledgeSnort"), None);
+        assert_eq!(config.conn_rate_limits.rate("Item.Hatch"), None);
+        assert!(config.conn_rate_limits.rate("Plant.Rub").is_some());
+
+        // out of the box, nothing is rate limited
+        assert_eq!(Config::default().rate_limits.rate("Item.Throw"), None);
+        assert_eq!(Config::default().conn_rate_limits.rate("Item.Throw"), None);
+    }
+
+    #[test]
+    fn cooldowns() {
+        let config = load(
+            &["--cooldowns", "Item.Hatch=1/60, Plant.Rub@plant=3/86400"],
+            &[],
+        )
+        .unwrap();
+
+        let hatch = config.cooldowns.rule("Item.Hatch").unwrap();
+        assert_eq!((hatch.uses, hatch.per), (1, Duration::from_secs(60)));
+        assert!(!hatch.per_plant);
+        assert!(config.cooldowns.rule("Plant.Rub").unwrap().per_plant);
+        assert_eq!(config.cooldowns.rule("Item.Throw"), None);
+    }
+
+    #[test]
+    fn plant_levels() {
+        let (named, other) = plants();
+        let levels = format!("*=100/300, {}=50/150/450", named);
+        let config = load(&["--plant-levels", &levels], &[]).unwrap();
+
+        let level = |plant: &str, xp| config.plant_levels.level(plant, xp);
+        assert_eq!(level(&other, 99), 0);
+        assert_eq!(level(&other, 100), 1);
+        assert_eq!(level(&other, 10_000), 2);
+        assert_eq!(level(&named, 150), 2);
+        assert_eq!(level(&named, 450), 3);
+
+        // out of the box, plants stay at the level they start at
+        assert_eq!(Config::default().plant_levels.level(&named, 10_000), 0);
+    }
+
+    #[test]
+    fn rub_modifiers() {
+        use super::{Modifier, Stacking};
+
+        let (rubbable, other, _) = items();
+        let modifiers = format!("{}#0=yield_speed*1.5&xp*2", rubbable);
+        let config = load(
+            &["--rub-modifiers", &modifiers, "--modifier-stacking", "add"],
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.rub_modifiers.of(&rubbable, 0),
+            &[(Modifier::YieldSpeed, 1.5), (Modifier::Xp, 2.0)]
+        );
+        assert!(config.rub_modifiers.of(&rubbable, 1).is_empty());
+        assert!(config.rub_modifiers.of(&other, 0).is_empty());
+        assert_eq!(config.modifier_stacking, Stacking::Add);
+
+        let two = || vec![1.5, 1.5].into_iter();
+        assert_eq!(Stacking::Multiply.combine(two()), 2.25);
+        assert_eq!(Stacking::Add.combine(two()), 2.0);
+        assert_eq!(Stacking::Highest.combine(two()), 1.5);
+        assert_eq!(Stacking::Highest.combine(std::iter::empty()), 1.0);
+    }
+
+    #[test]
+    fn effect_limits() {
+        use super::RubStacking;
+
+        let (uncapped, capped) = plants();
+        let (a, b, c) = items();
+        let config = load(
+            &[
+                "--effect-caps",
+                &format!("*=3, {}=off", uncapped),
+                "--rub-stacking",
+                &format!("{}=refresh, {}=reject", a, b),
+                "--cleansers",
+                &format!("{}, {}", b, a),
+            ],
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(config.effect_caps.cap(&capped), Some(3));
+        assert_eq!(config.effect_caps.cap(&uncapped), None);
+        let policy = |item: &str| config.rub_stacking.policy(item);
+        assert_eq!(policy(&a), RubStacking::Refresh);
+        assert_eq!(policy(&b), RubStacking::Reject);
+        assert_eq!(policy(&c), RubStacking::Stack);
+        assert_eq!(config.cleansers, vec![b, a.clone()]);
+
+        // out of the box, rubbing works just like it did before there were any limits
+        let default = Config::default();
+        assert_eq!(default.effect_caps.cap(&uncapped), None);
+        assert_eq!(default.rub_stacking.policy(&a), RubStacking::Stack);
+        assert!(default.cleansers.is_empty());
+    }
+
+    #[test]
+    fn invalid_settings_are_refused() {
+        // each should be refused for what it's testing, not for anything else
+        let bad = |args: &[&str], env: &[(&str, &str)], why: &str| match load(args, env) {
+            Err(e) => assert!(
+                e.to_string().contains(why),
+                "expected an error about {}, got: {}",
+                why,
+                e
+            ),
+            Ok(c) => panic!("unexpectedly accepted config: {:#?}", c),
+        };
+        let (rubbable, _, _) = items();
+
+        bad(&["--workers", "0"], &[], "workers must be at least 1");
+        bad(&["--admin-token", "hunter2"], &[], "admin_token must be");
+        bad(
+            &["--audit-rotate-bytes", "0"],
+            &[],
+            "audit_rotate_bytes must be",
+        );
+        bad(&["--max-strikes", "0"], &[], "max_strikes must be");
+        bad(
+            &["--rate-limits", "Item.Yeet=1/5"],
+            &[],
+            "no kind of ask called 'Item.Yeet'",
+        );
+        bad(
+            &["--rate-limits", "*=10"],
+            &[],
+            "rate_limits: '10' should look like",
+        );
+        bad(
+            &["--conn-rate-limits", "*=0/5"],
+            &[],
+            "conn_rate_limits: '0' in '*=0/5'",
+        );
+        bad(
+            &["--cooldowns", "KnowledgeSnort=1/60"],
+            &[],
+            "asks called 'KnowledgeSnort' can't have cooldowns",
+        );
+        bad(
+            &["--cooldowns", "Item.Hatch@plant=1/60"],
+            &[],
+            "Item.Hatch asks aren't made on plants",
+        );
+        bad(
+            &["--cooldowns", "Item.Hatch=0/60"],
+            &[],
+            "'Item.Hatch=0/60' should allow at least one ask",
+        );
+        bad(
+            &["--plant-levels", "*=300/100"],
+            &[],
+            "in '*=300/100' should go up",
+        );
+        bad(&["--plant-levels", "*=lots"], &[], "'lots' in '*=lots'");
+        bad(
+            &["--plant-levels", "Not A Plant=100"],
+            &[],
+            "plant_levels: there's no plant called 'Not A Plant'",
+        );
+        bad(
+            &["--level-yield-speedup", "1"],
+            &[],
+            "level_yield_speedup must be",
+        );
+        bad(
+            &["--rub-modifiers", "Warp Powder=xp*2"],
+            &[],
+            "'Warp Powder=xp*2' should look like <item>#<effect>",
+        );
+        bad(
+            &["--rub-modifiers", "Warp Powder#first=xp*2"],
+            &[],
+            "'Warp Powder#first=xp*2' should look like",
+        );
+        bad(
+            &["--rub-modifiers", "Warp Powder#0=zoom*2"],
+            &[],
+            "no modifier called 'zoom'",
+        );
+        bad(
+            &["--rub-modifiers", "Warp Powder#0=yield_quantity*2"],
+            &[],
+            "no modifier called 'yield_quantity'",
+        );
+        bad(
+            &["--rub-modifiers", "Warp Powder#0=xp*-1"],
+            &[],
+            "'xp*-1' in 'Warp Powder#0=xp*-1'",
+        );
+        bad(
+            &["--rub-modifiers", "Not An Item#0=xp*2"],
+            &[],
+            "rub_modifiers: there's no item called 'Not An Item'",
+        );
+        bad(
+            &["--rub-modifiers", &format!("{}#1000=xp*2", rubbable)],
+            &[],
+            "so it has no effect #1000",
+        );
+        bad(
+            &["--modifier-stacking", "average"],
+            &[],
+            "modifier_stacking must be multiply, add or highest, got 'average'",
+        );
+        bad(
+            &["--effect-caps", "*=lots"],
+            &[],
+            "effect_caps: 'lots' in '*=lots'",
+        );
+        bad(
+            &["--effect-caps", "Not A Plant=3"],
+            &[],
+            "effect_caps: there's no plant called 'Not A Plant'",
+        );
+        bad(
+            &["--rub-stacking", "*=double"],
+            &[],
+            "rub_stacking: 'double' in '*=double'",
+        );
+        bad(
+            &["--rub-stacking", "Not An Item=reject"],
+            &[],
+            "rub_stacking: there's no item called 'Not An Item'",
+        );
+        bad(
+            &["--cleansers", "Not An Item"],
+            &[],
+            "cleansers: there's no item called 'Not An Item'",
+        );
+        bad(&["--workers", "lots"], &[], "was set to 'lots'");
+        bad(&["--frobnicate"], &[], "unknown flag --frobnicate");
+        bad(&["--data-dir"], &[], "data-dir flag requires a value");
+        bad(
+            &[],
+            &[("HACKAGOTCHI_CLIENT_TIMEOUT", "-1")],
+            "client_timeout must be a positive number of seconds",
+        );
+        bad(
+            &["--heartbeat-interval", "10", "--client-timeout", "5"],
+            &[],
+            "must be shorter than client_timeout",
+        );
+    }
+}
@@ -106,6 +106,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     pretty_env_logger::init();
 
+    let config = backend::Config::load()?;
+    config.create_data_dirs()?;
+
     let mut rdr = csv::ReaderBuilder::new()
         .from_path("hackagotchi.csv")
         .map_err(|e| format!("invalid csv: {}", e))?;
@@ -248,7 +251,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 len,
                 (i as f32 / len as f32) * 100.0
             );
-            backend::fs_put_stead(&hs)?;
+            backend::fs_put_stead(&config.data_dir, &hs)?;
         } else {
             println!("ignoring {}", id);
         }
@@ -0,0 +1,37 @@
+//! What the fuzz targets in `fuzz/` need to get at, which is otherwise kept to ourselves.
+use crate::config::{self, Config, EffectCaps, RubStackings};
+pub use crate::wormhole::{
+    server::transfer,
+    session::{cooldown::Cooldowns, decode_ask, decode_inbound, settle, Orifice, Settled},
+    version::Version,
+};
+
+/// One of a few sets of rules to settle Asks by, picked by `which`: those out of the box, and
+/// others that cap effects, refuse or refresh rubs, allow cleansing and put Asks on cooldown.
+pub fn rules(which: u8) -> Config {
+    let specs = |specs: &[&str]| specs.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let mut config = Config::default();
+    if which % 4 == 0 {
+        return config;
+    }
+
+    let (caps, stacking) = match which % 4 {
+        1 => ("*=1", "*=reject"),
+        2 => ("*=2", "*=refresh"),
+        _ => ("*=off", "*=stack"),
+    };
+    config.effect_caps = EffectCaps::parse(&specs(&[caps])).unwrap();
+    config.rub_stacking = RubStackings::parse(&specs(&[stacking])).unwrap();
+    config.cooldowns = config::Cooldowns::parse(&specs(&[
+        "TileSummon=1/60",
+        "Item.Hatch=2/60",
+        "Plant.Rub@plant=1/60",
+    ]))
+    .unwrap();
+    config.cleansers = hcor::CONFIG
+        .possession_archetypes
+        .iter()
+        .map(|a| a.name.clone())
+        .collect();
+    config
+}
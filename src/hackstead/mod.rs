@@ -1,56 +1,114 @@
 use crate::{
-    wormhole::{self, server},
-    ServiceError,
+    audit, metrics,
+    wormhole::{self, server, session::cooldown},
+    Config, ServiceError,
 };
 use actix_web::{post, web, HttpResponse};
 use hcor::{hackstead::NewHacksteadRequest, Hackstead, IdentifiesSteader, IdentifiesUser, UserId};
 use log::*;
-use std::fs;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
-#[cfg(all(test, feature = "hcor_client"))]
+#[cfg(test)]
 mod test;
 
-fn user_path(iu: impl IdentifiesUser) -> String {
+fn user_path(data_dir: &Path, iu: impl IdentifiesUser) -> PathBuf {
     match iu.user_id() {
-        UserId::Uuid(uuid) | UserId::Both { uuid, .. } => stead_path(uuid),
-        UserId::Slack(slack_id) => slack_path(&slack_id),
+        UserId::Uuid(uuid) | UserId::Both { uuid, .. } => stead_path(data_dir, uuid),
+        UserId::Slack(slack_id) => slack_path(data_dir, &slack_id),
     }
 }
 
-fn stead_path(is: impl IdentifiesSteader) -> String {
-    format!("stead/{}.json", is.steader_id())
+fn stead_path(data_dir: &Path, is: impl IdentifiesSteader) -> PathBuf {
+    data_dir
+        .join("stead")
+        .join(format!("{}.json", is.steader_id()))
+}
+
+fn slack_path(data_dir: &Path, slack: &str) -> PathBuf {
+    data_dir.join("slack").join(format!("{}.json", slack))
+}
+
+/// How a hackstead is kept on disk: the hackstead itself, with what's left of its steader's
+/// cooldowns alongside, so that the two are always written together.
+#[derive(serde::Serialize)]
+struct Stored<'a> {
+    #[serde(flatten)]
+    hackstead: &'a Hackstead,
+    cooldowns: &'a cooldown::Remaining,
 }
 
-fn slack_path(slack: &str) -> String {
-    format!("slack/{}.json", slack)
+/// Just the cooldowns out of a `Stored` hackstead, which has none if it was saved without them.
+#[derive(serde::Deserialize)]
+struct StoredCooldowns {
+    #[serde(default)]
+    cooldowns: cooldown::Remaining,
 }
 
-pub fn fs_get_stead(user_id: impl IdentifiesUser) -> Result<Hackstead, ServiceError> {
-    Ok(serde_json::from_str(&fs::read_to_string(user_path(
-        user_id,
-    ))?)?)
+pub fn fs_get_stead(
+    data_dir: &Path,
+    user_id: impl IdentifiesUser,
+) -> Result<Hackstead, ServiceError> {
+    let raw = metrics::time_storage("read", || fs::read_to_string(user_path(data_dir, user_id)))?;
+    Ok(serde_json::from_str(&raw)?)
 }
 
-pub fn fs_put_stead(hs: &Hackstead) -> Result<(), ServiceError> {
-    let stead_path = stead_path(hs);
-    fs::write(&stead_path, serde_json::to_string(hs)?)?;
+/// Saves a hackstead, keeping whatever cooldowns were already saved with it.
+pub fn fs_put_stead(data_dir: &Path, hs: &Hackstead) -> Result<(), ServiceError> {
+    let cooldowns = fs_get_cooldowns(data_dir, hs)?;
+    fs_put_stead_with_cooldowns(data_dir, hs, &cooldowns)
+}
 
+/// Saves a hackstead along with what's left of its steader's cooldowns, in a single write.
+pub fn fs_put_stead_with_cooldowns(
+    data_dir: &Path,
+    hs: &Hackstead,
+    cooldowns: &cooldown::Remaining,
+) -> Result<(), ServiceError> {
+    let stead_path = stead_path(data_dir, hs);
+    let raw = serde_json::to_string(&Stored {
+        hackstead: hs,
+        cooldowns,
+    })?;
+    metrics::time_storage("write", || fs::write(&stead_path, raw))?;
+
+    // the slack path is a hard link to the stead path, so if it's already there,
+    // writing to the stead path updated it as well.
     if let Some(s) = hs.profile.slack_id.as_ref() {
-        fs::hard_link(&stead_path, &slack_path(s))?;
+        match fs::hard_link(&stead_path, &slack_path(data_dir, s)) {
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            other => other?,
+        }
     }
 
     Ok(())
 }
 
+/// What's left of a steader's cooldowns, as saved with their hackstead; nothing, if there's no
+/// hackstead saved for them yet.
+pub fn fs_get_cooldowns(
+    data_dir: &Path,
+    is: impl IdentifiesSteader,
+) -> Result<cooldown::Remaining, ServiceError> {
+    match metrics::time_storage("read", || fs::read_to_string(stead_path(data_dir, is))) {
+        Ok(raw) => Ok(serde_json::from_str::<StoredCooldowns>(&raw)?.cooldowns),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(cooldown::Remaining::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
 #[post("/hackstead/spy")]
 /// Returns a user's hackstead, complete with Profile, Inventory, and Tiles.
 pub async fn hackstead_spy(
     user: web::Json<UserId>,
     srv: web::Data<actix::Addr<wormhole::Server>>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, ServiceError> {
     debug!("servicing get_hackstead request");
 
-    let mut stead = fs_get_stead(&*user)?;
+    let mut stead = fs_get_stead(&config.data_dir, &*user)?;
     trace!("got hackstead from fs: {:#?}", stead);
 
     // if there's already a Session up for this user, that Session will have a much fresher
@@ -66,28 +124,49 @@ pub async fn hackstead_spy(
 #[post("/hackstead/summon")]
 pub async fn hackstead_summon(
     user: web::Json<NewHacksteadRequest>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, ServiceError> {
     debug!("servicing new_hackstead request");
 
     let slack = user.slack_id.as_ref();
     let stead = Hackstead::new_user(slack);
 
-    fs_put_stead(&stead)?;
+    fs_put_stead(&config.data_dir, &stead)?;
+    audit::record(
+        &config,
+        stead.steader_id(),
+        audit::Event::Snapshot {
+            hackstead: Box::new(stead.clone()),
+        },
+    );
 
     Ok(HttpResponse::Created().json(&stead))
 }
 
 #[post("/hackstead/slaughter")]
-pub async fn hackstead_slaughter(user: web::Json<UserId>) -> Result<HttpResponse, ServiceError> {
+pub async fn hackstead_slaughter(
+    user: web::Json<UserId>,
+    srv: web::Data<actix::Addr<wormhole::Server>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ServiceError> {
     debug!("servicing remove_hackstead request");
 
-    let stead = fs_get_stead(&*user)?;
-    debug!(":( removing hackstead: {:#?}", stead);
+    let mut stead = fs_get_stead(&config.data_dir, &*user)?;
 
-    fs::remove_file(&stead_path(stead.profile.steader_id))?;
-    if let Some(slack) = stead.profile.slack_id.as_ref() {
-        fs::remove_file(&slack_path(slack))?;
+    // a live Session would save this hackstead right back to disk when it ends,
+    // so it has to be told that it's been slaughtered.
+    if let Some(ses) = srv.send(server::GetSession::new(&stead)).await? {
+        stead = ses.send(wormhole::session::Slaughter).await?;
     }
+    debug!(":( removing hackstead: {:#?}", stead);
+
+    metrics::time_storage("remove", || {
+        fs::remove_file(&stead_path(&config.data_dir, stead.profile.steader_id))?;
+        if let Some(slack) = stead.profile.slack_id.as_ref() {
+            fs::remove_file(&slack_path(&config.data_dir, slack))?;
+        }
+        Ok::<(), std::io::Error>(())
+    })?;
 
     Ok(HttpResponse::Ok().json(stead))
 }
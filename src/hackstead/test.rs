@@ -1,28 +1,26 @@
 #[actix_rt::test]
-async fn hackstead() -> hcor::ClientResult<()> {
-    use hcor::Hackstead;
+async fn hackstead() {
+    use crate::harness::Harness;
 
-    // attempt to establish logging, do nothing if it fails
-    // (it probably fails because it's already been established in another test)
-    drop(pretty_env_logger::try_init());
+    let h = Harness::start().await;
 
     // create bob's stead!
-    let bobstead = Hackstead::register().await?;
+    let bobstead = h.summon().await;
 
     // fetch bob
-    assert_eq!(Hackstead::fetch(&bobstead).await?, bobstead);
+    assert_eq!(h.spy(&bobstead).await.unwrap(), bobstead);
 
     // now kill bob
-    bobstead.slaughter().await?;
+    h.slaughter(&bobstead).await.unwrap();
 
     // make sure we can't get ded bob
-    match Hackstead::fetch(&bobstead).await {
+    match h.spy(&bobstead).await {
         Err(e) => log::info!("fetching ded bobstead failed as expected: {}", e),
         Ok(_) => panic!("fetching bobstead succeeded after he was slaughtered!"),
     }
 
     // make sure we can't kill an already dead bob
-    match bobstead.slaughter().await {
+    match h.slaughter(&bobstead).await {
         Err(e) => log::info!(
             "received error as expected killing bob a second time: {}",
             e
@@ -31,11 +29,9 @@ async fn hackstead() -> hcor::ClientResult<()> {
     }
 
     // even if we cheat and use the API directly
-    match hcor::client_internal::request::<Hackstead, _>(
-        "hackstead/slaughter",
-        &bobstead.profile.steader_id,
-    )
-    .await
+    match h
+        .post::<hcor::Hackstead>("hackstead/slaughter", &bobstead.profile.steader_id)
+        .await
     {
         Err(e) => log::info!(
             "received error as expected killing bob a second time with API: {}",
@@ -44,5 +40,5 @@ async fn hackstead() -> hcor::ClientResult<()> {
         Ok(_) => panic!("killing bob a second time with the API worked somehow"),
     }
 
-    Ok(())
+    h.stop().await;
 }
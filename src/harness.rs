@@ -0,0 +1,405 @@
+//! Runs the whole server in-process, on an ephemeral port and with a fresh data directory,
+//! so that tests needn't share a server with each other and can all run at once.
+//!
+//! ```ignore
+//! let h = Harness::start().await;
+//! let bobstead = h.summon().await;
+//! let mut wormhole = h.wormhole(&bobstead).await;
+//! wormhole.ask(Ask::KnowledgeSnort { xp: 10 }).await;
+//! h.stop().await;
+//! ```
+use crate::{
+    routes,
+    world::ManualClock,
+    wormhole::protocol::{BatchMessage, Inbound, Outbound, ProtocolNote},
+    wormhole::session::Orifice,
+    Config, Relays, World, WormholeServer,
+};
+use actix::{Actor, Addr};
+use actix_web::{web, App, HttpServer};
+use awc::{ws, BoxedSocket};
+use futures::{SinkExt, StreamExt};
+use hcor::{
+    wormhole::{AskMessage, AskedNote, PlantAsk, RudeNote},
+    Ask, Hackstead, IdentifiesSteader, Note, Plant, UserId, CONFIG,
+};
+use std::{path::PathBuf, time::Duration};
+
+/// A server all to yourself.
+pub struct Harness {
+    pub url: String,
+    pub config: web::Data<Config>,
+    pub world: web::Data<World>,
+    pub wormhole: Addr<WormholeServer>,
+    http: actix_web::dev::Server,
+}
+
+impl Harness {
+    /// Starts a server listening on some free port, storing hacksteads in a new temp directory.
+    /// Must be called from within an actix System, i.e. in an `#[actix_rt::test]`.
+    pub async fn start() -> Self {
+        Self::start_in(World::real()).await
+    }
+
+    /// Like `start`, but with the clock and dice provided, i.e. a `World::deterministic` one.
+    pub async fn start_in(world: World) -> Self {
+        Self::start_with(world, |_| {}).await
+    }
+
+    /// Like `start_in`, but with whatever changes to the `Config` you like.
+    pub async fn start_with(world: World, configure: impl FnOnce(&mut Config)) -> Self {
+        // attempt to establish logging, do nothing if it fails
+        // (it probably fails because it's already been established in another test)
+        drop(pretty_env_logger::try_init());
+
+        let data_dir: PathBuf =
+            std::env::temp_dir().join(format!("hackagotchi-test-{}", uuid::Uuid::new_v4()));
+        let mut config = Config {
+            bind: vec!["127.0.0.1:0".to_string()],
+            data_dir,
+            workers: Some(1),
+            autoclose: None,
+            ..Config::default()
+        };
+        configure(&mut config);
+        config
+            .create_data_dirs()
+            .expect("couldn't create test data dir");
+        let config = web::Data::new(config);
+
+        let wormhole = WormholeServer::new(config.clone()).start();
+        let world = web::Data::new(world);
+        let relays = web::Data::new(Relays::default());
+        let (app_config, app_world, app_wormhole) =
+            (config.clone(), world.clone(), wormhole.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .register_data(app_config.clone())
+                .register_data(app_world.clone())
+                .register_data(relays.clone())
+                .data(app_wormhole.clone())
+                .configure(routes)
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .expect("couldn't bind test server to an ephemeral port");
+
+        let addr = server.addrs()[0];
+        let http = server.disable_signals().run();
+        wormhole.do_send(crate::Serving(http.clone()));
+
+        Self {
+            url: format!("http://{}", addr),
+            config,
+            world,
+            wormhole,
+            http,
+        }
+    }
+
+    /// Shuts the server down. The data directory isn't thrown away until the `Harness` is
+    /// dropped, which, since this takes it, is as soon as the server has stopped.
+    pub async fn stop(self) {
+        self.http.stop(false).await;
+    }
+
+    /// POSTs `body` as JSON to a route under `/api`, reading the response as a `T` if it succeeded.
+    pub async fn post<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &impl serde::Serialize,
+    ) -> Result<T, String> {
+        let mut res = awc::Client::new()
+            .post(format!("{}/api/{}", self.url, path))
+            .send_json(body)
+            .await
+            .map_err(|e| format!("couldn't reach test server: {}", e))?;
+
+        if !res.status().is_success() {
+            let body = res.body().await.unwrap_or_default();
+            return Err(format!(
+                "{} from /api/{}: {}",
+                res.status(),
+                path,
+                String::from_utf8_lossy(&body)
+            ));
+        }
+        res.json()
+            .await
+            .map_err(|e| format!("couldn't parse response from /api/{}: {}", path, e))
+    }
+
+    /// Makes a brand new hackstead.
+    pub async fn summon(&self) -> Hackstead {
+        self.post(
+            "hackstead/summon",
+            &hcor::hackstead::NewHacksteadRequest { slack_id: None },
+        )
+        .await
+        .expect("couldn't summon hackstead")
+    }
+
+    /// The freshest copy of a user's hackstead.
+    pub async fn spy(&self, hs: impl IdentifiesSteader) -> Result<Hackstead, String> {
+        self.post("hackstead/spy", &UserId::Uuid(hs.steader_id()))
+            .await
+    }
+
+    /// Removes a user's hackstead.
+    pub async fn slaughter(&self, hs: impl IdentifiesSteader) -> Result<Hackstead, String> {
+        self.post("hackstead/slaughter", &UserId::Uuid(hs.steader_id()))
+            .await
+    }
+
+    /// Has a user's session catch its timers up with the clock right away;
+    /// call this after advancing the `ManualClock` of a deterministic `World`.
+    pub async fn tick(&self, hs: impl IdentifiesSteader) {
+        use crate::wormhole::{server::GetSession, session::Tick};
+
+        self.wormhole
+            .send(GetSession(hs.steader_id()))
+            .await
+            .unwrap()
+            .expect("can't tick a user without a session")
+            .send(Tick)
+            .await
+            .unwrap()
+    }
+
+    /// Lets `updates` updates pass for a user, one at a time, so that timers finish on exactly
+    /// the update they're due on rather than whenever a long stretch of time rounds out to.
+    pub async fn pass(&self, clock: &ManualClock, hs: impl IdentifiesSteader, updates: usize) {
+        use crate::wormhole::{server::GetSession, session::Tick};
+
+        let session = self
+            .wormhole
+            .send(GetSession(hs.steader_id()))
+            .await
+            .unwrap()
+            .expect("can't tick a user without a session");
+        for _ in 0..updates {
+            clock.advance(hcor::UPDATE_INTERVAL);
+            session.send(Tick).await.unwrap();
+        }
+    }
+
+    /// Opens a wormhole for a user, through the JSON orifice.
+    pub async fn wormhole(&self, hs: impl IdentifiesSteader) -> Wormhole {
+        self.wormhole_through(hs, Orifice::Json).await
+    }
+
+    /// Opens a wormhole for a user, through whichever orifice you like.
+    pub async fn wormhole_through(&self, hs: impl IdentifiesSteader, orifice: Orifice) -> Wormhole {
+        let user = serde_json::to_string(&UserId::Uuid(hs.steader_id())).unwrap();
+        let (_, framed) = awc::Client::new()
+            .ws(format!("{}/api/wormhole", self.url))
+            .header("WormholeUser", user)
+            .header("WormholeOrifice", serde_json::to_string(&orifice).unwrap())
+            .header(
+                "WormholeVersion",
+                crate::wormhole::version::CURRENT.to_string(),
+            )
+            .connect()
+            .await
+            .expect("couldn't open wormhole to test server");
+
+        Wormhole {
+            framed,
+            orifice,
+            next_ask_id: 0,
+        }
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        drop(std::fs::remove_dir_all(&self.config.data_dir));
+    }
+}
+
+/// The archetype handles of a seed and of an item that imparts effects when rubbed on what that
+/// seed grows into, which wear off if `wearing_off` is set.
+pub fn rubbable(wearing_off: bool) -> (usize, usize) {
+    CONFIG
+        .possession_archetypes
+        .iter()
+        .enumerate()
+        .find_map(|(seed, arch)| {
+            let grows_into = &arch.seed.as_ref()?.grows_into;
+            let rub = CONFIG.possession_archetypes.iter().position(|arch| {
+                arch.rub_effects_for_plant(grows_into)
+                    .any(|e| !wearing_off || e.duration.is_some())
+            })?;
+            Some((seed, rub))
+        })
+        .expect("no seeds in config that grow into plants we can rub with effects?")
+}
+
+/// One end of a wormhole to a `Harness`.
+pub struct Wormhole {
+    framed: actix_codec::Framed<BoxedSocket, ws::Codec>,
+    orifice: Orifice,
+    next_ask_id: usize,
+}
+
+impl Wormhole {
+    /// Sends an Ask through the wormhole, returning the `ask_id` it was given.
+    pub async fn ask(&mut self, ask: Ask) -> usize {
+        let ask_id = self.next_ask_id;
+        self.next_ask_id += 1;
+        self.send(Inbound::Ask(AskMessage { ask, ask_id })).await;
+        ask_id
+    }
+
+    /// Sends an Ask through the wormhole with an `ask_id` of our choosing, i.e. one that's been
+    /// used before.
+    pub async fn resend(&mut self, ask_id: usize, ask: Ask) {
+        self.send(Inbound::Ask(AskMessage { ask, ask_id })).await;
+    }
+
+    /// Sends a batch of Asks through the wormhole, returning the `ask_id` it was given.
+    pub async fn batch(&mut self, asks: Vec<Ask>) -> usize {
+        let ask_id = self.next_ask_id;
+        self.next_ask_id += 1;
+        self.send(Inbound::Batch(BatchMessage { asks, ask_id }))
+            .await;
+        ask_id
+    }
+
+    async fn send(&mut self, inbound: Inbound) {
+        let message = match self.orifice {
+            Orifice::Json => ws::Message::Text(serde_json::to_string(&inbound).unwrap()),
+            binary => ws::Message::Binary(binary.encode(&inbound).unwrap().into()),
+        };
+        self.framed
+            .send(message)
+            .await
+            .expect("couldn't send through wormhole");
+    }
+
+    /// Waits for something that `f` returns `Some` for, panicking if it doesn't arrive in time.
+    /// Anything that can't be read as what `f` takes, i.e. `ProtocolNote`s while waiting for an
+    /// `Outbound`, is skipped. Binary messages are read in this wormhole's orifice.
+    pub async fn until<M: serde::de::DeserializeOwned, T>(
+        &mut self,
+        wait: Duration,
+        mut f: impl FnMut(M) -> Option<T>,
+    ) -> T {
+        let (framed, orifice) = (&mut self.framed, self.orifice);
+        let found = async move {
+            while let Some(frame) = framed.next().await {
+                let message = match frame.expect("wormhole error") {
+                    ws::Frame::Text(bytes) => serde_json::from_slice(&bytes).ok(),
+                    ws::Frame::Binary(bytes) => orifice.decode(&bytes).ok(),
+                    ws::Frame::Close(reason) => panic!("wormhole closed: {:?}", reason),
+                    _ => None,
+                };
+                if let Some(t) = message.and_then(&mut f) {
+                    return Some(t);
+                }
+            }
+            None
+        };
+
+        tokio::time::timeout(wait, found)
+            .await
+            .expect("timed out waiting for note")
+            .expect("wormhole ended before note arrived")
+    }
+
+    /// Waits for the response to a particular Ask.
+    pub async fn asked(&mut self, ask_id: usize) -> AskedNote {
+        self.until(Duration::from_secs(5), |o: Outbound| match o {
+            Outbound::Note(Note::Asked { ask_id: id, note }) if id == ask_id => Some(note),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Waits for a `RudeNote` that `f` returns `Some` for.
+    pub async fn rude<T>(&mut self, mut f: impl FnMut(RudeNote) -> Option<T>) -> T {
+        self.until(Duration::from_secs(5), |o: Outbound| match o {
+            Outbound::Note(Note::Rude(note)) => f(note),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Spawns a seed of this archetype and plants it on `tile_id`, panicking if that fails.
+    pub async fn plant(&mut self, tile_id: hcor::TileId, seed_archetype_handle: usize) -> Plant {
+        let seed_item_id = self.spawn(seed_archetype_handle, 1).await[0].item_id;
+        let ask_id = self
+            .ask(Ask::Plant(PlantAsk::Summon {
+                tile_id,
+                seed_item_id,
+            }))
+            .await;
+        match self.asked(ask_id).await {
+            AskedNote::PlantSummonResult(Ok(plant)) => plant,
+            other => panic!("couldn't plant seed: {:?}", other),
+        }
+    }
+
+    /// Spawns `amount` items of an archetype into the hackstead on the other end.
+    pub async fn spawn(&mut self, item_archetype_handle: usize, amount: usize) -> Vec<hcor::Item> {
+        use hcor::wormhole::ItemAsk;
+
+        let ask_id = self
+            .ask(Ask::Item(ItemAsk::Spawn {
+                item_archetype_handle,
+                amount,
+            }))
+            .await;
+        match self.asked(ask_id).await {
+            AskedNote::ItemSpawnResult(Ok(items)) => items,
+            other => panic!("couldn't spawn items: {:?}", other),
+        }
+    }
+
+    /// Waits for the next `ProtocolNote`.
+    pub async fn protocol_note(&mut self) -> ProtocolNote {
+        #[derive(serde::Deserialize)]
+        enum Wrapper {
+            Protocol(ProtocolNote),
+        }
+
+        self.until(
+            Duration::from_secs(5),
+            |Wrapper::Protocol(note): Wrapper| Some(note),
+        )
+        .await
+    }
+
+    /// Waits for the response to a particular batch, i.e. whether it was committed,
+    /// and the response to each of its Asks.
+    pub async fn batched(&mut self, ask_id: usize) -> (bool, Vec<AskedNote>) {
+        self.until(Duration::from_secs(5), |o: Outbound| match o {
+            Outbound::Batched {
+                ask_id: id,
+                committed,
+                results,
+            } if id == ask_id => Some((committed, results)),
+            _ => None,
+        })
+        .await
+    }
+}
+
+#[actix_rt::test]
+async fn knowledge_snort() {
+    let h = Harness::start().await;
+    let bobstead = h.summon().await;
+
+    let mut wormhole = h.wormhole(&bobstead).await;
+    let ask_id = wormhole.ask(Ask::KnowledgeSnort { xp: 10 }).await;
+    match wormhole.asked(ask_id).await {
+        AskedNote::KnowledgeSnortResult(Ok(xp)) => assert_eq!(xp, bobstead.profile.xp + 10),
+        other => panic!("unexpected response to KnowledgeSnort: {:?}", other),
+    }
+
+    assert_eq!(
+        h.spy(&bobstead).await.unwrap().profile.xp,
+        bobstead.profile.xp + 10
+    );
+    h.stop().await;
+}
@@ -0,0 +1,91 @@
+//! Routes for an orchestrator to probe: `/health` says whether the process is alive and
+//! responsive, `/ready` says whether it's actually capable of serving users.
+use crate::{wormhole, Config};
+use actix_web::{get, web, HttpResponse};
+use serde_json::json;
+use std::{fs, time::Duration};
+
+/// How long the Server actor has to answer before we consider it unresponsive.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[get("/health")]
+/// Succeeds so long as the wormhole Server is processing its mailbox.
+pub async fn health(srv: web::Data<actix::Addr<wormhole::Server>>) -> HttpResponse {
+    match srv
+        .send(wormhole::server::Census)
+        .timeout(SERVER_TIMEOUT)
+        .await
+    {
+        Ok(sessions) => HttpResponse::Ok().json(json!({
+            "status": "ok",
+            "sessions": sessions,
+        })),
+        Err(e) => HttpResponse::ServiceUnavailable().json(json!({
+            "status": "unhealthy",
+            "server": format!("wormhole server unresponsive: {}", e),
+        })),
+    }
+}
+
+#[get("/ready")]
+/// Succeeds if hacksteads can be stored and the game config is loaded and sane.
+pub async fn ready(config: web::Data<Config>) -> HttpResponse {
+    let (storage, game_config) = (storage_writable(&config), game_config_valid());
+    let all_ok = storage.is_ok() && game_config.is_ok();
+    let checks = json!({
+        "storage": check(storage),
+        "config": check(game_config),
+    });
+
+    if all_ok {
+        HttpResponse::Ok().json(json!({ "status": "ready", "checks": checks }))
+    } else {
+        HttpResponse::ServiceUnavailable().json(json!({ "status": "unready", "checks": checks }))
+    }
+}
+
+fn check(r: Result<(), String>) -> serde_json::Value {
+    match r {
+        Ok(()) => json!({ "ok": true }),
+        Err(e) => json!({ "ok": false, "error": e }),
+    }
+}
+
+/// Makes sure we can actually write to where hacksteads are kept.
+fn storage_writable(config: &Config) -> Result<(), String> {
+    let path = config
+        .data_dir
+        .join("stead")
+        .join(format!(".ready-{}", uuid::Uuid::new_v4()));
+
+    fs::write(&path, b"ready?")
+        .and_then(|()| fs::remove_file(&path))
+        .map_err(|e| format!("can't write to {}: {}", path.display(), e))
+}
+
+/// Makes sure `hcor::CONFIG` loads, and that it describes a game that can be played.
+fn game_config_valid() -> Result<(), String> {
+    use hcor::CONFIG;
+
+    // CONFIG panics when it can't be loaded, and will continue to each time it's accessed.
+    let item_count = std::panic::catch_unwind(|| CONFIG.possession_archetypes.len())
+        .map_err(|_| "hcor CONFIG couldn't be loaded, see logs for details".to_string())?;
+
+    if item_count == 0 {
+        return Err("hcor CONFIG has no items".to_string());
+    }
+
+    let bad_seeds: Vec<String> = CONFIG
+        .seeds()
+        .filter(|(seed, _)| CONFIG.find_plant(&seed.grows_into).is_err())
+        .map(|(seed, arch)| format!("{} grows into unknown {}", arch.name, seed.grows_into))
+        .collect();
+    if !bad_seeds.is_empty() {
+        return Err(format!(
+            "hcor CONFIG has bad seeds: {}",
+            bad_seeds.join(", ")
+        ));
+    }
+
+    Ok(())
+}
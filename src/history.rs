@@ -0,0 +1,200 @@
+//! Prints what's happened to a steader's hackstead, from their audit logs,
+//! or what their hackstead looked like at some point in the past.
+use backend::{
+    audit::{self, Cause, Event, Point, Record},
+    Config,
+};
+use chrono::{DateTime, Utc};
+use hcor::SteaderId;
+use std::path::PathBuf;
+
+const USAGE: &str = "\
+usage: history [flags] <steader id>
+
+flags:
+    --data-dir <path>   where audit logs are stored [default: as the server is configured]
+    --since <time>      only show events at or after this RFC 3339 timestamp
+    --until <time>      only show events before this RFC 3339 timestamp
+    --kind <kind>       only show Edit, Refused, Timer, Throw or Admin events; may be repeated
+    --json              print each record as a line of JSON, rather than a summary
+    --version <n>       instead, print their hackstead as of this local_version
+    --at <time>         instead, print their hackstead as of this RFC 3339 timestamp
+    -h, --help          print this message";
+
+#[derive(Default)]
+struct Query {
+    data_dir: Option<PathBuf>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    kinds: Vec<String>,
+    json: bool,
+    point: Option<Point>,
+    steader_id: Option<SteaderId>,
+}
+
+impl Query {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut q = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("the {} flag requires a value", arg))
+            };
+            let time = |s: String| {
+                DateTime::parse_from_rfc3339(&s)
+                    .map(|t| t.with_timezone(&Utc))
+                    .map_err(|e| format!("'{}' isn't an RFC 3339 timestamp: {}", s, e))
+            };
+
+            match arg.as_str() {
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                "--data-dir" => q.data_dir = Some(PathBuf::from(value()?)),
+                "--since" => q.since = Some(time(value()?)?),
+                "--until" => q.until = Some(time(value()?)?),
+                "--kind" => q.kinds.push(value()?),
+                "--json" => q.json = true,
+                "--version" => {
+                    let v = value()?;
+                    q.point = Some(Point::Version(
+                        v.parse().map_err(|_| format!("'{}' isn't a version", v))?,
+                    ))
+                }
+                "--at" => q.point = Some(Point::Time(time(value()?)?)),
+                flag if flag.starts_with('-') => {
+                    return Err(format!("unknown flag {}, try --help", flag))
+                }
+                _ if q.steader_id.is_some() => return Err(USAGE.to_string()),
+                _ => {
+                    q.steader_id = Some(
+                        serde_json::from_value(serde_json::Value::String(arg.clone()))
+                            .map_err(|e| format!("'{}' isn't a steader id: {}", arg, e))?,
+                    )
+                }
+            }
+        }
+
+        Ok(q)
+    }
+
+    fn wants(&self, r: &Record) -> bool {
+        self.since.map_or(true, |t| r.at >= t)
+            && self.until.map_or(true, |t| r.at < t)
+            && (self.kinds.is_empty() || self.kinds.iter().any(|k| k == r.event.kind()))
+    }
+}
+
+fn describe_cause(cause: &Cause) -> String {
+    match cause {
+        Cause::Ask { ask_id, ask } => format!("ask #{} {:?}", ask_id, ask),
+        Cause::Batch { ask_id, asks } => format!("batch #{} {:?}", ask_id, asks),
+        Cause::Throw {
+            sender_id,
+            receiver_id,
+        } => format!("throw from {} to {}", sender_id, receiver_id),
+        Cause::Admin { action } => format!("admin {}", action),
+        Cause::Unspecified => "unspecified".to_string(),
+    }
+}
+
+fn describe(event: &Event) -> String {
+    match event {
+        Event::Edit {
+            cause,
+            diff,
+            local_version,
+        } => format!("v{} by {}: {}", local_version, describe_cause(cause), diff),
+        Event::Snapshot { hackstead } => format!("v{}", hackstead.local_version),
+        Event::Refused { cause, error } => format!("{} refused: {}", describe_cause(cause), error),
+        Event::Timer {
+            timer,
+            outcome,
+            local_version,
+            ..
+        } => format!(
+            "at v{}, {:?} timer on tile {:?}: {:?}",
+            local_version, timer.kind, timer.tile_id, outcome
+        ),
+        Event::Throw {
+            sender_id,
+            receiver_id,
+            item_ids,
+            outcome,
+        } => format!(
+            "{} items from {} to {}: {:?} ({:?})",
+            item_ids.len(),
+            sender_id,
+            receiver_id,
+            outcome,
+            item_ids
+        ),
+        Event::Admin { action, detail } => format!("{}: {}", action, detail),
+    }
+}
+
+fn main() {
+    let query = match Query::parse(std::env::args().skip(1)) {
+        Ok(q) => q,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let steader_id = match query.steader_id {
+        Some(id) => id,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let data_dir = match &query.data_dir {
+        Some(d) => d.clone(),
+        None => match Config::from_sources(std::iter::empty(), |var| std::env::var(var).ok()) {
+            Ok(c) => c.data_dir,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        },
+    };
+
+    if let Some(point) = query.point {
+        match audit::reconstruct(&data_dir, steader_id, point)
+            .map_err(|e| e.to_string())
+            .and_then(|hs| serde_json::to_string_pretty(&hs).map_err(|e| e.to_string()))
+        {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let records = match audit::history(&data_dir, steader_id) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    for r in records.iter().filter(|r| query.wants(r)) {
+        if query.json {
+            match serde_json::to_string(r) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("couldn't serialize record: {}", e),
+            }
+        } else {
+            println!(
+                "{} {:<7} {}",
+                r.at.to_rfc3339(),
+                r.event.kind(),
+                describe(&r.event)
+            );
+        }
+    }
+}
@@ -97,6 +97,16 @@
 //! of the Rust ecosystem. [`EditNote`s](hcor::wormhole::EditNote) in particular are a
 //! great deal more efficient when encoded as [Bincode](https://github.com/servo/bincode).
 //!
+//! Clients that want something compact but can't count on a Bincode implementation can use
+//! `"MessagePack"` or `"CBOR"` instead, both self-describing binary formats with implementations
+//! in most languages. These behave just like the Bincode orifice, except that every binary
+//! message is [MessagePack](https://msgpack.org) (with structs encoded as maps, so that their
+//! fields are named) or [CBOR](https://cbor.io) respectively. The one exception is edits:
+//! [`EditNote`](hcor::wormhole::EditNote) has no variant for either, so clients speaking version 4
+//! or later are sent an [`Outbound::Edit`](crate::Outbound::Edit) instead, holding the diff itself in
+//! that format rather than as a byte string. Older clients are sent the bytes of the diff in that
+//! format in an [`EditNote`](hcor::wormhole::EditNote)'s `Bincode` variant, as they always were.
+//!
 //! Input into the websockets connection (henceforth referred to as "the wormhole")
 //! should take the form of an [`AskMessage`](hcor::wormhole::AskMessage).
 //! Messages coming to the connected client through the wormhole will take the form of
@@ -141,6 +151,21 @@
 //! # }
 //! ```
 //!
+//! ### Speaking the same language
+//! Clients should also set a `WormholeVersion` header to the version of the wormhole protocol
+//! they speak, as a JSON number; the newest is [`version::CURRENT`](crate::version::CURRENT).
+//! The server speaks that version back, and says so in the `WormholeVersion` header of its
+//! response. Versions it no longer knows how to speak are refused with a Bad Request explaining
+//! which ones it does. Clients that leave the header out are assumed to predate it, and are
+//! spoken to in version 1, which never sends [`ProtocolNote`s](crate::ProtocolNote).
+//!
+//! From version 3 on, everything sent through the wormhole is wrapped: what clients send in an
+//! [`Inbound`](crate::Inbound), like `{ "Ask": <AskMessage> }`, and what they're sent in an
+//! [`Outbound`](crate::Outbound), like `{ "Note": <Note> }`. The examples below show what's
+//! inside the wrapper, which is all that's sent to and from older clients. From version 4 on,
+//! clients on the MessagePack and CBOR orifices are sent edits as an
+//! [`Outbound::Edit`](crate::Outbound::Edit).
+//!
 //! ### Asking for Trouble
 //! When requesting that the server perform an action through the wormhole, clients may
 //! submit an arbitrary `ask_id` alongside an [`Ask`](hcor::Ask).
@@ -155,6 +180,15 @@
 //! allows clients to track the resolution of individual [`Ask`s](hcor::Ask) and make sure each of
 //! them is addressed by the server.
 //!
+//! The one other thing the server uses `ask_id`s for is noticing when the same message is sent
+//! twice, i.e. by a client that isn't sure whether it got through before its connection dropped.
+//! For `ask_retention` seconds (five minutes, by default), the same `ask_id` sent with the same
+//! [`Ask`](hcor::Ask) by the same user, through any wormhole or relay, is answered again with
+//! whatever came of it the first time, rather than being handled again. If it's still being
+//! handled, i.e. a throw, the resend is ignored, and the answer arrives once it's ready. So, so
+//! long as clients don't reuse an `ask_id` for the same [`Ask`](hcor::Ask) within that window,
+//! they may safely resend anything they didn't hear back about.
+//!
 //! ```
 //! # use uuid::Uuid;
 //! # use serde_json::json;
@@ -264,6 +298,40 @@
 //! # }
 //! ```
 //!
+//! #### All or nothing
+//! Clients speaking version 3 or later can send several [`Ask`s](hcor::Ask) at once, as a
+//! [`BatchMessage`](crate::BatchMessage). They're handled in order, and if any of them fail, the
+//! rest are skipped and none of their changes are kept; otherwise, they're all described by a
+//! single [`EditNote`](hcor::wormhole::EditNote). Either way, the response is a single
+//! [`Batched`](crate::Outbound::Batched) holding an [`AskedNote`](hcor::wormhole::AskedNote) for
+//! each [`Ask`](hcor::Ask) up to and including the one that failed, rather than a `Note::Asked`
+//! for each. Throws can't be part of a batch.
+//!
+//! ```
+//! # use serde_json::json;
+//! # use backend::{BatchMessage, Inbound};
+//! # use hcor::Ask;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let inbound: Inbound = serde_json::from_value(json!({
+//!     "Batch": {
+//!         "ask_id": 1337,
+//!         "asks": [
+//!             { "KnowledgeSnort": { "xp": 10 } },
+//!             { "KnowledgeSnort": { "xp": 5 } }
+//!         ]
+//!     }
+//! }))?;
+//! match inbound {
+//!     Inbound::Batch(BatchMessage { ask_id, asks }) => {
+//!         assert_eq!(ask_id, 1337);
+//!         assert_eq!(asks, vec![Ask::KnowledgeSnort { xp: 10 }, Ask::KnowledgeSnort { xp: 5 }]);
+//!     }
+//!     other => panic!("expected a batch, got {:?}", other),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
 //! #### [`Ask`](hcor::Ask); don't [`Beg`](hcor::wormhole::Beg)
 //! Having read (and hopefully understood) this description of the behavior of this
 //! [`Ask`](hcor::Ask) and [`AskedNote`](hcor::wormhole::AskedNote) system, you may find yourself
@@ -284,6 +352,11 @@
 //! when i.e. they are sent items from other users, their yields or crafts finish, effects wear
 //! off, etc. One is spared only the effort of sending messages into the wormhole and pairing them
 //! with their responses.
+//!
+//! That said, one needn't have a wormhole open to beg. If the user doesn't have one, the server
+//! starts a headless session for them, which handles the [`Ask`](hcor::Ask) and keeps their
+//! timers ticking until it's gone `headless_idle` seconds without being asked anything else.
+//! Should the user open a wormhole in the meantime, it takes over from the headless session.
 //! ```
 //! # use serde_json::json;
 //! # use hcor::{Hackstead, wormhole::{Beg, Ask, AskedNote::self}};
@@ -336,6 +409,26 @@
 //! items rubbed onto a plant ([`RubEffectFinish`](hcor::wormhole::RudeNote::RubEffectFinish)) are
 //! all examples of events that users cannot request the immediate completion of, and therefore
 //! have [`RudeNote`s](hcor::wormhole::RudeNote).
+//!
+//! Each yield and craft also earns the plant some xp (`yield_xp` and `craft_xp`), given in the
+//! `xp` of its note. If the server has levels configured for it (`plant_levels`, none by
+//! default), once a plant has earned enough to reach its next level, it yields faster from then
+//! on, which clients are told of with a
+//! [`PlantLevelUp`](hcor::wormhole::RudeNote::PlantLevelUp).
+//!
+//! The effects rubbed onto a plant may change how quickly it yields and crafts and how much xp it
+//! earns (`rub_modifiers`, given for each effect archetype), for as long as they last. Since yields
+//! don't produce any items yet, there's no modifier for how many they produce.
+//! The effects on a plant combine as `modifier_stacking` says they should. A plant may only
+//! have so many effects at once (`effect_caps`), and rubbing on an item whose effects a plant
+//! already has may add more of them, start them over, or be refused (`rub_stacking`). Refused
+//! rubs leave the item in the inventory.
+//!
+//! Effects needn't be waited out, either; [`PlantAsk::Cleanse`](hcor::wormhole::PlantAsk::Cleanse)
+//! takes one off of its plant right away, using up one of the `cleansers`, if any are configured.
+//! The effect's timer is stopped, and the client is sent the same
+//! [`RubEffectFinish`](hcor::wormhole::RudeNote::RubEffectFinish) it would have gotten when it
+//! wore off.
 //! ```
 //! # use uuid::Uuid;
 //! # use serde_json::json;
@@ -366,6 +459,81 @@
 //! # Ok(())
 //! # }
 //! ```
+//! ### Hanging up
+//! Some messages concern the wormhole itself rather than anybody's hackstead. These
+//! [`ProtocolNote`s](crate::ProtocolNote) are always sent as websockets text messages containing
+//! JSON like `{ "Protocol": "Shutdown" }`, whichever `WormholeOrifice` you chose, so long as
+//! you speak version 2 or later of the protocol.
+//!
+//! If you send something that can't be read as an `Inbound` (or `AskMessage`, before version 3)
+//! through your orifice, you'll get a [`Malformed`](crate::ProtocolNote::Malformed) note back
+//! instead of an `Asked` note. Too many of these and the connection is closed with the
+//! "invalid payload" (1007) close code.
+//!
+//! Servers may limit how often each kind of [`Ask`](hcor::Ask) is made, both through any one
+//! wormhole (`conn_rate_limits`) and by any one user, however they're asking (`rate_limits`);
+//! out of the box, nothing is limited. Messages that would go over either limit aren't handled
+//! at all; instead, you'll get a [`RateLimited`](crate::ProtocolNote::RateLimited) note, saying
+//! which kind of [`Ask`](hcor::Ask) there have been too many of, and how many seconds to wait
+//! before sending the message again. Clients speaking version 1 get an `Asked` note with an
+//! error instead. Resending a message that was already handled costs nothing, since it's
+//! answered from memory.
+//!
+//! Some Asks, i.e. hatching an item or rubbing one on a plant, may also have cooldowns
+//! (`cooldowns`), which are part of the game rather than the protocol: an Ask made while its
+//! cooldown hasn't worn off fails like any other, changing nothing, with an error in its `Asked`
+//! note saying how many seconds are left.
+//!
+//! When the server is going down, it saves every hackstead, sends each client a
+//! [`Shutdown`](crate::ProtocolNote::Shutdown) note, and then closes the connection with the
+//! "going away" (1001) close code. Clients should wait a moment and then reconnect.
+//!
+//! ### Without a websocket
+//! Clients that can't keep a websocket open, i.e. because of a proxy, can go through a relay
+//! instead. A relay is opened with a `POST` to `/api/relay`, bearing the same headers one would
+//! use to open a wormhole; the response is JSON like `{ "relay_id": "...", "version": 4 }`,
+//! where `version` is the one negotiated for the relay, just as for a wormhole. From then on,
+//! the relay behaves just like a wormhole, only over plain HTTP:
+//!
+//! - `POST /api/relay/{relay_id}` with an [`Inbound`](crate::Inbound) as the body, encoded for
+//!   your orifice, to ask for something.
+//! - `GET /api/relay/{relay_id}/events` to receive messages as Server-Sent Events. Text messages
+//!   arrive as `text` events, binary messages as `binary` events holding base64, and when the
+//!   relay is closed, a `close` event holds the `code` and `description` of the close frame.
+//! - or, `GET /api/relay/{relay_id}` to long-poll for messages, which responds with a JSON list
+//!   of objects like `{ "Text": "..." }`, `{ "Binary": "<base64>" }` or
+//!   `{ "Close": { "code": 1000, "description": null } }` once there's anything to send,
+//!   or an empty list after a while without.
+//! - `DELETE /api/relay/{relay_id}` to hang up.
+//!
+//! If nobody's listened for events, polled or asked anything in `client_timeout`,
+//! the relay is closed, just like a wormhole whose pings go unanswered.
+//!
+//! ### Just asking
+//! Clients that only ever want to ask for one thing at a time, like bots, can skip the wormhole
+//! entirely. Every [`Ask`](hcor::Ask) has a route of its own that can be sent a `POST` request,
+//! whose body should be JSON holding the `steader_id` to act for alongside the fields of the Ask:
+//!
+//! | Route                   | Ask                                                          |
+//! |-------------------------|--------------------------------------------------------------|
+//! | `/api/knowledge/snort`  | [`KnowledgeSnort`](hcor::Ask::KnowledgeSnort)                |
+//! | `/api/tile/summon`      | [`TileSummon`](hcor::Ask::TileSummon)                        |
+//! | `/api/plant/summon`     | [`PlantAsk::Summon`](hcor::wormhole::PlantAsk::Summon)       |
+//! | `/api/plant/slaughter`  | [`PlantAsk::Slaughter`](hcor::wormhole::PlantAsk::Slaughter) |
+//! | `/api/plant/craft`      | [`PlantAsk::Craft`](hcor::wormhole::PlantAsk::Craft)         |
+//! | `/api/plant/rub`        | [`PlantAsk::Rub`](hcor::wormhole::PlantAsk::Rub)             |
+//! | `/api/plant/cleanse`    | [`PlantAsk::Cleanse`](hcor::wormhole::PlantAsk::Cleanse)     |
+//! | `/api/item/spawn`       | [`ItemAsk::Spawn`](hcor::wormhole::ItemAsk::Spawn)           |
+//! | `/api/item/throw`       | [`ItemAsk::Throw`](hcor::wormhole::ItemAsk::Throw)           |
+//! | `/api/item/hatch`       | [`ItemAsk::Hatch`](hcor::wormhole::ItemAsk::Hatch)           |
+//!
+//! i.e. `{ "steader_id": "...", "tile_id": "...", "seed_item_id": "..." }` for `/api/plant/summon`.
+//! The steader's Session handles the Ask, as though it'd come through the wormhole; if they
+//! don't have a wormhole open, a headless Session is started for them, just as for `/api/beg`.
+//! Either way, the response is the [`AskedNote`](hcor::wormhole::AskedNote), as JSON. These routes, and
+//! `/api/beg`, count against the user's `rate_limits` too; going over them is answered with
+//! Too Many Requests (429), with a `Retry-After` header saying how many seconds to wait.
+//!
 //! ## Keeping your steads Farm Fresh™
 //! It is assumed that client implementations keep a copy of a user's
 //! [`Hackstead`](hcor::Hackstead) in memory locally, to reference as application state
@@ -379,9 +547,10 @@
 //! your data.
 //!
 //! [`EditNote`s](hcor::wormhole::EditNote) themselves come in two different varieties, one for
-//! each possible `WormholeOrifice`. Clients should expect to receive the variety that matches the
-//! one they specified when establishing their wormhole connection, and any variation from that
-//! should be regarded as a bug.
+//! the `Json` and one for the `Bincode` `WormholeOrifice`; from version 4 on, the `MessagePack`
+//! and `CBOR` orifices are sent an [`Outbound::Edit`](crate::Outbound::Edit) instead. Clients
+//! should expect to receive the variety that matches the one they specified when establishing
+//! their wormhole connection, and any variation from that should be regarded as a bug.
 //!
 //! It is not strictly necessary, however, that clients apply the list of atomic changes the server
 //! supplies to their local data; they can simply ignore the data the server sends inside of the
@@ -450,6 +619,16 @@ use actix_web::{error::ResponseError, HttpResponse};
 use log::*;
 use std::fmt;
 
+#[cfg(any(feature = "csv_migration", feature = "webserver"))]
+pub mod config;
+#[cfg(any(feature = "csv_migration", feature = "webserver"))]
+pub use config::Config;
+
+#[cfg(feature = "webserver")]
+pub mod admin;
+#[cfg(feature = "webserver")]
+pub mod audit;
+
 #[cfg(any(feature = "csv_migration", feature = "webserver"))]
 mod hackstead;
 #[cfg(feature = "csv_migration")]
@@ -457,32 +636,104 @@ pub use hackstead::fs_put_stead;
 #[cfg(feature = "webserver")]
 pub use hackstead::{hackstead_slaughter, hackstead_spy, hackstead_summon};
 
+#[cfg(feature = "webserver")]
+mod health;
+#[cfg(feature = "webserver")]
+pub use health::{health, ready};
+
+#[cfg(feature = "webserver")]
+mod metrics;
+#[cfg(feature = "webserver")]
+pub use metrics::serve_metrics;
+
+#[cfg(feature = "webserver")]
+mod rest;
+
+#[cfg(feature = "webserver")]
+pub mod world;
+#[cfg(feature = "webserver")]
+pub use world::World;
+
 #[cfg(feature = "webserver")]
 mod wormhole;
 #[cfg(feature = "webserver")]
-pub use wormhole::{establish_wormhole, Server as WormholeServer};
+pub use wormhole::{
+    establish_wormhole,
+    protocol::{BatchMessage, FramedEdit, Inbound, Outbound, ProtocolNote},
+    server::{Serving, Shutdown},
+    version, Relays, Server as WormholeServer, Version as WormholeVersion,
+};
+
+#[cfg(all(test, feature = "webserver"))]
+mod harness;
+
+#[cfg(all(feature = "fuzzing", feature = "webserver"))]
+#[doc(hidden)]
+pub mod fuzzing;
+
+/// Registers every route the server serves. The `App` being configured must already have the
+/// `web::Data<Config>`, `web::Data<World>` and `web::Data<Addr<WormholeServer>>` they rely on.
+#[cfg(feature = "webserver")]
+pub fn routes(cfg: &mut actix_web::web::ServiceConfig) {
+    use actix_web::web;
+
+    cfg
+        // probes
+        .service(health)
+        .service(ready)
+        // metrics
+        .service(serve_metrics)
+        .service(
+            web::scope("/api")
+                // wormhole
+                .service(web::resource("/wormhole").to(establish_wormhole))
+                .service(wormhole::relay::open)
+                .service(wormhole::relay::ask)
+                .service(wormhole::relay::poll)
+                .service(wormhole::relay::events)
+                .service(wormhole::relay::close)
+                // hackstead
+                .service(hackstead_summon)
+                .service(hackstead_spy)
+                .service(hackstead_slaughter)
+                // beg
+                .service(beg)
+                // asks, without a wormhole
+                .service(rest::knowledge_snort)
+                .service(rest::tile_summon)
+                .service(rest::plant_summon)
+                .service(rest::plant_slaughter)
+                .service(rest::plant_craft)
+                .service(rest::plant_rub)
+                .service(rest::plant_cleanse)
+                .service(rest::item_spawn)
+                .service(rest::item_throw)
+                .service(rest::item_hatch),
+        )
+        .service(
+            web::scope("/admin")
+                .service(admin::sessions)
+                .service(admin::stead)
+                .service(admin::persist)
+                .service(admin::kick)
+                .service(admin::broadcast)
+                .service(admin::grant)
+                .service(admin::reconstruct)
+                .service(admin::rollback),
+        );
+}
 
 #[cfg(feature = "webserver")]
 #[actix_web::post("/beg")]
 pub async fn beg(
     beg: actix_web::web::Json<hcor::wormhole::Beg>,
     srv: actix_web::web::Data<actix::Addr<wormhole::Server>>,
+    world: actix_web::web::Data<World>,
 ) -> Result<actix_web::HttpResponse, ServiceError> {
     debug!("servicing beg request");
     let hcor::wormhole::Beg { ask, steader_id } = beg.clone();
 
-    Ok(HttpResponse::Ok().json(
-        srv.send(wormhole::server::GetSession::new(&steader_id))
-            .await?
-            .ok_or_else(|| {
-                ServiceError::BadRequest(format!(
-                    "{} has no active wormhole connection!",
-                    steader_id
-                ))
-            })?
-            .send(wormhole::session::DoAsk(ask))
-            .await??,
-    ))
+    Ok(HttpResponse::Ok().json(rest::ask_as(&srv, world, steader_id, ask).await?))
 }
 
 #[derive(Debug)]
@@ -496,6 +747,13 @@ pub enum ServiceError {
     Unauthorized,
     /// We don't know anything about what you requested.
     NoData,
+    /// We can't take on any more work right now.
+    Unavailable(String),
+    /// You've been asking for too much, too quickly; wait `retry_after` and try again.
+    TooManyRequests {
+        reason: String,
+        retry_after: std::time::Duration,
+    },
 }
 impl ServiceError {
     /// A shortcut for making a `ServiceError::BadRequest`.
@@ -520,6 +778,8 @@ impl fmt::Display for ServiceError {
             BadRequest(s) => write!(f, "Bad Request: {}", s),
             Unauthorized => write!(f, "Unauthorized"),
             NoData => write!(f, "No data found"),
+            Unavailable(s) => write!(f, "Service Unavailable: {}", s),
+            TooManyRequests { reason, .. } => write!(f, "Too Many Requests: {}", reason),
         }
     }
 }
@@ -536,6 +796,14 @@ impl ResponseError for ServiceError {
             ServiceError::BadRequest(s) => HttpResponse::BadRequest().body(s),
             ServiceError::Unauthorized => HttpResponse::Unauthorized().body("Unauthorized"),
             ServiceError::NoData => HttpResponse::NotFound().body("Data not found"),
+            ServiceError::Unavailable(s) => HttpResponse::ServiceUnavailable().body(s),
+            ServiceError::TooManyRequests {
+                reason,
+                retry_after,
+            } => HttpResponse::TooManyRequests()
+                // in whole seconds, rounded up so that retrying then won't be too soon
+                .header("Retry-After", retry_after.as_secs_f64().ceil().to_string())
+                .body(reason),
         }
     }
 }
@@ -1,37 +1,77 @@
 use actix::Actor;
 use actix_web::{web, App, HttpServer};
+use backend::{config, Config};
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     pretty_env_logger::init();
 
-    let wormhole = backend::WormholeServer::new().start();
+    let config = match Config::load().and_then(|c| c.create_data_dirs().map(|()| c)) {
+        Ok(c) => web::Data::new(c),
+        Err(config::Error::Help) => {
+            println!("{}", config::USAGE);
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    log::info!("starting with {:#?}", *config);
+
+    let wormhole = backend::WormholeServer::new(config.clone()).start();
+
+    let world = web::Data::new(backend::World::real());
+    let relays = web::Data::new(backend::Relays::default());
+
+    let (app_config, app_wormhole) = (config.clone(), wormhole.clone());
+    let mut server = HttpServer::new(move || {
+        App::new()
+            .register_data(app_config.clone())
+            .register_data(world.clone())
+            .register_data(relays.clone())
+            .data(app_wormhole.clone())
+            .configure(backend::routes)
+    });
+
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    for addr in &config.bind {
+        server = server.bind(addr)?;
+    }
+
+    // actix would stop the HTTP server on its own when signalled, but sessions must be
+    // allowed to save their hacksteads and say goodbye first, so we handle signals ourselves.
+    let server = server.disable_signals().run();
+    wormhole.do_send(backend::Serving(server.clone()));
+    actix_rt::spawn(async move {
+        terminated().await;
+        log::warn!("received signal, shutting down");
+        if let Err(e) = wormhole.send(backend::Shutdown).await {
+            log::error!("couldn't shut down gracefully: {}", e);
+            std::process::exit(1);
+        }
+    });
 
-    // create fs dirs where we dump the data, if they don't already exist
-    let mkdir = |name| {
-        std::fs::create_dir(name).unwrap_or_else(|e| match e.kind() {
-            std::io::ErrorKind::AlreadyExists => {}
-            _ => panic!("couldn't make {} folder: {}", name, e),
-        })
+    server.await
+}
+
+/// Resolves when the process is asked to terminate.
+#[cfg(unix)]
+async fn terminated() {
+    use actix_rt::signal::{
+        ctrl_c,
+        unix::{signal, SignalKind},
     };
-    mkdir("stead");
-    mkdir("slack");
-
-    HttpServer::new(move || {
-        App::new().service(
-            web::scope("/api")
-                .data(wormhole.clone())
-                // wormhole
-                .service(web::resource("/wormhole").to(backend::establish_wormhole))
-                // hackstead
-                .service(backend::hackstead_summon)
-                .service(backend::hackstead_spy)
-                .service(backend::hackstead_slaughter)
-                // beg
-                .service(backend::beg),
-        )
-    })
-    .bind("127.0.0.1:8000")?
-    .run()
-    .await
+    use futures::future::select;
+
+    let mut term = signal(SignalKind::terminate()).expect("couldn't listen for SIGTERM");
+    select(Box::pin(ctrl_c()), Box::pin(term.recv())).await;
+}
+
+/// Resolves when the process is asked to terminate.
+#[cfg(not(unix))]
+async fn terminated() {
+    drop(actix_rt::signal::ctrl_c().await);
 }
@@ -0,0 +1,154 @@
+//! Prometheus metrics about the wormhole, the asks that come through it, and the storage behind
+//! it, all served in the Prometheus text format from the `/metrics` route.
+use actix_web::{get, HttpResponse};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+use std::time::Instant;
+
+lazy_static! {
+    /// How many users have a Session open right now.
+    pub static ref SESSIONS: IntGauge = register_int_gauge!(
+        "hackagotchi_sessions",
+        "Number of sessions connected through the wormhole"
+    )
+    .unwrap();
+
+    /// Asks handled, by kind (i.e. "Item.Hatch") and outcome ("ok" or "err").
+    pub static ref ASKS: IntCounterVec = register_int_counter_vec!(
+        "hackagotchi_asks_total",
+        "Number of asks handled, by kind and outcome",
+        &["kind", "outcome"]
+    )
+    .unwrap();
+
+    /// How long it took to handle asks, by kind.
+    pub static ref ASK_LATENCY: HistogramVec = register_histogram_vec!(
+        "hackagotchi_ask_duration_seconds",
+        "Time taken to handle an ask, by kind",
+        &["kind"]
+    )
+    .unwrap();
+
+    /// Timers that the tickers of every Session are waiting on.
+    pub static ref TIMERS_OUTSTANDING: IntGauge = register_int_gauge!(
+        "hackagotchi_timers_outstanding",
+        "Number of timers currently ticking in all sessions"
+    )
+    .unwrap();
+
+    /// Timers that have gone off, by the kind of timer and outcome.
+    pub static ref TIMER_COMPLETIONS: IntCounterVec = register_int_counter_vec!(
+        "hackagotchi_timer_completions_total",
+        "Number of timers that have finished, by kind and outcome",
+        &["kind", "outcome"]
+    )
+    .unwrap();
+
+    /// Plants that have leveled up, by the level they reached.
+    pub static ref PLANT_LEVEL_UPS: IntCounterVec = register_int_counter_vec!(
+        "hackagotchi_plant_level_ups_total",
+        "Number of times plants have leveled up, by the level reached",
+        &["level"]
+    )
+    .unwrap();
+
+    /// Attempts to throw items between users, by outcome.
+    pub static ref THROWS: IntCounterVec = register_int_counter_vec!(
+        "hackagotchi_throws_total",
+        "Number of attempts to throw items to another user, by outcome",
+        &["outcome"]
+    )
+    .unwrap();
+
+    /// Wormholes opened, by the protocol version they were opened with,
+    /// or "unsupported" for those turned away.
+    pub static ref HANDSHAKES: IntCounterVec = register_int_counter_vec!(
+        "hackagotchi_wormhole_handshakes_total",
+        "Number of wormhole connections attempted, by protocol version",
+        &["version"]
+    )
+    .unwrap();
+
+    /// Messages from clients that couldn't be read, by the orifice they came through.
+    pub static ref MALFORMED: IntCounterVec = register_int_counter_vec!(
+        "hackagotchi_malformed_messages_total",
+        "Number of messages from clients that couldn't be read, by orifice",
+        &["orifice"]
+    )
+    .unwrap();
+
+    /// Messages that were sent again and answered from memory, by whether the answer was ready
+    /// ("answered") or still being worked out ("pending").
+    pub static ref REPLAYED: IntCounterVec = register_int_counter_vec!(
+        "hackagotchi_replayed_asks_total",
+        "Number of asks sent again with an ask_id already being or been handled, by outcome",
+        &["outcome"]
+    )
+    .unwrap();
+
+    /// Messages refused because too many Asks of some kind had been made, by that kind and
+    /// whether it was the steader's or the connection's quota that ran out.
+    pub static ref RATE_LIMITED: IntCounterVec = register_int_counter_vec!(
+        "hackagotchi_rate_limited_asks_total",
+        "Number of messages refused for exceeding a rate limit, by ask and scope",
+        &["ask", "scope"]
+    )
+    .unwrap();
+
+    /// How long it took to read, write or remove hacksteads on disk.
+    pub static ref STORAGE_LATENCY: HistogramVec = register_histogram_vec!(
+        "hackagotchi_storage_duration_seconds",
+        "Time taken to access hacksteads on disk, by operation",
+        &["op"]
+    )
+    .unwrap();
+}
+
+/// Converts a count into something a gauge can be set to.
+pub fn count(n: usize) -> i64 {
+    use std::convert::TryFrom;
+    i64::try_from(n).unwrap_or(i64::MAX)
+}
+
+/// "ok" or "err", for use as the value of an `outcome` label.
+pub fn outcome<T, E>(r: &Result<T, E>) -> &'static str {
+    if r.is_ok() {
+        "ok"
+    } else {
+        "err"
+    }
+}
+
+/// Records an ask of the given kind that was received at `started`.
+pub fn observe_ask(kind: &str, outcome: &str, started: Instant) {
+    ASKS.with_label_values(&[kind, outcome]).inc();
+    ASK_LATENCY
+        .with_label_values(&[kind])
+        .observe(started.elapsed().as_secs_f64());
+}
+
+/// Runs `f`, recording how long it took as a storage operation of the given kind.
+pub fn time_storage<T>(op: &str, f: impl FnOnce() -> T) -> T {
+    let _timer = STORAGE_LATENCY.with_label_values(&[op]).start_timer();
+    f()
+}
+
+#[get("/metrics")]
+/// Every metric we keep, in the Prometheus text format.
+pub async fn serve_metrics() -> HttpResponse {
+    let encoder = TextEncoder::new();
+    let mut buf = vec![];
+
+    match encoder.encode(&prometheus::gather(), &mut buf) {
+        Ok(()) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(buf),
+        Err(e) => {
+            log::error!("couldn't encode metrics: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Routes mirroring every [`Ask`](hcor::Ask), for clients that only want to make a request and
+//! get a response, without keeping a wormhole open.
+//!
+//! Each route takes JSON holding the `steader_id` to act for, alongside the fields of the Ask it
+//! mirrors; `/api/plant/summon`, for example, takes `{ "steader_id": ..., "tile_id": ...,
+//! "seed_item_id": ... }`. The Ask is handled by the steader's Session, just as `/beg` has it
+//! handled, starting a headless one for them if they don't have one, and the
+//! [`AskedNote`](hcor::wormhole::AskedNote) is returned as JSON.
+use crate::{
+    wormhole::{
+        server::{Awaken, Server},
+        session::DoAsk,
+    },
+    ServiceError, World,
+};
+use actix::Addr;
+use actix_web::{post, web, HttpResponse};
+use hcor::{wormhole::AskedNote, Ask, SteaderId};
+use log::*;
+use serde_json::{Map, Value};
+
+type Srv = web::Data<Addr<Server>>;
+
+/// Has an Ask handled for a steader by their Session, starting a headless one if they don't
+/// have one, so that it's handled in turn with anything else done to their hackstead.
+pub async fn ask_as(
+    srv: &Addr<Server>,
+    world: web::Data<World>,
+    steader_id: SteaderId,
+    ask: Ask,
+) -> Result<AskedNote, ServiceError> {
+    Ok(srv
+        .send(Awaken(steader_id, world))
+        .await??
+        .send(DoAsk(ask))
+        .await??)
+}
+
+/// Reads the `steader_id` out of a request's body, and the rest of it as the fields of the Ask
+/// found by following `variants`, i.e. `["Plant", "Summon"]`, then has that Ask handled.
+async fn mirror(
+    variants: &[&str],
+    body: web::Json<Value>,
+    srv: Srv,
+    world: web::Data<World>,
+) -> Result<HttpResponse, ServiceError> {
+    let name = variants.join(".");
+    debug!("servicing {} request", name);
+
+    let mut fields = body.into_inner();
+    let steader_id = fields
+        .as_object_mut()
+        .and_then(|o| o.remove("steader_id"))
+        .ok_or_else(|| ServiceError::bad_request("please supply a steader_id"))?;
+    let steader_id: SteaderId = serde_json::from_value(steader_id)
+        .map_err(|e| ServiceError::bad_request(&format!("couldn't read steader_id: {}", e)))?;
+
+    let ask = variants.iter().rev().fold(fields, |inner, variant| {
+        let mut outer = Map::new();
+        outer.insert(variant.to_string(), inner);
+        Value::Object(outer)
+    });
+    let ask: Ask = serde_json::from_value(ask)
+        .map_err(|e| ServiceError::bad_request(&format!("couldn't read {} ask: {}", name, e)))?;
+
+    Ok(HttpResponse::Ok().json(ask_as(&srv, world, steader_id, ask).await?))
+}
+
+#[post("/knowledge/snort")]
+/// Mirrors [`Ask::KnowledgeSnort`](hcor::Ask::KnowledgeSnort).
+pub async fn knowledge_snort(
+    body: web::Json<Value>,
+    srv: Srv,
+    world: web::Data<World>,
+) -> Result<HttpResponse, ServiceError> {
+    mirror(&["KnowledgeSnort"], body, srv, world).await
+}
+
+#[post("/tile/summon")]
+/// Mirrors [`Ask::TileSummon`](hcor::Ask::TileSummon).
+pub async fn tile_summon(
+    body: web::Json<Value>,
+    srv: Srv,
+    world: web::Data<World>,
+) -> Result<HttpResponse, ServiceError> {
+    mirror(&["TileSummon"], body, srv, world).await
+}
+
+#[post("/plant/summon")]
+/// Mirrors [`PlantAsk::Summon`](hcor::wormhole::PlantAsk::Summon).
+pub async fn plant_summon(
+    body: web::Json<Value>,
+    srv: Srv,
+    world: web::Data<World>,
+) -> Result<HttpResponse, ServiceError> {
+    mirror(&["Plant", "Summon"], body, srv, world).await
+}
+
+#[post("/plant/slaughter")]
+/// Mirrors [`PlantAsk::Slaughter`](hcor::wormhole::PlantAsk::Slaughter).
+pub async fn plant_slaughter(
+    body: web::Json<Value>,
+    srv: Srv,
+    world: web::Data<World>,
+) -> Result<HttpResponse, ServiceError> {
+    mirror(&["Plant", "Slaughter"], body, srv, world).await
+}
+
+#[post("/plant/craft")]
+/// Mirrors [`PlantAsk::Craft`](hcor::wormhole::PlantAsk::Craft).
+pub async fn plant_craft(
+    body: web::Json<Value>,
+    srv: Srv,
+    world: web::Data<World>,
+) -> Result<HttpResponse, ServiceError> {
+    mirror(&["Plant", "Craft"], body, srv, world).await
+}
+
+#[post("/plant/rub")]
+/// Mirrors [`PlantAsk::Rub`](hcor::wormhole::PlantAsk::Rub).
+pub async fn plant_rub(
+    body: web::Json<Value>,
+    srv: Srv,
+    world: web::Data<World>,
+) -> Result<HttpResponse, ServiceError> {
+    mirror(&["Plant", "Rub"], body, srv, world).await
+}
+
+#[post("/plant/cleanse")]
+/// Mirrors [`PlantAsk::Cleanse`](hcor::wormhole::PlantAsk::Cleanse).
+pub async fn plant_cleanse(
+    body: web::Json<Value>,
+    srv: Srv,
+    world: web::Data<World>,
+) -> Result<HttpResponse, ServiceError> {
+    mirror(&["Plant", "Cleanse"], body, srv, world).await
+}
+
+#[post("/item/spawn")]
+/// Mirrors [`ItemAsk::Spawn`](hcor::wormhole::ItemAsk::Spawn).
+pub async fn item_spawn(
+    body: web::Json<Value>,
+    srv: Srv,
+    world: web::Data<World>,
+) -> Result<HttpResponse, ServiceError> {
+    mirror(&["Item", "Spawn"], body, srv, world).await
+}
+
+#[post("/item/throw")]
+/// Mirrors [`ItemAsk::Throw`](hcor::wormhole::ItemAsk::Throw), with `steader_id` as the thrower.
+/// The `receiver_id` must have a Session open.
+pub async fn item_throw(
+    body: web::Json<Value>,
+    srv: Srv,
+    world: web::Data<World>,
+) -> Result<HttpResponse, ServiceError> {
+    mirror(&["Item", "Throw"], body, srv, world).await
+}
+
+#[post("/item/hatch")]
+/// Mirrors [`ItemAsk::Hatch`](hcor::wormhole::ItemAsk::Hatch).
+pub async fn item_hatch(
+    body: web::Json<Value>,
+    srv: Srv,
+    world: web::Data<World>,
+) -> Result<HttpResponse, ServiceError> {
+    mirror(&["Item", "Hatch"], body, srv, world).await
+}
+
+#[cfg(test)]
+mod test {
+    use crate::harness::Harness;
+    use hcor::{wormhole::AskedNote, IdentifiesSteader};
+    use serde_json::json;
+
+    #[actix_rt::test]
+    async fn knowledge_snort_without_a_wormhole() {
+        let h = Harness::start().await;
+        let bobstead = h.summon().await;
+
+        let note: AskedNote = awc::Client::new()
+            .post(format!("{}/api/knowledge/snort", h.url))
+            .send_json(&json!({ "steader_id": bobstead.steader_id(), "xp": 10 }))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        match note {
+            AskedNote::KnowledgeSnortResult(Ok(xp)) => assert_eq!(xp, bobstead.profile.xp + 10),
+            other => panic!("unexpected response to KnowledgeSnort: {:?}", other),
+        }
+
+        let stored = h.spy(&bobstead).await.unwrap();
+        assert_eq!(stored.profile.xp, bobstead.profile.xp + 10);
+        assert_eq!(stored.local_version, bobstead.local_version + 1);
+        h.stop().await;
+    }
+
+    #[actix_rt::test]
+    async fn concurrent_asks_are_handled_in_turn() {
+        let h = Harness::start().await;
+        let bobstead = h.summon().await;
+
+        let snort = || {
+            awc::Client::new()
+                .post(format!("{}/api/knowledge/snort", h.url))
+                .send_json(&json!({ "steader_id": bobstead.steader_id(), "xp": 10 }))
+        };
+        let (a, b) = futures::join!(snort(), snort());
+        assert!(a.unwrap().status().is_success());
+        assert!(b.unwrap().status().is_success());
+
+        // neither was handled against a hackstead the other had already changed
+        let stored = h.spy(&bobstead).await.unwrap();
+        assert_eq!(stored.profile.xp, bobstead.profile.xp + 20);
+        assert_eq!(stored.local_version, bobstead.local_version + 2);
+        h.stop().await;
+    }
+}
@@ -0,0 +1,140 @@
+//! Where sessions learn what time it is and roll their dice.
+//!
+//! The server runs in the `real` world, with the system clock and dice seeded from entropy.
+//! Tests can instead set up a `deterministic` world, with a `ManualClock` they advance
+//! themselves and dice that roll the same way each time for a given seed and steader.
+use hcor::SteaderId;
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Something that can tell the time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The time according to the operating system.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when it's told to.
+pub struct ManualClock(Mutex<Instant>);
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self(Mutex::new(Instant::now()))
+    }
+
+    /// Moves this clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// The clock and dice every Session is given.
+#[derive(Clone)]
+pub struct World {
+    pub clock: Arc<dyn Clock>,
+    /// If `Some`, every steader's dice are seeded from this and their `SteaderId`.
+    seed: Option<u64>,
+}
+
+impl World {
+    /// The system clock, and dice seeded from entropy.
+    pub fn real() -> Self {
+        Self {
+            clock: Arc::new(SystemClock),
+            seed: None,
+        }
+    }
+
+    /// The clock provided, and dice that roll the same way each time for the same seed.
+    pub fn deterministic(clock: Arc<dyn Clock>, seed: u64) -> Self {
+        Self {
+            clock,
+            seed: Some(seed),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Dice for a particular steader's Session.
+    pub fn rng(&self, steader_id: SteaderId) -> StdRng {
+        match self.seed {
+            // laid out by hand rather than hashed, so that the same seed rolls the same way
+            // whichever toolchain built us
+            Some(seed) => {
+                let mut bytes = [0; 32];
+                bytes[..8].copy_from_slice(&seed.to_le_bytes());
+                bytes[8..24].copy_from_slice(steader_id.0.as_bytes());
+                StdRng::from_seed(bytes)
+            }
+            None => StdRng::from_entropy(),
+        }
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::real()
+    }
+}
+
+/// A random v4 uuid, rolled with the dice given rather than the thread's.
+pub fn roll_uuid(rng: &mut impl rand::Rng) -> uuid::Uuid {
+    uuid::Builder::from_bytes(rng.gen())
+        .set_variant(uuid::Variant::RFC4122)
+        .set_version(uuid::Version::Random)
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{roll_uuid, ManualClock, World};
+    use hcor::SteaderId;
+    use rand::Rng;
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    fn deterministic_world() {
+        let clock = Arc::new(ManualClock::new());
+        let world = World::deterministic(clock.clone(), 1337);
+
+        let before = world.now();
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(world.now() - before, Duration::from_secs(5));
+
+        let bob = SteaderId(uuid::Uuid::new_v4());
+        let rolls = |mut rng: rand::rngs::StdRng| (rng.gen::<u64>(), roll_uuid(&mut rng));
+        assert_eq!(rolls(world.rng(bob)), rolls(world.rng(bob)));
+        assert_ne!(
+            rolls(world.rng(bob)),
+            rolls(world.rng(SteaderId(uuid::Uuid::new_v4())))
+        );
+        assert_ne!(
+            rolls(world.rng(bob)),
+            rolls(World::deterministic(clock, 1338).rng(bob))
+        );
+    }
+}
@@ -8,16 +8,43 @@ use session::Session;
 pub mod server;
 pub use server::Server;
 
+pub mod protocol;
+
+pub mod version;
+pub use version::Version;
+
+pub mod relay;
+pub use relay::Relays;
+
 /// This route facilitates establishing a connection to the Wormhole,
 /// through which clients can receive messages about their hackstead.
 pub async fn establish_wormhole(
     req: actix_web::HttpRequest,
     stream: web::Payload,
     srv: web::Data<Addr<Server>>,
+    config: web::Data<crate::Config>,
+    world: web::Data<crate::World>,
 ) -> Result<actix_web::HttpResponse, actix_web::Error> {
-    use crate::ServiceError;
     log::debug!("servicing establish_wormhole request");
 
+    let (session, version) = open_session(&req, &srv, &config, &world).await?;
+
+    // the same as ws::start, but telling the client which version we'll be speaking
+    Ok(ws::handshake(&req)?
+        .header("WormholeVersion", version.to_string())
+        .streaming(ws::WebsocketContext::create(session, stream)))
+}
+
+/// Reads the `WormholeUser`, `WormholeOrifice` and `WormholeVersion` headers off of a request
+/// and sets up a Session for that user, if the server has room for them.
+async fn open_session(
+    req: &actix_web::HttpRequest,
+    srv: &Addr<Server>,
+    config: &web::Data<crate::Config>,
+    world: &web::Data<crate::World>,
+) -> Result<(Session, Version), crate::ServiceError> {
+    use crate::ServiceError;
+
     fn json_header<D: serde::de::DeserializeOwned>(
         header_name: &str,
         err: &str,
@@ -43,13 +70,125 @@ pub async fn establish_wormhole(
         })
     }
 
-    let hs = crate::hackstead::fs_get_stead(&json_header::<hcor::UserId>(
-        "WormholeUser",
-        "valid UserId JSON",
-        &req,
-    )?)?;
-    let orifice =
-        json_header::<session::Orifice>("WormholeOrifice", "either 'Bincode' or 'Json'", &req)?;
+    let user = json_header::<hcor::UserId>("WormholeUser", "valid UserId JSON", req)?;
+    let mut hs = crate::hackstead::fs_get_stead(&config.data_dir, &user)?;
+    let orifice = json_header::<session::Orifice>(
+        "WormholeOrifice",
+        "one of 'Bincode', 'Json', 'MessagePack' or 'CBOR'",
+        req,
+    )?;
+    let version = match req.headers().get("WormholeVersion") {
+        None => Version::default(),
+        Some(_) => json_header::<Version>("WormholeVersion", "a protocol version number", req)
+            .map_err(|e| {
+                crate::metrics::HANDSHAKES
+                    .with_label_values(&["unsupported"])
+                    .inc();
+                e
+            })?,
+    };
+    crate::metrics::HANDSHAKES
+        .with_label_values(&[&version.to_string()])
+        .inc();
+
+    if !srv.send(server::HasRoom::new(&hs)).await? {
+        return Err(ServiceError::Unavailable(
+            "the server isn't taking new connections right now, try again later!".to_string(),
+        ));
+    }
+
+    // a headless session may have a fresher copy of this hackstead than the one on disk;
+    // only now that this wormhole is sure to open may it take that session's place
+    if let Some(ses) = srv.send(server::GetSession::new(&hs)).await? {
+        if ses.send(session::Surrender).await? {
+            hs = crate::hackstead::fs_get_stead(&config.data_dir, &user)?;
+        }
+    }
+
+    let memory = srv.send(server::GetMemory::new(&hs)).await?;
+    let quota = srv.send(server::GetQuota::new(&hs)).await?;
+
+    Ok((
+        Session::new(hs, srv, orifice, version, config.clone(), world.clone())
+            .remembering(memory)
+            .rationed(quota),
+        version,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::harness::Harness;
+    use hcor::{
+        wormhole::{AskedNote, Beg},
+        Ask, IdentifiesSteader,
+    };
 
-    ws::start(Session::new(hs, &*srv, orifice), &req, stream)
+    #[actix_rt::test]
+    async fn beg_without_a_wormhole() {
+        let h = Harness::start().await;
+        let bobstead = h.summon().await;
+
+        let note: AskedNote = h
+            .post(
+                "beg",
+                &Beg {
+                    steader_id: bobstead.steader_id(),
+                    ask: Ask::KnowledgeSnort { xp: 10 },
+                },
+            )
+            .await
+            .unwrap();
+        match note {
+            AskedNote::KnowledgeSnortResult(Ok(xp)) => assert_eq!(xp, bobstead.profile.xp + 10),
+            other => panic!("unexpected response to KnowledgeSnort: {:?}", other),
+        }
+
+        // a wormhole takes over from the headless session, without losing what it did
+        let mut wormhole = h.wormhole(&bobstead).await;
+        let ask_id = wormhole.ask(Ask::KnowledgeSnort { xp: 5 }).await;
+        match wormhole.asked(ask_id).await {
+            AskedNote::KnowledgeSnortResult(Ok(xp)) => assert_eq!(xp, bobstead.profile.xp + 15),
+            other => panic!("unexpected response to KnowledgeSnort: {:?}", other),
+        }
+        h.stop().await;
+    }
+
+    #[actix_rt::test]
+    async fn failed_handshakes_leave_headless_sessions_be() {
+        use std::time::Duration;
+
+        let h = Harness::start().await;
+        let bobstead = h.summon().await;
+
+        let beg = Beg {
+            steader_id: bobstead.steader_id(),
+            ask: Ask::KnowledgeSnort { xp: 10 },
+        };
+        h.post::<AskedNote>("beg", &beg).await.unwrap();
+        let census = || h.wormhole.send(crate::wormhole::server::Census);
+        assert_eq!(census().await.unwrap(), 1);
+
+        // nobody speaks this orifice, so no wormhole opens
+        let user = serde_json::to_string(&hcor::UserId::Uuid(bobstead.steader_id())).unwrap();
+        let refused = awc::Client::new()
+            .ws(format!("{}/api/wormhole", h.url))
+            .header("WormholeUser", user)
+            .header("WormholeOrifice", "\"Morse\"")
+            .connect()
+            .await;
+        assert!(
+            refused.is_err(),
+            "opened a wormhole through a made-up orifice"
+        );
+
+        // the headless session is still there, and still answering
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+        assert_eq!(census().await.unwrap(), 1);
+        match h.post::<AskedNote>("beg", &beg).await.unwrap() {
+            AskedNote::KnowledgeSnortResult(Ok(xp)) => assert_eq!(xp, bobstead.profile.xp + 20),
+            other => panic!("unexpected response to KnowledgeSnort: {:?}", other),
+        }
+        h.stop().await;
+    }
 }
@@ -0,0 +1,106 @@
+//! What travels through the wormhole besides what `hcor` defines.
+//!
+//! Notes about the wormhole connection itself, rather than anybody's hackstead, are
+//! `ProtocolNote`s. [`Note`](hcor::Note) only describes the game, so these travel separately:
+//! always as a websockets text message holding JSON of the form `{ "Protocol": <ProtocolNote> }`,
+//! whatever `WormholeOrifice` the client asked for.
+//!
+//! Clients speaking version 3 of the protocol or later wrap what they send in an `Inbound`,
+//! and what they're sent comes wrapped in an `Outbound`, so that there's room for messages like
+//! batches that `hcor` doesn't know about. Both are encoded for the client's orifice.
+use hcor::{
+    wormhole::{AskMessage, AskedNote},
+    Ask, Note,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ProtocolNote {
+    /// The server is going down. Your hackstead has already been saved,
+    /// and the connection will be closed right after this note is sent.
+    Shutdown,
+    /// We couldn't make sense of a message you sent through the wormhole, so nothing was done
+    /// about it. `ask_id` is included if we could find one in the message.
+    ///
+    /// Each of these is a strike against your connection; after `strikes_left` more,
+    /// it'll be closed with the "invalid payload" (1007) close code.
+    Malformed {
+        ask_id: Option<usize>,
+        error: String,
+        strikes_left: usize,
+    },
+    /// You've made too many Asks of the kind named, i.e. "Item.Hatch", too quickly, so the
+    /// message with this `ask_id` wasn't handled. Nothing about it was done; send it again
+    /// after `retry_after` seconds.
+    RateLimited {
+        ask_id: usize,
+        ask: String,
+        retry_after: f64,
+    },
+}
+
+impl ProtocolNote {
+    /// Encodes this note the way it's sent down the wormhole.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        #[derive(Serialize)]
+        enum Wrapper<'a> {
+            Protocol(&'a ProtocolNote),
+        }
+
+        serde_json::to_string(&Wrapper::Protocol(self))
+    }
+}
+
+/// What clients speaking version 3 or later send through the wormhole.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Inbound {
+    /// A single Ask, just as earlier versions send it.
+    Ask(AskMessage),
+    /// Several Asks, to be handled all together or not at all.
+    Batch(BatchMessage),
+}
+
+impl Inbound {
+    pub fn ask_id(&self) -> usize {
+        match self {
+            Inbound::Ask(AskMessage { ask_id, .. })
+            | Inbound::Batch(BatchMessage { ask_id, .. }) => *ask_id,
+        }
+    }
+}
+
+/// Several Asks, handled in order against the same hackstead. If any of them fail, none of their
+/// changes are kept; otherwise, they're all described by a single `EditNote`. Throws involve
+/// another steader's hackstead, so they can't be part of a batch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchMessage {
+    pub asks: Vec<Ask>,
+    pub ask_id: usize,
+}
+
+/// What's sent to clients speaking version 3 or later through the wormhole.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Outbound {
+    Note(Note),
+    /// What came of a `BatchMessage`. `results` holds the response to each Ask in the batch,
+    /// up to and including the first to fail, if any did. If none did, the batch was
+    /// `committed`, and all of their changes were kept; otherwise, none were.
+    Batched {
+        ask_id: usize,
+        committed: bool,
+        results: Vec<AskedNote>,
+    },
+    /// Sent instead of an `EditNote` to clients on the MessagePack or CBOR orifices,
+    /// from version 4 on.
+    Edit(FramedEdit),
+}
+
+/// An edit to the hackstead, for the MessagePack and CBOR orifices, which
+/// [`EditNote`](hcor::wormhole::EditNote) has no variant for. Rather than a byte string, the diff
+/// itself is written out in the orifice's own format, just like everything around it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FramedEdit {
+    MessagePack(serde_json::Value),
+    #[serde(rename = "CBOR")]
+    Cbor(serde_json::Value),
+}
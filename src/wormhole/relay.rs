@@ -0,0 +1,447 @@
+//! A way through the wormhole for clients that can't keep a websocket open,
+//! i.e. because a proxy between them and us gets in the way.
+//!
+//! A relay is a Session like any other; it just isn't attached to a websocket. Instead, the
+//! frames it sends out are read by a pump, which answers its heartbeat pings so long as the client
+//! has been around recently and queues up everything else for the client to pick up, either as
+//! Server-Sent Events or by long-polling. Asks are POSTed, and handed to the Session as frames,
+//! just as if they'd come through a websocket. That way, relays get the same heartbeat, ticker
+//! and orifice as any other Session, without any of it being written twice.
+use super::{open_session, session::Session, Server};
+use crate::{Config, ServiceError, World};
+use actix::Addr;
+use actix_codec::{Decoder, Encoder};
+use actix_web::{
+    delete,
+    error::PayloadError,
+    get, post,
+    web::{self, Bytes, BytesMut},
+    HttpRequest, HttpResponse,
+};
+use actix_web_actors::ws;
+use futures::{channel::mpsc, Stream, StreamExt};
+use log::*;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// Something a relay's Session sent out, waiting to be picked up.
+#[derive(Debug, Clone, Serialize)]
+pub enum Relayed {
+    /// A text message, i.e. a JSON `Outbound` (or bare `Note`, before version 3) or a
+    /// `ProtocolNote`.
+    Text(String),
+    /// A binary message, encoded in base64.
+    Binary(String),
+    /// The Session has hung up.
+    Close {
+        code: u16,
+        description: Option<String>,
+    },
+    /// A heartbeat; only sent as a Server-Sent Event, to keep proxies from giving up on us.
+    #[serde(skip)]
+    Ping,
+}
+
+impl Relayed {
+    /// This, as a Server-Sent Event.
+    fn event(&self) -> String {
+        let (event, data) = match self {
+            Relayed::Text(t) => ("text", t.clone()),
+            Relayed::Binary(b) => ("binary", b.clone()),
+            Relayed::Close { code, description } => (
+                "close",
+                serde_json::json!({ "code": code, "description": description }).to_string(),
+            ),
+            Relayed::Ping => return ": ping\n\n".to_string(),
+        };
+
+        let mut event = format!("event: {}\n", event);
+        for line in data.lines() {
+            event.push_str("data: ");
+            event.push_str(line);
+            event.push('\n');
+        }
+        event.push('\n');
+        event
+    }
+}
+
+/// One client's way through the wormhole, without a websocket.
+pub struct Relay {
+    /// Frames for the Session to read, as though they'd come from a websocket client.
+    inbound: mpsc::UnboundedSender<Result<Bytes, PayloadError>>,
+    /// What the Session has sent out; taken by whoever's listening for it.
+    outbound: Mutex<Option<mpsc::UnboundedReceiver<Relayed>>>,
+    /// When the client last listened for or sent anything.
+    last_seen: Mutex<Instant>,
+    orifice: super::session::Orifice,
+    world: web::Data<World>,
+}
+
+impl Relay {
+    /// Hands the Session a message as though it came from a websocket client.
+    fn send(&self, msg: ws::Message) {
+        let mut buf = BytesMut::new();
+        match ws::Codec::new().client_mode().encode(msg, &mut buf) {
+            // if this fails, the Session is already gone
+            Ok(()) => drop(self.inbound.unbounded_send(Ok(buf.freeze()))),
+            Err(e) => error!("couldn't encode frame for relay: {}", e),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_seen.lock().unwrap() = self.world.now();
+    }
+
+    /// Whether the client is still around: listening right now, or by recently enough.
+    fn alive(&self, client_timeout: Duration) -> bool {
+        self.outbound.lock().unwrap().is_none()
+            || self
+                .world
+                .now()
+                .duration_since(*self.last_seen.lock().unwrap())
+                < client_timeout
+    }
+}
+
+/// Takes what a relay's Session sends out, until it's dropped.
+struct Listener {
+    rx: mpsc::UnboundedReceiver<Relayed>,
+    relay: Arc<Relay>,
+}
+
+fn listen(relay: &Arc<Relay>) -> Result<Listener, ServiceError> {
+    let rx =
+        relay.outbound.lock().unwrap().take().ok_or_else(|| {
+            ServiceError::bad_request("someone is already listening to this relay")
+        })?;
+
+    Ok(Listener {
+        rx,
+        relay: relay.clone(),
+    })
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let (_, closed) = mpsc::unbounded();
+        *self.relay.outbound.lock().unwrap() = Some(std::mem::replace(&mut self.rx, closed));
+        // they've got until client_timeout to come back
+        self.relay.touch();
+    }
+}
+
+/// The body of a Server-Sent Events response.
+impl Stream for Listener {
+    type Item = Result<Bytes, actix_web::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.rx
+            .poll_next_unpin(cx)
+            .map(|r| r.map(|r| Ok(Bytes::from(r.event()))))
+    }
+}
+
+/// Every relay that's open, by `relay_id`. There should only be one of these, shared by every
+/// worker, as the requests concerning a relay may be handled by any of them.
+#[derive(Default)]
+pub struct Relays(Mutex<HashMap<Uuid, Arc<Relay>>>);
+
+impl Relays {
+    fn get(&self, relay_id: Uuid) -> Result<Arc<Relay>, ServiceError> {
+        let relay = self
+            .0
+            .lock()
+            .unwrap()
+            .get(&relay_id)
+            .cloned()
+            .ok_or(ServiceError::NoData)?;
+        relay.touch();
+        Ok(relay)
+    }
+}
+
+/// Starts a Session without a websocket, returning the id of the relay through which it's reached.
+pub fn start(
+    relays: &web::Data<Relays>,
+    session: Session,
+    config: &Config,
+    world: web::Data<World>,
+) -> Uuid {
+    let relay_id = Uuid::new_v4();
+    let (inbound, frames_in) = mpsc::unbounded();
+    let (relayed, outbound) = mpsc::unbounded();
+    let relay = Arc::new(Relay {
+        inbound,
+        outbound: Mutex::new(Some(outbound)),
+        last_seen: Mutex::new(world.now()),
+        orifice: session.orifice(),
+        world,
+    });
+    relays.0.lock().unwrap().insert(relay_id, relay.clone());
+
+    let frames_out = ws::WebsocketContext::create(session, frames_in);
+    let (relays, client_timeout) = (relays.clone(), config.client_timeout);
+    actix::spawn(async move {
+        pump(&relay, frames_out, relayed, client_timeout).await;
+        relays.0.lock().unwrap().remove(&relay_id);
+        debug!("relay {} closed", relay_id);
+    });
+
+    relay_id
+}
+
+/// Reads the frames a relay's Session sends out, answering its pings while the client is around
+/// and passing everything else along to them.
+async fn pump(
+    relay: &Relay,
+    frames: impl Stream<Item = Result<Bytes, actix_web::Error>>,
+    relayed: mpsc::UnboundedSender<Relayed>,
+    client_timeout: Duration,
+) {
+    let mut frames = Box::pin(frames);
+    let mut codec = ws::Codec::new().client_mode();
+    let mut buf = BytesMut::new();
+
+    while let Some(chunk) = frames.next().await {
+        match chunk {
+            Ok(bytes) => buf.extend_from_slice(&bytes),
+            Err(e) => {
+                error!("relay's session failed: {}", e);
+                return;
+            }
+        }
+
+        loop {
+            let frame = match codec.decode(&mut buf) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => {
+                    error!("couldn't read frame from relay's session: {}", e);
+                    return;
+                }
+            };
+
+            // nobody may be listening right now, so these wait in `outbound` until someone is
+            drop(
+                relayed.unbounded_send(match frame {
+                    ws::Frame::Text(t) => Relayed::Text(String::from_utf8_lossy(&t).into_owned()),
+                    ws::Frame::Binary(b) => Relayed::Binary(base64::encode(&b)),
+                    ws::Frame::Close(reason) => Relayed::Close {
+                        code: reason
+                            .as_ref()
+                            .map_or(ws::CloseCode::Normal, |r| r.code)
+                            .into(),
+                        description: reason.and_then(|r| r.description),
+                    },
+                    ws::Frame::Ping(p) => {
+                        if relay.alive(client_timeout) {
+                            relay.send(ws::Message::Pong(p));
+                        }
+                        Relayed::Ping
+                    }
+                    // pongs and continuations aren't anything the client needs
+                    _ => continue,
+                }),
+            );
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Opened {
+    relay_id: Uuid,
+    version: super::Version,
+}
+
+#[post("/relay")]
+/// Opens a relay, given the same headers as `/api/wormhole`.
+pub async fn open(
+    req: HttpRequest,
+    srv: web::Data<Addr<Server>>,
+    config: web::Data<Config>,
+    world: web::Data<World>,
+    relays: web::Data<Relays>,
+) -> Result<HttpResponse, ServiceError> {
+    let (session, version) = open_session(&req, &srv, &config, &world).await?;
+    let relay_id = start(&relays, session, &config, world.clone());
+
+    Ok(HttpResponse::Ok()
+        .header("WormholeVersion", version.to_string())
+        .json(Opened { relay_id, version }))
+}
+
+#[post("/relay/{relay_id}")]
+/// Hands the body, an `Inbound` (or bare `AskMessage`, before version 3) encoded for the relay's
+/// orifice, to its Session.
+/// Whatever comes of it is sent out through the relay, as it would be through a websocket.
+pub async fn ask(
+    relay_id: web::Path<Uuid>,
+    body: Bytes,
+    relays: web::Data<Relays>,
+) -> Result<HttpResponse, ServiceError> {
+    let relay = relays.get(*relay_id)?;
+
+    relay.send(if relay.orifice.is_binary() {
+        ws::Message::Binary(body)
+    } else {
+        ws::Message::Text(String::from_utf8(body.to_vec()).map_err(|e| {
+            ServiceError::bad_request(&format!(
+                "the {} orifice only takes text: {}",
+                relay.orifice.name(),
+                e
+            ))
+        })?)
+    });
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[get("/relay/{relay_id}")]
+/// Waits for the relay's Session to send something, then responds with everything it's sent
+/// since anyone last listened, as a JSON list of `Relayed`. If nothing's sent for half of
+/// `client_timeout`, the list is empty.
+pub async fn poll(
+    relay_id: web::Path<Uuid>,
+    relays: web::Data<Relays>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut listener = listen(&relays.get(*relay_id)?)?;
+    let rx = &mut listener.rx;
+
+    let first = tokio::time::timeout(config.client_timeout / 2, async {
+        loop {
+            match rx.next().await {
+                Some(Relayed::Ping) => continue,
+                other => return other,
+            }
+        }
+    })
+    .await;
+
+    let mut relayed = vec![];
+    if let Ok(Some(r)) = first {
+        relayed.push(r);
+        while let Ok(Some(r)) = rx.try_next() {
+            if !matches!(r, Relayed::Ping) {
+                relayed.push(r);
+            }
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(relayed))
+}
+
+#[get("/relay/{relay_id}/events")]
+/// Streams whatever the relay's Session sends out as Server-Sent Events, for as long as the
+/// client keeps the connection open.
+pub async fn events(
+    relay_id: web::Path<Uuid>,
+    relays: web::Data<Relays>,
+) -> Result<HttpResponse, ServiceError> {
+    let listener = listen(&relays.get(*relay_id)?)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(listener))
+}
+
+#[delete("/relay/{relay_id}")]
+/// Hangs up on the relay's Session.
+pub async fn close(
+    relay_id: web::Path<Uuid>,
+    relays: web::Data<Relays>,
+) -> Result<HttpResponse, ServiceError> {
+    relays
+        .get(*relay_id)?
+        .send(ws::Message::Close(Some(ws::CloseCode::Normal.into())));
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        harness::Harness,
+        wormhole::protocol::{Inbound, Outbound},
+    };
+    use hcor::{
+        wormhole::{AskMessage, AskedNote},
+        Ask, IdentifiesSteader, Note, UserId,
+    };
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn knowledge_snort_through_relay() {
+        let h = Harness::start().await;
+        let bobstead = h.summon().await;
+        let client = awc::Client::new();
+
+        let opened: serde_json::Value = client
+            .post(format!("{}/api/relay", h.url))
+            .header(
+                "WormholeUser",
+                serde_json::to_string(&UserId::Uuid(bobstead.steader_id())).unwrap(),
+            )
+            .header("WormholeOrifice", "\"Json\"")
+            .header("WormholeVersion", crate::version::CURRENT.to_string())
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let relay = format!(
+            "{}/api/relay/{}",
+            h.url,
+            opened["relay_id"].as_str().unwrap()
+        );
+
+        let ask = Inbound::Ask(AskMessage {
+            ask: Ask::KnowledgeSnort { xp: 10 },
+            ask_id: 7,
+        });
+        let res = client
+            .post(&relay)
+            .send_body(serde_json::to_string(&ask).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 202);
+
+        for _ in 0..10 {
+            let relayed: Vec<serde_json::Value> = client
+                .get(&relay)
+                .timeout(Duration::from_secs(30))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+
+            for text in relayed.iter().filter_map(|r| r.get("Text")?.as_str()) {
+                if let Ok(Outbound::Note(Note::Asked { ask_id: 7, note })) =
+                    serde_json::from_str(text)
+                {
+                    match note {
+                        AskedNote::KnowledgeSnortResult(Ok(xp)) => {
+                            assert_eq!(xp, bobstead.profile.xp + 10)
+                        }
+                        other => panic!("unexpected response to KnowledgeSnort: {:?}", other),
+                    }
+                    client.delete(&relay).send().await.unwrap();
+                    return h.stop().await;
+                }
+            }
+        }
+        panic!("no response to KnowledgeSnort came through the relay");
+    }
+}
@@ -3,14 +3,24 @@
 
 use std::collections::HashMap;
 
-use actix::{dev::Envelope, Actor, Addr, AsyncContext, Context, Handler, Message};
+use actix::{
+    dev::Envelope, Actor, Addr, AsyncContext, Context, Handler, MailboxError, Message,
+    ResponseFuture,
+};
 use log::*;
 
-use super::session::{self, Session};
-use hcor::{IdentifiesSteader, Note, SteaderId};
+use super::session::{
+    self,
+    memory::SharedMemory,
+    quota::{Quota, SharedQuota},
+    Session,
+};
+use crate::{metrics, Config, ServiceError, World};
+use actix_web::web;
+use hcor::{IdentifiesSteader, Note, SteaderId, UserId};
 
 mod throw;
-pub use throw::ThrowItems;
+pub use throw::{transfer, ThrowItems};
 
 /// New session is created
 #[derive(Message)]
@@ -23,7 +33,14 @@ impl Handler<Connect> for Server {
     type Result = ();
 
     fn handle(&mut self, Connect(u, a): Connect, _: &mut Context<Self>) {
+        if self.closing {
+            info!("user connected while shutting down, sending them away");
+            a.do_send(session::Shutdown);
+            return;
+        }
+
         self.sessions.insert(u, a);
+        metrics::SESSIONS.set(metrics::count(self.sessions.len()));
 
         info!("user connected - {} users online", self.sessions.len());
     }
@@ -32,23 +49,40 @@ impl Handler<Connect> for Server {
 /// Session disconnected
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Disconnect(pub SteaderId);
+pub struct Disconnect(pub SteaderId, pub Addr<Session>);
 
 impl Handler<Disconnect> for Server {
     type Result = ();
 
-    fn handle(&mut self, Disconnect(u): Disconnect, ctx: &mut Context<Self>) {
-        self.sessions.remove(&u);
+    fn handle(&mut self, Disconnect(u, a): Disconnect, ctx: &mut Context<Self>) {
+        // the session ending may already have been replaced by a newer one
+        if self.sessions.get(&u) == Some(&a) {
+            self.sessions.remove(&u);
+        }
+        // a memory's kept after its sessions end, but only for as long as there's something in it
+        let forgotten = |m: &SharedMemory| m.lock().unwrap().is_empty();
+        if !self.sessions.contains_key(&u) && self.memories.get(&u).map_or(false, forgotten) {
+            self.memories.remove(&u);
+        }
+        // likewise, a quota with every bucket full is no different from a new one
+        let full = |q: &SharedQuota| q.lock().unwrap().is_empty();
+        if !self.sessions.contains_key(&u) && self.quotas.get(&u).map_or(false, full) {
+            self.quotas.remove(&u);
+        }
+        metrics::SESSIONS.set(metrics::count(self.sessions.len()));
 
         info!("user logged off - {} users online", self.sessions.len());
 
-        #[cfg(feature = "autoclose")]
-        if self.sessions.len() == 0 {
-            warn!("ending process in 30 seconds if no more users log on");
-            ctx.run_later(std::time::Duration::from_secs(30), |act, _| {
-                if act.sessions.len() == 0 {
+        if self.closing {
+            return;
+        }
+
+        if let (Some(after), true) = (self.config.autoclose, self.sessions.is_empty()) {
+            warn!("ending process in {:?} if no more users log on", after);
+            ctx.run_later(after, |act, ctx| {
+                if act.sessions.is_empty() {
                     warn!("ending process");
-                    std::process::exit(0)
+                    ctx.notify(Shutdown);
                 }
             });
         }
@@ -87,6 +121,141 @@ impl Handler<GetSession> for Server {
     }
 }
 
+/// Get the `Memory` shared by a user's Sessions of what came of the Asks they've made recently.
+#[derive(Message)]
+#[rtype(result = "SharedMemory")]
+pub struct GetMemory(pub SteaderId);
+
+impl GetMemory {
+    pub fn new(iu: impl IdentifiesSteader) -> Self {
+        Self(iu.steader_id())
+    }
+}
+
+impl Handler<GetMemory> for Server {
+    type Result = SharedMemory;
+
+    fn handle(&mut self, GetMemory(sr): GetMemory, _: &mut Context<Self>) -> Self::Result {
+        self.memory(sr)
+    }
+}
+
+/// Get the `Quota` shared by a user's Sessions, limited by `Config::rate_limits`.
+#[derive(Message)]
+#[rtype(result = "SharedQuota")]
+pub struct GetQuota(pub SteaderId);
+
+impl GetQuota {
+    pub fn new(iu: impl IdentifiesSteader) -> Self {
+        Self(iu.steader_id())
+    }
+}
+
+impl Handler<GetQuota> for Server {
+    type Result = SharedQuota;
+
+    fn handle(&mut self, GetQuota(sr): GetQuota, _: &mut Context<Self>) -> Self::Result {
+        self.quota(sr)
+    }
+}
+
+/// Get the Session associated with a user, starting a headless one for them if they don't have
+/// one, so that their Asks can be handled even without a wormhole.
+#[derive(Message)]
+#[rtype(result = "Result<Addr<Session>, ServiceError>")]
+pub struct Awaken(pub SteaderId, pub web::Data<World>);
+
+impl Handler<Awaken> for Server {
+    type Result = Result<Addr<Session>, ServiceError>;
+
+    fn handle(&mut self, Awaken(sr, world): Awaken, ctx: &mut Context<Self>) -> Self::Result {
+        use actix_web::{error::PayloadError, web::Bytes};
+        use actix_web_actors::ws;
+        use futures::{future, stream, StreamExt};
+
+        if let Some(ses) = self.sessions.get(&sr) {
+            return Ok(ses.clone());
+        }
+        if !self.has_room(sr) {
+            return Err(ServiceError::Unavailable(
+                "the server isn't taking new sessions right now, try again later!".to_string(),
+            ));
+        }
+
+        let hs = crate::hackstead::fs_get_stead(&self.config.data_dir, UserId::Uuid(sr))?;
+        let session = Session::new(
+            hs,
+            &ctx.address(),
+            session::Orifice::Json,
+            super::version::CURRENT,
+            self.config.clone(),
+            world,
+        )
+        .headless(self.config.headless_idle)
+        .remembering(self.memory(sr))
+        .rationed(self.quota(sr));
+
+        // nobody's listening to what a headless session sends out, but it has to go somewhere
+        let (addr, frames) = ws::WebsocketContext::create_with_addr(
+            session,
+            stream::pending::<Result<Bytes, PayloadError>>(),
+        );
+        actix::spawn(frames.for_each(|_| future::ready(())));
+
+        // registered right away, rather than once it starts, so it's found by the next Awaken
+        self.sessions.insert(sr, addr.clone());
+        info!("started headless session for {}", sr);
+        Ok(addr)
+    }
+}
+
+/// Would the server accept a new session for this user, given `Config::max_sessions`?
+/// Users who already have a session are always welcome, as their new session will replace it,
+/// but nobody is welcome once the server has begun to shut down.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct HasRoom(pub SteaderId);
+
+impl HasRoom {
+    pub fn new(iu: impl IdentifiesSteader) -> Self {
+        Self(iu.steader_id())
+    }
+}
+
+impl Handler<HasRoom> for Server {
+    type Result = bool;
+
+    fn handle(&mut self, HasRoom(sr): HasRoom, _: &mut Context<Self>) -> Self::Result {
+        self.has_room(sr)
+    }
+}
+
+/// How many sessions are open? Also handy for checking that the Server is responsive.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct Census;
+
+impl Handler<Census> for Server {
+    type Result = usize;
+
+    fn handle(&mut self, Census: Census, _: &mut Context<Self>) -> Self::Result {
+        self.sessions.len()
+    }
+}
+
+/// Who has a session open?
+#[derive(Message)]
+#[rtype(result = "Vec<SteaderId>")]
+pub struct ListSessions;
+
+impl Handler<ListSessions> for Server {
+    type Result = Vec<SteaderId>;
+
+    fn handle(&mut self, ListSessions: ListSessions, _: &mut Context<Self>) -> Self::Result {
+        self.sessions.keys().copied().collect()
+    }
+}
+
 /// Get the Session associated with a user, if there is one currently registered for them.
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -101,16 +270,110 @@ impl Handler<HandleEnvelope> for Server {
     }
 }
 
+/// Lets the Server know which HTTP server it's running behind, so that it can stop it
+/// when it's time to shut down.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Serving(pub actix_web::dev::Server);
+
+impl Handler<Serving> for Server {
+    type Result = ();
+
+    fn handle(&mut self, Serving(http): Serving, _: &mut Context<Self>) {
+        self.http = Some(http);
+    }
+}
+
+/// Shut down in an orderly fashion: stop accepting new wormholes, have each session save its
+/// hackstead and say goodbye to its client, then stop the HTTP server. Whether the process is
+/// ending because of a signal or because of `Config::autoclose`, this is how it goes down.
+#[derive(Message)]
+#[rtype(result = "Result<(), MailboxError>")]
+pub struct Shutdown;
+
+impl Handler<Shutdown> for Server {
+    type Result = ResponseFuture<Result<(), MailboxError>>;
+
+    fn handle(&mut self, Shutdown: Shutdown, _: &mut Context<Self>) -> Self::Result {
+        if self.closing {
+            debug!("already shutting down");
+            return Box::pin(async { Ok(()) });
+        }
+        self.closing = true;
+
+        info!(
+            "shutting down, saying goodbye to {} users",
+            self.sessions.len()
+        );
+        let goodbyes = futures::future::join_all(
+            self.sessions
+                .values()
+                .map(|ses| ses.send(session::Shutdown)),
+        );
+        let http = self.http.take();
+
+        Box::pin(async move {
+            for res in goodbyes.await {
+                if let Err(e) = res {
+                    error!("couldn't shut down session: {}", e);
+                }
+            }
+            info!("all sessions closed");
+
+            if let Some(http) = http {
+                http.stop(true).await;
+            }
+            Ok(())
+        })
+    }
+}
+
 /// `Server` manages connected clients and is responsible for dispatching Notes to them.
-#[derive(Default)]
 pub struct Server {
     sessions: HashMap<SteaderId, Addr<Session>>,
+    /// See `GetMemory`.
+    memories: HashMap<SteaderId, SharedMemory>,
+    /// See `GetQuota`.
+    quotas: HashMap<SteaderId, SharedQuota>,
+    config: web::Data<Config>,
+    http: Option<actix_web::dev::Server>,
+    /// Set once we've begun shutting down, after which no new sessions are accepted.
+    closing: bool,
 }
 
 impl Server {
+    /// See `HasRoom`.
+    fn has_room(&self, sr: SteaderId) -> bool {
+        !self.closing
+            && match self.config.max_sessions {
+                Some(max) => self.sessions.len() < max || self.sessions.contains_key(&sr),
+                None => true,
+            }
+    }
+
+    /// See `GetMemory`.
+    fn memory(&mut self, sr: SteaderId) -> SharedMemory {
+        self.memories.entry(sr).or_default().clone()
+    }
+
+    /// See `GetQuota`.
+    fn quota(&mut self, sr: SteaderId) -> SharedQuota {
+        self.quotas
+            .entry(sr)
+            .or_insert_with(|| Quota::shared("steader"))
+            .clone()
+    }
+
     #[must_use]
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(config: web::Data<Config>) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            memories: HashMap::new(),
+            quotas: HashMap::new(),
+            config,
+            http: None,
+            closing: false,
+        }
     }
 
     /// Send note to all users
@@ -1,4 +1,8 @@
-use crate::wormhole::session::SessSend;
+use crate::{
+    audit::{self, Cause},
+    metrics,
+    wormhole::session::SessSend,
+};
 use actix::{Context, Handler, MailboxError, Message, ResponseFuture};
 use hcor::{
     id, item,
@@ -6,9 +10,9 @@ use hcor::{
         AskedNote::{self, ItemThrowResult},
         RudeNote::ItemThrowReceipt,
     },
-    Item, ItemId, Note, SteaderId,
+    Hackstead, IdentifiesSteader, Item, ItemId, Note, SteaderId,
 };
-use std::fmt;
+use std::{fmt, time::Instant};
 
 #[derive(Debug)]
 pub enum Error {
@@ -96,6 +100,7 @@ impl Handler<ThrowItems> for super::Server {
             receiver_id,
             item_ids,
         } = ti;
+        let started = Instant::now();
 
         // unfortunately we have to clone these even though they contain several Arcs so that we
         // can pass them into the 'static future.
@@ -115,6 +120,8 @@ impl Handler<ThrowItems> for super::Server {
         };
         let tx = ses(sender_id);
         let rx = ses(receiver_id);
+        let logged_item_ids = item_ids.clone();
+        let config = self.config.clone();
 
         let f = async move {
             if sender_id == receiver_id {
@@ -123,28 +130,14 @@ impl Handler<ThrowItems> for super::Server {
 
             let (mut tx_ss, tx_ses) = tx.await?;
             let (mut rx_ss, rx_ses) = rx.await?;
+            let cause = Cause::Throw {
+                sender_id,
+                receiver_id,
+            };
+            tx_ss.cause = cause.clone();
+            rx_ss.cause = cause;
 
-            // n^2 perf right here D:
-            let mut items = item_ids
-                .clone()
-                .into_iter()
-                .map(|i| tx_ss.take_item(i))
-                .collect::<Result<Vec<Item>, id::NoSuch>>()?;
-
-            for i in &mut items {
-                if i.owner_id != sender_id {
-                    return Err(MixedOwnership(i.item_id));
-                }
-                i.owner_id = receiver_id;
-
-                i.ownership_log.push(item::LoggedOwner {
-                    logged_owner_id: receiver_id,
-                    acquisition: item::Acquisition::Trade,
-                    owner_index: i.ownership_log.len(),
-                })
-            }
-
-            rx_ss.inventory.append(&mut items.clone());
+            let items = transfer(&mut tx_ss, &mut rx_ss, item_ids)?;
 
             rx_ss.send_note(Note::Rude(ItemThrowReceipt {
                 from: sender_id,
@@ -156,166 +149,262 @@ impl Handler<ThrowItems> for super::Server {
             Ok(items)
         };
 
-        Box::pin(async move { ItemThrowResult(super::session::strerr(f.await)) })
+        Box::pin(async move {
+            let res = f.await;
+            let outcome = metrics::outcome(&res);
+            metrics::THROWS.with_label_values(&[outcome]).inc();
+            metrics::observe_ask("Item.Throw", outcome, started);
+
+            for steader_id in &[sender_id, receiver_id] {
+                audit::record(
+                    &config,
+                    *steader_id,
+                    audit::Event::Throw {
+                        sender_id,
+                        receiver_id,
+                        item_ids: logged_item_ids.clone(),
+                        outcome: res.as_ref().map(|_| ()).map_err(ToString::to_string),
+                    },
+                );
+            }
+
+            ItemThrowResult(super::session::strerr(res))
+        })
     }
 }
 
-#[cfg(all(test, feature = "hcor_client"))]
-mod test {
-    #[test]
-    pub fn throw() {
-        use actix::System;
-        use hcor::{
-            wormhole::{self, Note, RudeNote},
-            Hackstead, IdentifiesSteader,
-        };
-        use log::*;
-        use std::time::Duration;
-        use tokio::{sync::oneshot, time::timeout};
-        const SERVER_WAIT: Duration = Duration::from_millis(100);
-
-        // attempt to establish logging, do nothing if it fails
-        // (it probably fails because it's already been established in another test)
-        drop(pretty_env_logger::try_init());
-
-        // send bob's name to eve so she knows where to send the items
-        let (tx, rx) = oneshot::channel();
-
-        // bob
-        let t1 = std::thread::spawn(move || {
-            System::new("test").block_on(async move {
-                let mut bobstead = Hackstead::register().await.unwrap();
-                tx.send(bobstead.profile.steader_id).unwrap();
-
-                let until = wormhole::until_map(|n| match n {
-                    Note::Rude(RudeNote::ItemThrowReceipt { from, items }) => Some((from, items)),
-                    _ => None,
-                });
-                let (eve_steader_id, items) =
-                    timeout(SERVER_WAIT, until).await.expect("timeout").unwrap();
-
-                // verify the items log eve then bob as the owners
-                bobstead = Hackstead::fetch(&bobstead).await.unwrap();
-                let evestead = Hackstead::fetch(eve_steader_id).await.unwrap();
-                for item in &items {
-                    assert!(
-                        !evestead.has_item(item),
-                        "eve still has an item she gave away: {:#?}",
-                        item
-                    );
-                    assert!(
-                        bobstead.has_item(item),
-                        "bob doesn't have an item he was transferred: {:#?}",
-                        item
-                    );
-                    assert_eq!(
-                        item.ownership_log.len(),
-                        2,
-                        "spawned then traded item doesn't have two owners: {:#?}",
-                        item
-                    );
-                    assert_eq!(
-                        item.ownership_log.first().unwrap().logged_owner_id,
-                        evestead.profile.steader_id,
-                        "item spawned for eve doesn't log her as the first owner: {:#?}",
-                        item
-                    );
-                    assert_eq!(
-                        item.ownership_log.get(1).unwrap().logged_owner_id,
-                        bobstead.profile.steader_id,
-                        "item given to bob doesn't log him as the second owner: {:#?}",
-                        item
-                    );
-                }
+/// Moves items from one hackstead's inventory to another's, transferring ownership.
+///
+/// If this fails, the hacksteads may have been left halfway through the transfer,
+/// so like any other changes in a `SessSend`, they should only be kept if it succeeds.
+pub fn transfer(
+    tx: &mut Hackstead,
+    rx: &mut Hackstead,
+    item_ids: Vec<ItemId>,
+) -> Result<Vec<Item>, Error> {
+    let (sender_id, receiver_id) = (tx.steader_id(), rx.steader_id());
+    if sender_id == receiver_id {
+        return Err(SelfGive);
+    }
 
-                // give one last item to eve
-                items.last().unwrap().throw_at(&evestead).await.unwrap();
-
-                // make sure all gives still fail when the items are of mixed ownership
-                // (the items are of mixed ownership now because the last one belongs to eve again)
-                match evestead.throw_items(&bobstead, &items).await {
-                    Err(e) => info!(
-                        "received error as expected trying to give items of mixed ownership: {}",
-                        e
-                    ),
-                    Ok(i) => panic!(
-                        "unexpectedly able to give away items of mixed ownership: {:#?}",
-                        i
-                    ),
-                }
+    // n^2 perf right here D:
+    let mut items = item_ids
+        .into_iter()
+        .map(|i| tx.take_item(i))
+        .collect::<Result<Vec<Item>, id::NoSuch>>()?;
 
-                bobstead.slaughter().await.unwrap();
-            });
-        });
-
-        // eve
-        let t2 = std::thread::spawn(move || {
-            System::new("test").block_on(async move {
-                const ITEM_ARCHETYPE: hcor::config::ArchetypeHandle = 0;
-                const ITEM_SPAWN_COUNT: usize = 10;
-
-                let evestead = Hackstead::register().await.unwrap();
-                let bobstead = Hackstead::fetch(rx.await.unwrap()).await.unwrap();
-
-                // spawn eve items and verify that they log her as the owner
-                let items = bobstead
-                    .spawn_items(ITEM_ARCHETYPE, ITEM_SPAWN_COUNT)
-                    .await
-                    .unwrap();
-                for item in &items {
-                    assert_eq!(
-                        item.ownership_log.len(),
-                        1,
-                        "freshly spawned item has more than one owner: {:#?}",
-                        item
-                    );
-                    assert_eq!(
-                        item.ownership_log.first().unwrap().logged_owner_id,
-                        evestead.profile.steader_id,
-                        "item spawned for eve doesn't log her as the first owner: {:#?}",
-                        item
-                    );
-                }
+    for i in &mut items {
+        if i.owner_id != sender_id {
+            return Err(MixedOwnership(i.item_id));
+        }
+        i.owner_id = receiver_id;
 
-                // give items to bob
-                evestead.throw_items(&bobstead, &items).await.unwrap();
+        i.ownership_log.push(item::LoggedOwner {
+            logged_owner_id: receiver_id,
+            acquisition: item::Acquisition::Trade,
+            owner_index: i.ownership_log.len(),
+        })
+    }
 
-                // wait until bob gives one item back
-                let until = wormhole::until_map(|n| match n {
-                    Note::Rude(RudeNote::ItemThrowReceipt { from, items }) => Some((from, items)),
-                    _ => None,
-                });
-                let (_, mut items) = timeout(SERVER_WAIT, until).await.expect("timeout").unwrap();
+    rx.inventory.append(&mut items.clone());
+    Ok(items)
+}
 
-                let item = items.pop().unwrap();
-                assert!(
-                    items.is_empty(),
-                    "bob only gave one item, which was taken from the list already"
-                );
+#[cfg(test)]
+mod prop {
+    use super::transfer;
+    use hcor::{item, Hackstead, Item, ItemId};
+    use proptest::{collection::vec, prelude::*};
+
+    fn stocked(archetypes: &[usize]) -> Hackstead {
+        let mut hs = Hackstead::new_user(None);
+        let owner = hs.profile.steader_id;
+        hs.inventory.extend(archetypes.iter().map(|&a| {
+            Item::from_archetype_handle(a, owner, item::Acquisition::spawned()).unwrap()
+        }));
+        hs
+    }
 
-                assert_eq!(
-                    vec![
-                        evestead.steader_id(),
-                        bobstead.steader_id(),
-                        evestead.steader_id(),
-                    ],
-                    item.ownership_log
-                        .iter()
-                        .map(|o| o.logged_owner_id)
-                        .collect::<Vec<_>>(),
-                    "malformed ownership log for spawned(eve), given(bob), then given(eve) item",
-                );
+    fn count(a: &Hackstead, b: &Hackstead) -> usize {
+        a.inventory.len() + b.inventory.len()
+    }
 
-                match item.throw_at(&evestead).await {
-                    Err(e) => info!("received error as expected trying to self-give: {}", e),
-                    Ok(i) => panic!("unexpectedly able to give item to self: {:#?}", i),
+    proptest! {
+        #[test]
+        /// However a throw turns out, no items are created or destroyed.
+        fn throws_conserve_items(
+            tx_arches in vec(0..hcor::CONFIG.possession_archetypes.len(), 0..8),
+            rx_arches in vec(0..hcor::CONFIG.possession_archetypes.len(), 0..8),
+            picks in vec(any::<prop::sample::Index>(), 0..8),
+            stranger in any::<bool>(),
+        ) {
+            let (tx, rx) = (stocked(&tx_arches), stocked(&rx_arches));
+
+            // some of the sender's items, maybe some of the receiver's, and maybe one nobody has
+            let mut ids: Vec<ItemId> = tx.inventory.iter().chain(&rx.inventory).map(|i| i.item_id).collect();
+            if stranger || ids.is_empty() {
+                ids.push(ItemId(uuid::Uuid::new_v4()));
+            }
+            let item_ids: Vec<ItemId> = picks.iter().map(|p| *p.get(&ids)).collect();
+
+            let (mut new_tx, mut new_rx) = (tx.clone(), rx.clone());
+            let (tx, rx) = match transfer(&mut new_tx, &mut new_rx, item_ids.clone()) {
+                Ok(moved) => {
+                    prop_assert!(moved.iter().all(|i| i.owner_id == rx.profile.steader_id));
+                    prop_assert_eq!(new_tx.inventory.len() + moved.len(), tx.inventory.len());
+                    (new_tx, new_rx)
                 }
+                Err(_) => (tx, rx),
+            };
+            prop_assert_eq!(count(&tx, &rx), tx_arches.len() + rx_arches.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::harness::Harness;
+    use hcor::{
+        wormhole::{ItemAsk, RudeNote},
+        Ask, Hackstead, IdentifiesSteader, Item, SteaderId,
+    };
+    use log::*;
+
+    #[actix_rt::test]
+    async fn throw() {
+        const ITEM_ARCHETYPE: hcor::config::ArchetypeHandle = 0;
+        const ITEM_SPAWN_COUNT: usize = 10;
+
+        let h = Harness::start().await;
+        let bobstead = h.summon().await;
+        let evestead = h.summon().await;
+        let mut bob = h.wormhole(&bobstead).await;
+        let mut eve = h.wormhole(&evestead).await;
+        let (bob_id, eve_id) = (bobstead.steader_id(), evestead.steader_id());
+
+        let throw = |receiver: &Hackstead, items: &[Item]| {
+            Ask::Item(ItemAsk::Throw {
+                receiver_id: receiver.steader_id(),
+                item_ids: items.iter().map(|i| i.item_id).collect(),
+            })
+        };
+        let owners = |item: &Item| -> Vec<SteaderId> {
+            item.ownership_log
+                .iter()
+                .map(|o| o.logged_owner_id)
+                .collect()
+        };
+        let receipt = |n: RudeNote| match n {
+            RudeNote::ItemThrowReceipt { from, items } => Some((from, items)),
+            _ => None,
+        };
+
+        // spawn eve items and verify that they log her as the owner
+        let items = eve.spawn(ITEM_ARCHETYPE, ITEM_SPAWN_COUNT).await;
+        for item in &items {
+            assert_eq!(
+                owners(item),
+                vec![eve_id],
+                "freshly spawned item doesn't log only eve as its owner: {:#?}",
+                item
+            );
+        }
+
+        // give items to bob
+        let ask_id = eve.ask(throw(&bobstead, &items)).await;
+        assert!(eve.asked(ask_id).await.err().is_none());
+        let (from, items) = bob.rude(receipt).await;
+        assert_eq!(from, eve_id);
+
+        // verify the items log eve then bob as the owners
+        let bobstead = h.spy(&bobstead).await.unwrap();
+        let evestead = h.spy(&evestead).await.unwrap();
+        for item in &items {
+            assert!(
+                !evestead.has_item(item),
+                "eve still has an item she gave away: {:#?}",
+                item
+            );
+            assert!(
+                bobstead.has_item(item),
+                "bob doesn't have an item he was transferred: {:#?}",
+                item
+            );
+            assert_eq!(
+                owners(item),
+                vec![eve_id, bob_id],
+                "spawned then traded item doesn't log eve then bob as its owners: {:#?}",
+                item
+            );
+        }
 
-                evestead.slaughter().await.unwrap();
-            });
-        });
+        // give one last item back to eve
+        let ask_id = bob.ask(throw(&evestead, &items[items.len() - 1..])).await;
+        assert!(bob.asked(ask_id).await.err().is_none());
+        let (_, mut returned) = eve.rude(receipt).await;
+        let item = returned.pop().unwrap();
+        assert!(
+            returned.is_empty(),
+            "bob only gave one item, which was taken from the list already"
+        );
+        assert_eq!(
+            owners(&item),
+            vec![eve_id, bob_id, eve_id],
+            "malformed ownership log for spawned(eve), given(bob), then given(eve) item",
+        );
+
+        // make sure all throws still fail when the items are of mixed ownership
+        // (the items are of mixed ownership now because the last one belongs to eve again)
+        let ask_id = bob.ask(throw(&evestead, &items)).await;
+        match bob.asked(ask_id).await.err() {
+            Some(e) => info!(
+                "received error as expected trying to give items of mixed ownership: {}",
+                e
+            ),
+            None => panic!("unexpectedly able to give away items of mixed ownership"),
+        }
+
+        let ask_id = eve.ask(throw(&evestead, &[item])).await;
+        match eve.asked(ask_id).await.err() {
+            Some(e) => info!("received error as expected trying to self-give: {}", e),
+            None => panic!("unexpectedly able to give item to self"),
+        }
+
+        h.stop().await;
+    }
+
+    #[actix_rt::test]
+    async fn throws_through_rest_have_a_cooldown() {
+        use crate::{config::Cooldowns, World};
+        use hcor::wormhole::AskedNote;
+
+        let h = Harness::start_with(World::real(), |c| {
+            c.cooldowns = Cooldowns::parse(&["Item.Throw=1/60".to_string()]).unwrap();
+        })
+        .await;
+        let bobstead = h.summon().await;
+        let alicestead = h.summon().await;
+        let mut bob = h.wormhole(&bobstead).await;
+        let _alice = h.wormhole(&alicestead).await;
+
+        let items = bob.spawn(0, 2).await;
+        let throw = |item: &hcor::Item| {
+            h.post::<AskedNote>(
+                "item/throw",
+                &serde_json::json!({
+                    "steader_id": bobstead.steader_id(),
+                    "receiver_id": alicestead.steader_id(),
+                    "item_ids": [item.item_id],
+                }),
+            )
+        };
 
-        t1.join().unwrap();
-        t2.join().unwrap();
+        assert!(throw(&items[0]).await.unwrap().err().is_none());
+        let error = match throw(&items[1]).await.unwrap().err() {
+            Some(e) => e.to_string(),
+            None => panic!("threw a second time within a minute of the first"),
+        };
+        assert!(error.contains("on cooldown"), "unexpected error: {}", error);
+        h.stop().await;
     }
 }
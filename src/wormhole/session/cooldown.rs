@@ -0,0 +1,166 @@
+//! When each steader may next make the kinds of Ask that `Config::cooldowns` puts limits on,
+//! i.e. so that an egg can only be hatched once a minute, however slowly it's asked for.
+//!
+//! Unlike a `Quota`, this is part of the steader's game, so it's saved alongside their hackstead.
+//! It's saved as how much longer each use counts against its cooldown, much as timers are saved
+//! as how much longer they have to go, so cooldowns don't wear off while the server is down.
+use crate::config::{self, Cooldown};
+use hcor::TileId;
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// How many seconds each recent use has left to count against its cooldown, by what it's counted
+/// against, as stored on disk.
+pub type Remaining = HashMap<String, Vec<f64>>;
+
+/// The rules from `Config::cooldowns`, and the recent uses of each.
+#[derive(Clone, Default)]
+pub struct Cooldowns {
+    rules: config::Cooldowns,
+    /// When each recent use stops counting, by the kind of Ask, or for cooldowns kept for each
+    /// plant, the kind of Ask and the tile the plant is on.
+    uses: HashMap<String, Vec<Instant>>,
+}
+
+impl Cooldowns {
+    /// Picks up where `remaining` left off, as of `now`.
+    pub fn restore(rules: config::Cooldowns, remaining: Remaining, now: Instant) -> Self {
+        let uses = remaining
+            .into_iter()
+            .map(|(key, secs)| {
+                let ends = secs
+                    .into_iter()
+                    .filter(|s| s.is_finite() && *s > 0.0)
+                    .map(|s| now + Duration::from_secs_f64(s))
+                    .collect();
+                (key, ends)
+            })
+            .collect();
+        Self { rules, uses }
+    }
+
+    /// What's left of each cooldown as of `now`, to be restored later.
+    pub fn remaining(&self, now: Instant) -> Remaining {
+        self.uses
+            .iter()
+            .map(|(key, ends)| {
+                let secs = ends
+                    .iter()
+                    .filter(|end| **end > now)
+                    .map(|end| end.duration_since(now).as_secs_f64())
+                    .collect::<Vec<_>>();
+                (key.clone(), secs)
+            })
+            .filter(|(_, secs)| !secs.is_empty())
+            .collect()
+    }
+
+    /// Counts an Ask of this kind, made on the plant on `tile` if there is one, against its
+    /// cooldown, unless it's already been used up.
+    pub fn use_up(
+        &mut self,
+        kind: &'static str,
+        tile: Option<TileId>,
+        now: Instant,
+    ) -> Result<(), OnCooldown> {
+        let Cooldown {
+            uses,
+            per,
+            per_plant,
+        } = match self.rules.rule(kind) {
+            Some(rule) => rule,
+            None => return Ok(()),
+        };
+        let key = match tile {
+            Some(tile) if per_plant => format!("{}@{}", kind, tile.0),
+            _ => kind.to_string(),
+        };
+
+        let ends = self.uses.entry(key).or_default();
+        ends.retain(|end| *end > now);
+        if ends.len() >= uses {
+            let soonest = ends.iter().min().copied().unwrap_or(now);
+            return Err(OnCooldown {
+                kind,
+                remaining: soonest.duration_since(now),
+            });
+        }
+        ends.push(now + per);
+        Ok(())
+    }
+
+    /// Forgets the uses counted against the cooldowns kept for the plant on `tile`.
+    pub fn forget_plant(&mut self, tile: TileId) {
+        let suffix = format!("@{}", tile.0);
+        self.uses.retain(|key, _| !key.ends_with(&suffix));
+    }
+}
+
+/// Why an Ask couldn't be made yet.
+#[derive(Debug)]
+pub struct OnCooldown {
+    pub kind: &'static str,
+    /// How long until another may be made.
+    pub remaining: Duration,
+}
+
+impl fmt::Display for OnCooldown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is on cooldown for another {:.1} seconds",
+            self.kind,
+            self.remaining.as_secs_f64()
+        )
+    }
+}
+
+impl std::error::Error for OnCooldown {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uses_count_until_their_cooldown_wears_off() {
+        let rules = config::Cooldowns::parse(&[
+            "Item.Hatch=2/60".to_string(),
+            "Plant.Rub@plant=1/60".to_string(),
+        ])
+        .unwrap();
+        let start = Instant::now();
+        let mut cooldowns = Cooldowns::restore(rules.clone(), Remaining::new(), start);
+        let (tile, other_tile) = (TileId(uuid::Uuid::new_v4()), TileId(uuid::Uuid::new_v4()));
+
+        cooldowns.use_up("Item.Hatch", None, start).unwrap();
+        let later = start + Duration::from_secs(20);
+        cooldowns.use_up("Item.Hatch", None, later).unwrap();
+        let on_cooldown = cooldowns.use_up("Item.Hatch", None, later).unwrap_err();
+        assert_eq!(on_cooldown.remaining, Duration::from_secs(40));
+
+        // each plant keeps its own count, and kinds of Ask without a rule are never limited
+        cooldowns.use_up("Plant.Rub", Some(tile), start).unwrap();
+        cooldowns
+            .use_up("Plant.Rub", Some(other_tile), start)
+            .unwrap();
+        assert!(cooldowns.use_up("Plant.Rub", Some(tile), start).is_err());
+        // a new plant on the same tile starts over
+        cooldowns.forget_plant(tile);
+        cooldowns.use_up("Plant.Rub", Some(tile), start).unwrap();
+        for _ in 0..10 {
+            cooldowns.use_up("Item.Throw", None, start).unwrap();
+        }
+
+        // what's left of each cooldown survives being saved and restored
+        let saved = cooldowns.remaining(later);
+        let mut restored = Cooldowns::restore(rules, saved, start);
+        let on_cooldown = restored.use_up("Item.Hatch", None, start).unwrap_err();
+        assert_eq!(on_cooldown.remaining, Duration::from_secs(40));
+        restored
+            .use_up("Item.Hatch", None, start + Duration::from_secs(40))
+            .unwrap();
+    }
+}
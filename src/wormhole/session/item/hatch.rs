@@ -1,4 +1,5 @@
 use super::SessSend;
+use crate::wormhole::session::cooldown::OnCooldown;
 use hcor::{id, item, Item, ItemId};
 use std::fmt;
 
@@ -6,6 +7,7 @@ use std::fmt;
 pub enum Error {
     NoSuch(id::NoSuch),
     NotConfigured(Item),
+    Cooldown(OnCooldown),
 }
 use Error::*;
 
@@ -15,6 +17,12 @@ impl From<id::NoSuch> for Error {
     }
 }
 
+impl From<OnCooldown> for Error {
+    fn from(oc: OnCooldown) -> Error {
+        Error::Cooldown(oc)
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "couldn't spawn items: ")?;
@@ -25,18 +33,20 @@ impl fmt::Display for Error {
                 "provided item {}, which, as a {}[{}], is not configured to be hatched",
                 i.item_id, i.name, i.archetype_handle
             ),
+            Cooldown(oc) => write!(f, "{}", oc),
         }
     }
 }
 
 pub fn hatch(ss: &mut SessSend, item_id: ItemId) -> Result<Vec<Item>, Error> {
+    ss.cool_down("Item.Hatch", None)?;
     let item = ss.take_item(item_id)?;
     let hatch_table = item
         .hatch_table
         .as_ref()
         .ok_or_else(|| NotConfigured(item.clone()))?;
 
-    let items = hcor::config::spawn(&hatch_table, &mut rand::thread_rng())
+    let items = hcor::config::spawn(&hatch_table, &mut ss.rng)
         .map(|item_name| {
             Item::from_archetype(
                 hcor::CONFIG.find_possession(&item_name)?,
@@ -53,61 +63,74 @@ pub fn hatch(ss: &mut SessSend, item_id: ItemId) -> Result<Vec<Item>, Error> {
     Ok(items)
 }
 
-#[cfg(all(test, feature = "hcor_client"))]
+#[cfg(test)]
 mod test {
     #[actix_rt::test]
     /// NOTE: requires that hatchable and unhatchable items exist in the config!
     /// relies on item/spawn!
-    async fn hatch() -> hcor::ClientResult<()> {
-        use hcor::Hackstead;
+    async fn hatch() {
+        use crate::harness::Harness;
+        use hcor::{
+            wormhole::{AskedNote, ItemAsk},
+            Ask,
+        };
         use log::*;
 
-        // attempt to establish logging, do nothing if it fails
-        // (it probably fails because it's already been established in another test)
-        drop(pretty_env_logger::try_init());
-
-        let mut bobstead = Hackstead::register().await?;
+        let h = Harness::start().await;
+        let bobstead = h.summon().await;
+        let mut wormhole = h.wormhole(&bobstead).await;
 
         debug!("finding prequisites in config...");
         let unhatchable_arch = hcor::CONFIG
             .possession_archetypes
             .iter()
-            .find(|x| x.hatch_table.is_none())
+            .position(|x| x.hatch_table.is_none())
             .expect("no unhatchable items in config?");
         let hatchable_arch = hcor::CONFIG
             .possession_archetypes
             .iter()
-            .find(|x| x.hatch_table.is_some())
-            .expect("no unhatchable items in config?");
+            .position(|x| x.hatch_table.is_some())
+            .expect("no hatchable items in config?");
 
         debug!("to prepare, we need to spawn bob hatchable and unhatchable items.");
-        let unhatchable_item = unhatchable_arch.spawn().await?;
-        let hatchable_item = hatchable_arch.spawn().await?;
-        bobstead = Hackstead::fetch(&bobstead).await?;
+        let unhatchable_item = wormhole.spawn(unhatchable_arch, 1).await.remove(0);
+        let hatchable_item = wormhole.spawn(hatchable_arch, 1).await.remove(0);
+        let bobstead = h.spy(&bobstead).await.unwrap();
+
+        let hatch = |item: &hcor::Item| {
+            Ask::Item(ItemAsk::Hatch {
+                hatchable_item_id: item.item_id,
+            })
+        };
 
         debug!("let's start off by hatching the unhatchable and making sure that doesn't work.");
-        match unhatchable_item.hatch().await {
-            Ok(items) => panic!("unhatchable item unexpectedly hatched into: {:#?}", items),
-            Err(e) => info!(
+        let ask_id = wormhole.ask(hatch(&unhatchable_item)).await;
+        match wormhole.asked(ask_id).await {
+            AskedNote::ItemHatchResult(Err(e)) => info!(
                 "received error as expected upon attempting to hatch unhatchable item: {}",
                 e
             ),
+            other => panic!("unhatchable item unexpectedly hatched: {:#?}", other),
         }
         assert_eq!(
             bobstead.inventory.len(),
-            Hackstead::fetch(&bobstead).await?.inventory.len(),
+            h.spy(&bobstead).await.unwrap().inventory.len(),
             "failing to hatch modified inventory item count somehow",
         );
 
         debug!("great, now let's try actually hatching something hatchable!");
-        let hatched_items = hatchable_item.hatch().await?;
+        let ask_id = wormhole.ask(hatch(&hatchable_item)).await;
+        let hatched_items = match wormhole.asked(ask_id).await {
+            AskedNote::ItemHatchResult(Ok(items)) => items,
+            other => panic!("couldn't hatch hatchable item: {:#?}", other),
+        };
 
         debug!(
             "let's make sure bob's inventory grew proportionally \
           to the amount of items hatching produced"
         );
         let starting_inventory = bobstead.inventory.clone();
-        let new_inventory = Hackstead::fetch(&bobstead).await?.inventory;
+        let new_inventory = h.spy(&bobstead).await.unwrap().inventory;
         assert_eq!(
             hatched_items.len(),
             (new_inventory.len() - (starting_inventory.len() - 1)),
@@ -120,21 +143,61 @@ mod test {
         );
 
         debug!("okay, but can we hatch the already hatched item?");
-        match hatchable_item.hatch().await {
-            Ok(items) => panic!(
-                "pretty sure I ain't supposed to be able \
-                to hatch this twice, got: {:#?}",
-                items
-            ),
-            Err(e) => info!(
+        let ask_id = wormhole.ask(hatch(&hatchable_item)).await;
+        match wormhole.asked(ask_id).await {
+            AskedNote::ItemHatchResult(Err(e)) => info!(
                 "got error as expected from hatching already hatched item: {}",
                 e
             ),
+            other => panic!(
+                "pretty sure I ain't supposed to be able \
+                to hatch this twice, got: {:#?}",
+                other
+            ),
         }
 
-        debug!("kill bob so he's not left in the database");
-        bobstead.slaughter().await?;
+        h.stop().await;
+    }
+
+    #[actix_rt::test]
+    async fn hatching_has_a_cooldown() {
+        use crate::{config::Cooldowns, harness::Harness, World};
+        use hcor::{wormhole::ItemAsk, Ask};
+
+        let h = Harness::start_with(World::real(), |c| {
+            c.cooldowns = Cooldowns::parse(&["Item.Hatch=1/60".to_string()]).unwrap();
+        })
+        .await;
+        let bobstead = h.summon().await;
+        let mut wormhole = h.wormhole(&bobstead).await;
 
-        Ok(())
+        let hatchable = hcor::CONFIG
+            .possession_archetypes
+            .iter()
+            .position(|x| x.hatch_table.is_some())
+            .expect("no hatchable items in config?");
+        let eggs = wormhole.spawn(hatchable, 2).await;
+        let hatch = |egg: &hcor::Item| {
+            Ask::Item(ItemAsk::Hatch {
+                hatchable_item_id: egg.item_id,
+            })
+        };
+
+        let ask_id = wormhole.ask(hatch(&eggs[0])).await;
+        assert!(wormhole.asked(ask_id).await.err().is_none());
+        let ask_id = wormhole.ask(hatch(&eggs[1])).await;
+        let error = match wormhole.asked(ask_id).await.err() {
+            Some(e) => e.to_string(),
+            None => panic!("hatched a second item within a minute of the first"),
+        };
+        assert!(error.contains("on cooldown"), "unexpected error: {}", error);
+
+        // the item that couldn't be hatched yet wasn't used up
+        let stored = h.spy(&bobstead).await.unwrap();
+        assert!(stored
+            .inventory
+            .iter()
+            .any(|i| i.item_id == eggs[1].item_id));
+        h.stop().await;
     }
 }
@@ -21,11 +21,19 @@ pub(super) fn handle_ask(ss: &mut SessSend, ask: ItemAsk) -> HandledAskKind {
             receiver_id,
             item_ids,
         } => {
-            return HandledAskKind::ServerRelinquish(super::NoteEnvelope::new(server::ThrowItems {
-                sender_id: ss.hackstead.profile.steader_id,
-                receiver_id,
-                item_ids,
-            }))
+            if let Err(oc) = ss.cool_down("Item.Throw", None) {
+                return HandledAskKind::Direct(ItemThrowResult(Err(format!(
+                    "couldn't throw items: {}",
+                    oc
+                ))));
+            }
+            return HandledAskKind::ServerRelinquish(super::NoteEnvelope::new(
+                server::ThrowItems {
+                    sender_id: ss.hackstead.profile.steader_id,
+                    receiver_id,
+                    item_ids,
+                },
+            ));
         }
         Hatch { hatchable_item_id } => ItemHatchResult(strerr(hatch(ss, hatchable_item_id))),
     })
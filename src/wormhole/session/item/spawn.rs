@@ -38,21 +38,22 @@ pub fn spawn(ss: &mut SessSend, item_conf: usize, amount: usize) -> Result<Vec<I
     Ok(items)
 }
 
-#[cfg(all(test, features = "hcor_client"))]
+#[cfg(test)]
 mod test {
+    const ITEM_ARCHETYPE: hcor::config::ArchetypeHandle = 0;
+    const ITEM_SPAWN_COUNT: usize = 10;
+
     #[actix_rt::test]
     /// NOTE: requires that at least one item exists in the config!
-    async fn spawn() -> hcor::ClientResult<()> {
-        use super::test::{ITEM_ARCHETYPE, ITEM_SPAWN_COUNT};
+    async fn spawn() {
+        use crate::harness::Harness;
         use hcor::Hackstead;
         use log::*;
 
-        // attempt to establish logging, do nothing if it fails
-        // (it probably fails because it's already been established in another test)
-        drop(pretty_env_logger::try_init());
-
         debug!("create bob's stead");
-        let mut bobstead = Hackstead::register().await?;
+        let h = Harness::start().await;
+        let bobstead = h.summon().await;
+        let mut wormhole = h.wormhole(&bobstead).await;
 
         // we'll need to keep track of how many items we have to see if spawning works.
         fn count_relevant_items(hackstead: &Hackstead) -> usize {
@@ -65,10 +66,10 @@ mod test {
         let starting_item_count = count_relevant_items(&bobstead);
 
         debug!("spawn bob some items and refresh his stead");
-        let items = bobstead
-            .spawn_items(ITEM_ARCHETYPE, ITEM_SPAWN_COUNT)
-            .await?;
-        bobstead = Hackstead::fetch(&bobstead).await?;
+        let items = wormhole
+            .spawn(ITEM_ARCHETYPE as usize, ITEM_SPAWN_COUNT)
+            .await;
+        let bobstead = h.spy(&bobstead).await.unwrap();
 
         debug!("make sure those new items are in there");
         assert_eq!(
@@ -86,9 +87,6 @@ mod test {
             );
         }
 
-        debug!("kill bob so he's not left in the database");
-        bobstead.slaughter().await?;
-
-        Ok(())
+        h.stop().await;
     }
 }
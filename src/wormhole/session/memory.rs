@@ -0,0 +1,226 @@
+//! What came of the Asks a steader has made recently, so that a client resending one after a
+//! flaky connection is told what came of it again, rather than having it done twice.
+//!
+//! Every Session for a steader, wormhole, relay or headless, shares the same `Memory`, which the
+//! Server keeps for them even after they've all ended, so that it outlives the connection
+//! a client is resending from. Anything older than `Config::ask_retention` is forgotten.
+use crate::wormhole::protocol::{Inbound, Outbound};
+use hcor::{wormhole::AskMessage, Ask};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A `Memory`, as shared between a steader's Sessions and the Server.
+pub type SharedMemory = Arc<Mutex<Memory>>;
+
+/// Tells Asks apart. Two messages with the same `ask_id` but different Asks aren't the same
+/// message sent twice, so the Asks themselves are part of this.
+#[derive(PartialEq)]
+struct Key {
+    ask_id: usize,
+    asks: Vec<Ask>,
+    batch: bool,
+}
+
+impl Key {
+    fn of(inbound: &Inbound) -> Self {
+        match inbound {
+            Inbound::Ask(AskMessage { ask, ask_id }) => Key {
+                ask_id: *ask_id,
+                asks: vec![ask.clone()],
+                batch: false,
+            },
+            Inbound::Batch(batch) => Key {
+                ask_id: batch.ask_id,
+                asks: batch.asks.clone(),
+                batch: true,
+            },
+        }
+    }
+}
+
+struct Answer {
+    key: Key,
+    /// When the message was first received.
+    at: Instant,
+    /// `None` while it's still being handled, i.e. while a throw is waiting on the Server.
+    outbound: Option<Outbound>,
+}
+
+/// What, if anything, has already come of a message.
+#[derive(Debug)]
+pub enum Recall {
+    /// We haven't seen it before, or not recently enough to remember; it should be handled.
+    New,
+    /// It's still being handled, and will be answered once it has been.
+    Pending,
+    /// It's been handled already, and this was the answer.
+    Answered(Outbound),
+}
+
+/// The messages a steader has sent recently, oldest first, and what came of them.
+#[derive(Default)]
+pub struct Memory {
+    answers: VecDeque<Answer>,
+}
+
+impl Memory {
+    /// Looks for what came of this message, after forgetting anything older than `retention`.
+    /// If it's `New`, it's remembered as pending from now on, until it's `answer`ed.
+    pub fn recall(&mut self, inbound: &Inbound, now: Instant, retention: Duration) -> Recall {
+        self.forget_before(now, retention);
+
+        let key = Key::of(inbound);
+        match self.answers.iter().find(|a| a.key == key) {
+            Some(Answer {
+                outbound: Some(outbound),
+                ..
+            }) => Recall::Answered(outbound.clone()),
+            Some(_) => Recall::Pending,
+            None => {
+                self.answers.push_back(Answer {
+                    key,
+                    at: now,
+                    outbound: None,
+                });
+                Recall::New
+            }
+        }
+    }
+
+    /// Forgets a message that was never handled after all, so that it's new if it's sent again.
+    pub fn forget(&mut self, inbound: &Inbound) {
+        let key = Key::of(inbound);
+        self.answers
+            .retain(|a| !(a.key == key && a.outbound.is_none()));
+    }
+
+    /// Records what came of this message, if it's still pending. Another message pending under
+    /// the same `ask_id`, but with different Asks, is left alone.
+    pub fn answer(&mut self, inbound: &Inbound, outbound: &Outbound) {
+        let key = Key::of(inbound);
+        if let Some(answer) = self
+            .answers
+            .iter_mut()
+            .find(|a| a.key == key && a.outbound.is_none())
+        {
+            answer.outbound = Some(outbound.clone());
+        }
+    }
+
+    /// Whether there's nothing left to remember.
+    pub fn is_empty(&self) -> bool {
+        self.answers.is_empty()
+    }
+
+    /// Forgets every message received more than `retention` before `now`.
+    pub fn forget_before(&mut self, now: Instant, retention: Duration) {
+        while let Some(oldest) = self.answers.front() {
+            if now.saturating_duration_since(oldest.at) <= retention {
+                break;
+            }
+            self.answers.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hcor::{wormhole::AskedNote, Note};
+
+    #[test]
+    fn replays_are_recalled_until_forgotten() {
+        let snort = |ask_id, xp| {
+            Inbound::Ask(AskMessage {
+                ask: Ask::KnowledgeSnort { xp },
+                ask_id,
+            })
+        };
+        let snorted = |ask_id, xp| {
+            Outbound::Note(Note::Asked {
+                ask_id,
+                note: AskedNote::KnowledgeSnortResult(Ok(xp)),
+            })
+        };
+        let retention = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut memory = Memory::default();
+
+        assert!(matches!(
+            memory.recall(&snort(1, 10), start, retention),
+            Recall::New
+        ));
+        assert!(matches!(
+            memory.recall(&snort(1, 10), start, retention),
+            Recall::Pending
+        ));
+
+        memory.answer(&snort(1, 10), &snorted(1, 10));
+        match memory.recall(&snort(1, 10), start + retention, retention) {
+            Recall::Answered(Outbound::Note(Note::Asked { ask_id: 1, .. })) => {}
+            other => panic!("expected the answer to be recalled, got {:?}", other),
+        }
+
+        // the same ask_id with a different Ask is a different message
+        assert!(matches!(
+            memory.recall(&snort(1, 5), start, retention),
+            Recall::New
+        ));
+
+        // one that was never handled is new again once forgotten, without losing any answers
+        memory.forget(&snort(1, 5));
+        assert!(matches!(
+            memory.recall(&snort(1, 5), start, retention),
+            Recall::New
+        ));
+        assert!(matches!(
+            memory.recall(&snort(1, 10), start, retention),
+            Recall::Answered(_)
+        ));
+
+        let later = start + retention + Duration::from_secs(1);
+        assert!(matches!(
+            memory.recall(&snort(1, 10), later, retention),
+            Recall::New
+        ));
+    }
+
+    #[test]
+    fn answers_go_to_the_message_they_answer() {
+        let snort = |xp| {
+            Inbound::Ask(AskMessage {
+                ask: Ask::KnowledgeSnort { xp },
+                ask_id: 1,
+            })
+        };
+        let snorted = |xp| {
+            Outbound::Note(Note::Asked {
+                ask_id: 1,
+                note: AskedNote::KnowledgeSnortResult(Ok(xp)),
+            })
+        };
+        let retention = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut memory = Memory::default();
+
+        // two different messages pending under the same ask_id, answered out of order
+        memory.recall(&snort(10), start, retention);
+        memory.recall(&snort(5), start, retention);
+        memory.answer(&snort(5), &snorted(5));
+
+        assert!(matches!(
+            memory.recall(&snort(10), start, retention),
+            Recall::Pending
+        ));
+        match memory.recall(&snort(5), start, retention) {
+            Recall::Answered(Outbound::Note(Note::Asked {
+                note: AskedNote::KnowledgeSnortResult(Ok(5)),
+                ..
+            })) => {}
+            other => panic!("expected the second message's answer, got {:?}", other),
+        }
+    }
+}
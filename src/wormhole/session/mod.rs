@@ -3,20 +3,42 @@ use std::time::Instant;
 use actix::{
     dev::Envelope, Actor, ActorContext, Addr, AsyncContext, Handler, MailboxError, StreamHandler,
 };
+use actix_web::web;
 use actix_web_actors::ws;
 use futures_channel::oneshot;
 use log::*;
 
-use super::server::{self, Server};
+use super::{
+    protocol::{BatchMessage, FramedEdit, Inbound, Outbound, ProtocolNote},
+    server::{self, Server},
+    Version,
+};
+use crate::{
+    audit::{self, Cause},
+    metrics, Config, World,
+};
 use hcor::{
-    wormhole::{AskMessage, AskedNote, EditNote, CLIENT_TIMEOUT, HEARTBEAT_INTERVAL},
+    wormhole::{AskMessage, AskedNote, EditNote},
     Hackstead, IdentifiesSteader, Note, UPDATE_INTERVAL,
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
+pub mod cooldown;
+use cooldown::Cooldowns;
 mod item;
+pub mod memory;
+use memory::{Recall, SharedMemory};
+pub mod quota;
+use quota::{Limited, Quota, SharedQuota};
 mod ticker;
 mod tile;
 use tile::plant;
+pub use tile::plant::{level, modifiers};
+
+#[cfg(test)]
+mod prop;
+#[cfg(test)]
+mod test;
 
 /// Which opening to the wormhole are they making use of?
 #[derive(Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -25,6 +47,60 @@ pub enum Orifice {
     Bincode,
     /// Text messages, encoded in JSON
     Json,
+    /// Binary messages, encoded in MessagePack, with structs as maps so that fields are named
+    MessagePack,
+    /// Binary messages, encoded in CBOR
+    #[serde(rename = "CBOR", alias = "Cbor")]
+    Cbor,
+}
+impl Orifice {
+    /// Every orifice there is.
+    pub const ALL: [Orifice; 4] = [
+        Orifice::Bincode,
+        Orifice::Json,
+        Orifice::MessagePack,
+        Orifice::Cbor,
+    ];
+
+    /// A short name for this orifice, for metrics and logs.
+    pub fn name(self) -> &'static str {
+        match self {
+            Orifice::Bincode => "Bincode",
+            Orifice::Json => "Json",
+            Orifice::MessagePack => "MessagePack",
+            Orifice::Cbor => "CBOR",
+        }
+    }
+
+    /// Whether this orifice deals in binary websockets messages, rather than text.
+    pub fn is_binary(self) -> bool {
+        self != Orifice::Json
+    }
+
+    /// Whether messages in this format can be read without knowing what they're meant to be.
+    pub fn is_self_describing(self) -> bool {
+        self != Orifice::Bincode
+    }
+
+    /// Encodes something in this orifice's format.
+    pub fn encode<T: serde::Serialize>(self, t: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Orifice::Bincode => bincode::serialize(t).map_err(|e| e.to_string()),
+            Orifice::Json => serde_json::to_vec(t).map_err(|e| e.to_string()),
+            Orifice::MessagePack => rmp_serde::to_vec_named(t).map_err(|e| e.to_string()),
+            Orifice::Cbor => serde_cbor::to_vec(t).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Decodes something from this orifice's format.
+    pub fn decode<T: serde::de::DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Orifice::Bincode => bincode::deserialize(bytes).map_err(|e| e.to_string()),
+            Orifice::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Orifice::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+            Orifice::Cbor => serde_cbor::from_slice(bytes).map_err(|e| e.to_string()),
+        }
+    }
 }
 
 /// An individual user's session with the Server. It contains an address to that server so
@@ -33,55 +109,236 @@ pub enum Orifice {
 /// to the user.
 pub struct Session {
     hackstead: Hackstead,
+    /// When the client last showed signs of life; for headless sessions, when they were last
+    /// asked to do anything.
     heartbeat: Instant,
     orifice: Orifice,
+    /// The version of the wormhole protocol this session's client speaks.
+    version: Version,
     server: Addr<Server>,
     ticker: ticker::Ticker,
+    config: web::Data<Config>,
+    world: web::Data<World>,
+    /// This session's dice; each `SessSend` gets its own, seeded from these.
+    rng: StdRng,
+    /// Set when this user's hackstead is removed, so that we don't save it back to disk.
+    slaughtered: bool,
+    /// How many malformed messages this client has sent us.
+    strikes: usize,
+    /// If `Some`, this session has no client of its own, and ends after this long without asks.
+    idle_timeout: Option<std::time::Duration>,
+    /// What came of the messages this steader has sent recently.
+    memory: SharedMemory,
+    /// How many more Asks may be made through this session.
+    quota: Quota,
+    /// How many more Asks this steader may make, through any session.
+    steader_quota: SharedQuota,
+    /// When this steader may next make the Asks that have cooldowns.
+    cooldowns: Cooldowns,
 }
 type SessionContext = ws::WebsocketContext<Session>;
 
 impl Session {
     /// Constructs a new wormhole session from the uuid of the user who owns this session
     /// and an address which points to the Server.
-    pub fn new(mut hackstead: Hackstead, srv: &Addr<Server>, orifice: Orifice) -> Self {
+    pub fn new(
+        mut hackstead: Hackstead,
+        srv: &Addr<Server>,
+        orifice: Orifice,
+        version: Version,
+        config: web::Data<Config>,
+        world: web::Data<World>,
+    ) -> Self {
+        let now = world.now();
+        let remaining = crate::hackstead::fs_get_cooldowns(&config.data_dir, &hackstead)
+            .unwrap_or_else(|e| {
+                error!(
+                    "couldn't read cooldowns for {}: {}",
+                    hackstead.steader_id(),
+                    e
+                );
+                Default::default()
+            });
         Self {
-            heartbeat: Instant::now(),
+            heartbeat: now,
+            cooldowns: Cooldowns::restore(config.cooldowns.clone(), remaining, now),
             server: srv.clone(),
-            ticker: ticker::Ticker::new(&mut hackstead),
+            ticker: ticker::Ticker::new(&mut hackstead, now),
             orifice,
+            version,
+            rng: world.rng(hackstead.steader_id()),
             hackstead,
+            config,
+            world,
+            slaughtered: false,
+            strikes: 0,
+            idle_timeout: None,
+            memory: SharedMemory::default(),
+            quota: Quota::new("connection"),
+            steader_quota: Quota::shared("steader"),
+        }
+    }
+
+    /// Shares a `Quota` with the steader's other Sessions, so that they can't make any more Asks
+    /// between them than they could through any one.
+    pub fn rationed(mut self, steader_quota: SharedQuota) -> Self {
+        self.steader_quota = steader_quota;
+        self
+    }
+
+    /// Shares a `Memory` with the steader's other Sessions, past and future, rather than
+    /// remembering only what was asked through this one.
+    pub fn remembering(mut self, memory: SharedMemory) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Makes this a session without a client of its own, that ends once it's gone
+    /// `idle_timeout` without being asked to do anything.
+    pub fn headless(mut self, idle_timeout: std::time::Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// The orifice this session's client is making use of.
+    pub fn orifice(&self) -> Orifice {
+        self.orifice
+    }
+
+    /// A place to make changes to this session's hackstead, with dice of its own.
+    fn sess_send(&mut self, hackstead: Hackstead) -> SessSend {
+        let mut ss = SessSend::new(hackstead);
+        ss.rng = StdRng::seed_from_u64(self.rng.gen());
+        ss.cooldowns = Some(self.cooldowns.clone());
+        ss.now = self.world.now();
+        ss.config = self.config.clone();
+        ss
+    }
+
+    /// This session's hackstead, complete with the timers the ticker is keeping track of.
+    fn stead(&self) -> Hackstead {
+        let mut hs = self.hackstead.clone();
+        hs.timers = self.ticker.timers.clone();
+        hs
+    }
+
+    /// Saves this session's hackstead, along with its cooldowns, to disk, unless it has been
+    /// slaughtered.
+    fn persist(&self) {
+        if self.slaughtered {
+            return;
+        }
+
+        let steader_id = self.hackstead.steader_id();
+        let remaining = self.cooldowns.remaining(self.world.now());
+        match crate::hackstead::fs_put_stead_with_cooldowns(
+            &self.config.data_dir,
+            &self.stead(),
+            &remaining,
+        ) {
+            Ok(()) => debug!("saved hackstead for {}", steader_id),
+            Err(e) => error!("couldn't save hackstead for {}: {}", steader_id, e),
+        }
+    }
+
+    /// `ProtocolNote`s are always sent as JSON text, regardless of our orifice.
+    /// Clients too old to know about them don't get them at all; anything they need to know
+    /// about the connection itself, they'll find out from how it's closed.
+    fn send_protocol_note(&self, ctx: &mut SessionContext, note: &ProtocolNote) {
+        if !self.version.knows_protocol_notes() {
+            debug!(
+                "not sending {:?} to a client speaking protocol version {}",
+                note, self.version
+            );
+            return;
+        }
+
+        match note.to_json() {
+            Ok(json) => ctx.text(json),
+            Err(e) => error!("couldn't Json serialize ProtocolNote: {}", e),
         }
     }
 
     fn send_note(&self, ctx: &mut SessionContext, note: &Note) {
-        match self.orifice {
-            Orifice::Json => match serde_json::to_string(note) {
-                Ok(json) => ctx.text(json),
-                Err(e) => error!("couldn't Json serialize Note: {}", e),
-            },
-            Orifice::Bincode => match bincode::serialize(note) {
-                Ok(bytes) => ctx.binary(bytes),
-                Err(e) => error!("couldn't Bincode serialize Note: {}", e),
-            },
+        // serialized just as `Outbound::Note` is, without cloning the Note.
+        #[derive(serde::Serialize)]
+        enum Wrapper<'a> {
+            Note(&'a Note),
+        }
+
+        if self.version.wraps_messages() {
+            self.send(ctx, &Wrapper::Note(note))
+        } else {
+            self.send(ctx, note)
+        }
+    }
+
+    /// Only clients speaking a version of the protocol that wraps messages can be sent an
+    /// `Outbound` that isn't a Note.
+    fn send_outbound(&self, ctx: &mut SessionContext, outbound: &Outbound) {
+        if self.version.wraps_messages() {
+            self.send(ctx, outbound)
+        }
+    }
+
+    fn send<T: serde::Serialize>(&self, ctx: &mut SessionContext, message: &T) {
+        let sent = match self.orifice {
+            Orifice::Json => serde_json::to_string(message)
+                .map(|json| ctx.text(json))
+                .map_err(|e| e.to_string()),
+            binary => binary.encode(message).map(|bytes| ctx.binary(bytes)),
+        };
+        if let Err(e) = sent {
+            error!("couldn't {} serialize message: {}", self.orifice.name(), e)
         }
     }
 
+    /// Keeps or throws away the changes made to `sess_send`. If they were made answering a
+    /// message from the client, `answering` is that message, so that what came of a relinquished
+    /// Ask is remembered for it once the Server is done.
     fn apply_change(
         &mut self,
         ctx: &mut SessionContext,
         sss: SessSendSubmit,
         sess_send: SessSend,
+        answering: Option<Inbound>,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()>>> {
         match sss {
             SessSendSubmit::Submit => sess_send.submit(self, ctx),
-            SessSendSubmit::Cancel(note) => self.send_note(ctx, &note),
+            SessSendSubmit::Cancel(note) => {
+                let error = match &note {
+                    Note::Asked { note, .. } => note.err().map(|e| e.to_string()),
+                    _ => None,
+                };
+                audit::record(
+                    &self.config,
+                    self.hackstead.steader_id(),
+                    audit::Event::Refused {
+                        cause: sess_send.cause,
+                        error: error.unwrap_or_else(|| "no reason given".to_string()),
+                    },
+                );
+                self.send_note(ctx, &note)
+            }
             SessSendSubmit::ServerRelinquishAsk { msg, ask_id } => {
+                // a throw counts against its cooldown whether or not the Server manages it
+                if let Some(cooldowns) = sess_send.cooldowns {
+                    self.cooldowns = cooldowns;
+                }
                 let server = self.server.clone();
                 let session = ctx.address();
+                let memory = self.memory.clone();
                 return Box::pin(async move {
                     async move {
-                        let note = msg.send(&server).await;
-                        session.send(SendNote(Note::Asked { note, ask_id })).await
+                        let note = Note::Asked {
+                            note: msg.send(&server).await,
+                            ask_id,
+                        };
+                        if let Some(inbound) = &answering {
+                            let answer = Outbound::Note(note.clone());
+                            memory.lock().unwrap().answer(inbound, &answer);
+                        }
+                        session.send(SendNote(note)).await
                     }
                     .await
                     .unwrap_or_else(|e| error!("couldn't relinquish task to server: {}", e));
@@ -92,33 +349,215 @@ impl Session {
         Box::pin(async move { () })
     }
 
+    /// Reads an Ask or a batch of them out of a message and handles it,
+    /// or tells the client what was wrong with it.
+    fn receive(&mut self, ctx: &mut SessionContext, bytes: &[u8]) {
+        let inbound = match decode_inbound(self.orifice, self.version, bytes) {
+            Ok(inbound) => inbound,
+            Err(e) => return self.strike(ctx, recover_ask_id(self.orifice, bytes), e),
+        };
+        let recalled = self.memory.lock().unwrap().recall(
+            &inbound,
+            self.world.now(),
+            self.config.ask_retention,
+        );
+        // resends are answered from memory, so only what's new costs anything
+        if let Recall::New = recalled {
+            if let Err(limited) = self.charge(&costs(&inbound), true) {
+                self.memory.lock().unwrap().forget(&inbound);
+                return self.refuse(ctx, &inbound, limited);
+            }
+        }
+        match (recalled, inbound) {
+            (Recall::New, Inbound::Ask(ask)) => self.spawn_ask_handler(ctx, ask),
+            (Recall::New, Inbound::Batch(batch)) => self.handle_batch(ctx, batch),
+            // whoever sent it the first time gets the answer once it's ready
+            (Recall::Pending, _) => {
+                metrics::REPLAYED.with_label_values(&["pending"]).inc();
+                debug!("ignoring a message sent again while it's still being handled");
+            }
+            (Recall::Answered(outbound), _) => {
+                metrics::REPLAYED.with_label_values(&["answered"]).inc();
+                match outbound {
+                    Outbound::Note(note) => self.send_note(ctx, &note),
+                    batched => self.send_outbound(ctx, &batched),
+                }
+            }
+        }
+    }
+
+    /// Takes what a message costs from this steader's quota, and, if it came through this
+    /// session's own client, from this session's.
+    fn charge(
+        &mut self,
+        costs: &[(&'static str, usize)],
+        through_client: bool,
+    ) -> Result<(), Limited> {
+        let mut steader_quota = self.steader_quota.lock().unwrap();
+        let mut quotas = vec![(&mut *steader_quota, &self.config.rate_limits)];
+        if through_client {
+            quotas.push((&mut self.quota, &self.config.conn_rate_limits));
+        }
+
+        let charged = quota::charge(&mut quotas, costs, self.world.now());
+        if let Err(limited) = &charged {
+            metrics::RATE_LIMITED
+                .with_label_values(&[limited.kind, limited.scope])
+                .inc();
+        }
+        charged
+    }
+
+    /// Lets the client know that nothing was done about a message, because they'd made too many
+    /// Asks too quickly. Clients too old for `ProtocolNote`s are told through the usual `Asked`.
+    fn refuse(&self, ctx: &mut SessionContext, inbound: &Inbound, limited: Limited) {
+        debug!("{}: {}", self.hackstead.steader_id(), limited);
+        match inbound {
+            _ if self.version.knows_protocol_notes() => self.send_protocol_note(
+                ctx,
+                &ProtocolNote::RateLimited {
+                    ask_id: inbound.ask_id(),
+                    ask: limited.kind.to_string(),
+                    retry_after: limited.retry_after.as_secs_f64(),
+                },
+            ),
+            Inbound::Ask(AskMessage { ask, ask_id }) => self.send_note(
+                ctx,
+                &Note::Asked {
+                    ask_id: *ask_id,
+                    note: refusal(ask, limited.to_string()),
+                },
+            ),
+            // only clients that know about protocol notes can send batches
+            Inbound::Batch(_) => {}
+        }
+    }
+
+    /// Records what came of this message, in case it's sent again.
+    fn remember(&self, inbound: &Inbound, outbound: &Outbound) {
+        self.memory.lock().unwrap().answer(inbound, outbound);
+    }
+
+    /// The client sent us something we couldn't understand; let them know, and if they've done
+    /// that too many times, hang up on them.
+    fn strike(&mut self, ctx: &mut SessionContext, ask_id: Option<usize>, error: String) {
+        metrics::MALFORMED
+            .with_label_values(&[self.orifice.name()])
+            .inc();
+        self.strikes += 1;
+        let strikes_left = self.config.max_strikes.saturating_sub(self.strikes);
+        warn!(
+            "malformed message from {} ({} strikes left): {}",
+            self.hackstead.steader_id(),
+            strikes_left,
+            error
+        );
+
+        self.send_protocol_note(
+            ctx,
+            &ProtocolNote::Malformed {
+                ask_id,
+                error,
+                strikes_left,
+            },
+        );
+
+        if strikes_left == 0 {
+            ctx.close(Some(ws::CloseReason {
+                code: ws::CloseCode::Invalid,
+                description: Some("too many malformed messages".to_string()),
+            }));
+            ctx.stop();
+        }
+    }
+
     fn spawn_ask_handler(&mut self, ctx: &mut SessionContext, ask: AskMessage) {
-        let mut ss = SessSend::new(self.hackstead.clone());
-        actix::spawn(self.apply_change(ctx, handle_ask(&mut ss, ask).into(), ss))
+        let inbound = Inbound::Ask(AskMessage {
+            ask: ask.ask.clone(),
+            ask_id: ask.ask_id,
+        });
+        let mut ss = self.sess_send(self.hackstead.clone());
+        let handled = handle_ask(&mut ss, ask);
+        if let HandledAskKind::Direct(note) = &handled.kind {
+            let note = Note::Asked {
+                ask_id: handled.ask_id,
+                note: note.clone(),
+            };
+            self.remember(&inbound, &Outbound::Note(note));
+        }
+        actix::spawn(self.apply_change(ctx, handled.into(), ss, Some(inbound)))
+    }
+
+    /// Keeps the changes made by every Ask in a batch if none of them failed, or else none of
+    /// them, then tells the client which it was.
+    fn handle_batch(&mut self, ctx: &mut SessionContext, batch: BatchMessage) {
+        let ask_id = batch.ask_id;
+        let inbound = Inbound::Batch(batch.clone());
+        let mut ss = self.sess_send(self.hackstead.clone());
+        let (committed, results) = run_batch(&mut ss, batch);
+
+        if committed {
+            ss.submit(self, ctx);
+        } else {
+            let error = results
+                .last()
+                .and_then(|note| note.err().map(|e| e.to_string()));
+            audit::record(
+                &self.config,
+                self.hackstead.steader_id(),
+                audit::Event::Refused {
+                    cause: ss.cause,
+                    error: error.unwrap_or_else(|| "no reason given".to_string()),
+                },
+            );
+        }
+
+        let batched = Outbound::Batched {
+            ask_id,
+            committed,
+            results,
+        };
+        self.remember(&inbound, &batched);
+        self.send_outbound(ctx, &batched);
     }
 
     /// This function is responsible for sending messages to the client to assure that we're still
     /// active and operational, and checking that the client has sent us a similar message recently
     /// to assure that they're still online. If they haven't sent any such message in a certain
     /// amount of time, we drop their connection and their session ends.
-    fn heartbeat(ctx: &mut SessionContext) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            if Instant::now().duration_since(act.heartbeat) > CLIENT_TIMEOUT {
-                warn!("Websocket Client heartbeat failed, disconnecting!");
-                ctx.stop();
-            } else {
-                ctx.ping(b"");
+    /// Headless sessions have nobody to ping, and instead end once they've been idle too long.
+    fn heartbeat(&self, ctx: &mut SessionContext) {
+        ctx.run_interval(self.config.heartbeat_interval, |act, ctx| {
+            let quiet = act.world.now().duration_since(act.heartbeat);
+            match act.idle_timeout {
+                Some(idle) if quiet > idle => {
+                    info!(
+                        "headless session for {} idled out",
+                        act.hackstead.steader_id()
+                    );
+                    ctx.stop();
+                }
+                Some(_) => {}
+                None if quiet > act.config.client_timeout => {
+                    warn!("Websocket Client heartbeat failed, disconnecting!");
+                    ctx.stop();
+                }
+                None => ctx.ping(b""),
             }
         });
     }
 
+    /// Every `UPDATE_INTERVAL`, the ticker catches up with however much time our clock says has
+    /// passed; with a `ManualClock`, that's only as much as it's been advanced.
     #[allow(clippy::unused_self)]
     fn tick(&self, ctx: &mut SessionContext) {
-        ctx.run_interval(*UPDATE_INTERVAL, |act, ctx| {
-            let mut ticker = std::mem::take(&mut act.ticker);
-            ticker.tick(act, ctx);
-            act.ticker = ticker;
-        });
+        ctx.run_interval(*UPDATE_INTERVAL, |act, ctx| act.tick_now(ctx));
+    }
+
+    fn tick_now(&mut self, ctx: &mut SessionContext) {
+        let mut ticker = std::mem::take(&mut self.ticker);
+        ticker.tick(self, ctx);
+        self.ticker = ticker;
     }
 }
 
@@ -130,21 +569,41 @@ impl Actor for Session {
     /// Notes.
     fn started(&mut self, ctx: &mut Self::Context) {
         // probably important to kick these off as soon as possible
-        Session::heartbeat(ctx);
+        self.heartbeat(ctx);
         self.tick(ctx);
 
         info!("session begins!");
+        audit::record(
+            &self.config,
+            self.hackstead.steader_id(),
+            audit::Event::Snapshot {
+                hackstead: Box::new(self.stead()),
+            },
+        );
 
         let addr = ctx.address();
         self.server
             .do_send(server::Connect(self.hackstead.steader_id(), addr));
     }
 
-    fn stopping(&mut self, _: &mut Self::Context) -> actix::Running {
-        // notify server
+    fn stopping(&mut self, ctx: &mut Self::Context) -> actix::Running {
         info!("ending session!");
-        self.server
-            .do_send(server::Disconnect(self.hackstead.steader_id()));
+        self.persist();
+        self.steader_quota
+            .lock()
+            .unwrap()
+            .refill(&self.config.rate_limits, self.world.now());
+        // so that the Server can tell whether there's anything left worth remembering
+        self.memory
+            .lock()
+            .unwrap()
+            .forget_before(self.world.now(), self.config.ask_retention);
+
+        // notify server
+        self.server.do_send(server::Disconnect(
+            self.hackstead.steader_id(),
+            ctx.address(),
+        ));
         actix::Running::Stop
     }
 }
@@ -170,21 +629,149 @@ impl Handler<GetStead> for Session {
     type Result = Hackstead;
 
     fn handle(&mut self, GetStead: GetStead, _: &mut Self::Context) -> Self::Result {
-        let mut hs = self.hackstead.clone();
-        hs.timers = self.ticker.timers.clone();
-        hs
+        self.stead()
+    }
+}
+
+/// The server is going down; save everything, let the client know, and hang up.
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub struct Shutdown;
+
+impl Handler<Shutdown> for Session {
+    type Result = ();
+
+    fn handle(&mut self, Shutdown: Shutdown, ctx: &mut Self::Context) {
+        // the server is waiting on this to return, so we can't leave saving up to `stopping`.
+        self.persist();
+        self.send_protocol_note(ctx, &ProtocolNote::Shutdown);
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Away,
+            description: Some("server shutting down".to_string()),
+        }));
+        ctx.stop();
+    }
+}
+
+/// Save this session's hackstead to disk right away, rather than waiting for it to end.
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub struct Persist;
+
+impl Handler<Persist> for Session {
+    type Result = ();
+
+    fn handle(&mut self, Persist: Persist, _: &mut Self::Context) {
+        self.persist();
+    }
+}
+
+/// If this session is headless, save its hackstead and end it, so that a wormhole can take its
+/// place. Returns whether it did.
+#[derive(actix::Message)]
+#[rtype(result = "bool")]
+pub struct Surrender;
+
+impl Handler<Surrender> for Session {
+    type Result = bool;
+
+    fn handle(&mut self, Surrender: Surrender, ctx: &mut Self::Context) -> bool {
+        if self.idle_timeout.is_none() {
+            return false;
+        }
+
+        // whoever's taking over reads the hackstead from disk as soon as this returns
+        self.persist();
+        ctx.stop();
+        true
+    }
+}
+
+/// Hang up on this user, for the reason given.
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub struct Kick(pub String);
+
+impl Handler<Kick> for Session {
+    type Result = ();
+
+    fn handle(&mut self, Kick(reason): Kick, ctx: &mut Self::Context) {
+        info!("kicking {}: {}", self.hackstead.steader_id(), reason);
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some(reason),
+        }));
+        ctx.stop();
     }
 }
 
+/// This user's hackstead has been removed; hang up without saving it back to disk.
+/// Returns the hackstead as it was the moment before it was slaughtered.
 #[derive(actix::Message)]
-#[rtype(result = "Result<AskedNote, MailboxError>")]
+#[rtype(result = "Hackstead")]
+pub struct Slaughter;
+
+impl Handler<Slaughter> for Session {
+    type Result = Hackstead;
+
+    fn handle(&mut self, Slaughter: Slaughter, ctx: &mut Self::Context) -> Self::Result {
+        self.slaughtered = true;
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Normal,
+            description: Some("hackstead slaughtered".to_string()),
+        }));
+        ctx.stop();
+        self.stead()
+    }
+}
+
+/// Replace this user's hackstead wholesale, i.e. to roll it back to an earlier version.
+/// Its timers replace those the ticker is keeping track of, and the client is sent an Edit
+/// describing the changes as though it were any other. Returns the hackstead as it now stands.
+#[derive(actix::Message)]
+#[rtype(result = "Hackstead")]
+pub struct Restore(pub Hackstead, pub Cause);
+
+impl Handler<Restore> for Session {
+    type Result = Hackstead;
+
+    fn handle(&mut self, Restore(mut hs, cause): Restore, ctx: &mut Self::Context) -> Hackstead {
+        hs.local_version = self.hackstead.local_version;
+        self.ticker = ticker::Ticker::new(&mut hs, self.world.now());
+
+        let mut ss = self.sess_send(hs);
+        ss.cause = cause;
+        ss.submit(self, ctx);
+
+        let stead = self.stead();
+        audit::record(
+            &self.config,
+            stead.steader_id(),
+            audit::Event::Snapshot {
+                hackstead: Box::new(stead.clone()),
+            },
+        );
+        self.persist();
+        stead
+    }
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "Result<AskedNote, crate::ServiceError>")]
 pub struct DoAsk(pub hcor::Ask);
 
 impl Handler<DoAsk> for Session {
-    type Result = actix::ResponseFuture<Result<AskedNote, MailboxError>>;
+    type Result = actix::ResponseFuture<Result<AskedNote, crate::ServiceError>>;
 
     fn handle(&mut self, DoAsk(ask): DoAsk, ctx: &mut Self::Context) -> Self::Result {
-        let mut ss = SessSend::new(self.hackstead.clone());
+        if self.idle_timeout.is_some() {
+            self.heartbeat = self.world.now();
+        }
+        // these don't come through this session's client, if it even has one
+        if let Err(limited) = self.charge(&[(ask_kind(&ask), 1)], false) {
+            return Box::pin(async move { Err(limited.into()) });
+        }
+        let mut ss = self.sess_send(self.hackstead.clone());
         let HandledAsk { kind, ask_id } = handle_ask(&mut ss, AskMessage { ask, ask_id: 1337 });
 
         match kind {
@@ -197,6 +784,7 @@ impl Handler<DoAsk> for Session {
                     }
                     .into(),
                     ss,
+                    None,
                 );
                 Box::pin(async move {
                     f.await;
@@ -204,6 +792,10 @@ impl Handler<DoAsk> for Session {
                 })
             }
             HandledAskKind::ServerRelinquish(msg) => {
+                // a throw counts against its cooldown whether or not the Server manages it
+                if let Some(cooldowns) = ss.cooldowns {
+                    self.cooldowns = cooldowns;
+                }
                 let server = self.server.clone();
                 Box::pin(async move { Ok(msg.send(&server).await) })
             }
@@ -213,7 +805,7 @@ impl Handler<DoAsk> for Session {
 
 #[derive(actix::Message)]
 #[rtype(result = "Result<(), ()>")]
-pub struct ChangeStead<F: FnOnce(&mut SessSend) -> SessSendSubmit + Send + 'static>(F);
+pub struct ChangeStead<F: FnOnce(&mut SessSend) -> SessSendSubmit + Send + 'static>(pub F);
 
 impl<F: FnOnce(&mut SessSend) -> SessSendSubmit + Send + 'static> Handler<ChangeStead<F>>
     for Session
@@ -225,8 +817,8 @@ impl<F: FnOnce(&mut SessSend) -> SessSendSubmit + Send + 'static> Handler<Change
         ChangeStead(change): ChangeStead<F>,
         ctx: &mut Self::Context,
     ) -> Self::Result {
-        let mut sess_send = SessSend::new(self.hackstead.clone());
-        let f = self.apply_change(ctx, change(&mut sess_send), sess_send);
+        let mut sess_send = self.sess_send(self.hackstead.clone());
+        let f = self.apply_change(ctx, change(&mut sess_send), sess_send, None);
         Box::pin(async move {
             f.await;
             Ok(())
@@ -234,6 +826,20 @@ impl<F: FnOnce(&mut SessSend) -> SessSendSubmit + Send + 'static> Handler<Change
     }
 }
 
+/// Catch the ticker up with the clock right away, rather than waiting for the next
+/// `UPDATE_INTERVAL`; handy after advancing a `ManualClock`.
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub struct Tick;
+
+impl Handler<Tick> for Session {
+    type Result = ();
+
+    fn handle(&mut self, Tick: Tick, ctx: &mut Self::Context) {
+        self.tick_now(ctx);
+    }
+}
+
 #[derive(actix::Message)]
 #[rtype(result = "()")]
 pub struct StartTimer(hcor::plant::Timer);
@@ -260,23 +866,22 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Session {
                 ctx.stop();
             }
             Ok(Ping(msg)) => {
-                self.heartbeat = Instant::now();
+                self.heartbeat = self.world.now();
                 ctx.pong(&msg);
             }
-            Ok(Pong(_)) => self.heartbeat = Instant::now(),
-            Ok(Text(t)) if self.orifice == Orifice::Json => {
-                // we're more lenient with deserialization errors than websocket errors
-                match serde_json::from_str(&t) {
-                    Ok(ask) => self.spawn_ask_handler(ctx, ask),
-                    Err(e) => error!("couldn't deserialize JSON AskMessage: {}", e),
-                }
-            }
-            Ok(Binary(b)) if self.orifice == Orifice::Bincode => {
-                // we're more lenient with deserialization errors than websocket errors
-                match bincode::deserialize(&b) {
-                    Ok(ask) => self.spawn_ask_handler(ctx, ask),
-                    Err(e) => error!("couldn't deserialize Bincode AskMessage: {}", e),
-                }
+            Ok(Pong(_)) => self.heartbeat = self.world.now(),
+            // we're more lenient with deserialization errors than websocket errors
+            Ok(Text(t)) if !self.orifice.is_binary() => self.receive(ctx, t.as_bytes()),
+            Ok(Binary(b)) if self.orifice.is_binary() => self.receive(ctx, &b),
+            Ok(wrong @ Text(_)) | Ok(wrong @ Binary(_)) => {
+                let kind = |text| if text { "text" } else { "binary" };
+                let error = format!(
+                    "got a {} message, but the {} orifice only takes {} messages",
+                    kind(matches!(wrong, Text(_))),
+                    self.orifice.name(),
+                    kind(!self.orifice.is_binary()),
+                );
+                self.strike(ctx, None, error)
             }
             Ok(Close(msg)) => {
                 info!("closing websockets: {:#?}", msg);
@@ -287,6 +892,100 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Session {
     }
 }
 
+/// Reads an AskMessage out of a message that came through the given orifice.
+pub fn decode_ask(orifice: Orifice, bytes: &[u8]) -> Result<AskMessage, String> {
+    orifice
+        .decode(bytes)
+        .map_err(|e| format!("couldn't deserialize {} AskMessage: {}", orifice.name(), e))
+}
+
+/// Reads whatever a client speaking the given version sent through the given orifice.
+/// Before version 3, that can only be an AskMessage.
+pub fn decode_inbound(orifice: Orifice, version: Version, bytes: &[u8]) -> Result<Inbound, String> {
+    if version.wraps_messages() {
+        orifice
+            .decode(bytes)
+            .map_err(|e| format!("couldn't deserialize {} Inbound: {}", orifice.name(), e))
+    } else {
+        decode_ask(orifice, bytes).map(Inbound::Ask)
+    }
+}
+
+/// Digs an `ask_id` out of a message that couldn't be read, if it has one, either at the top
+/// level or inside the `Inbound` variant wrapping it, however the orifice tags variants.
+/// Only self-describing formats have the structure needed for this;
+/// a broken Bincode message is a lost cause.
+fn recover_ask_id(orifice: Orifice, bytes: &[u8]) -> Option<usize> {
+    use serde_json::Value;
+    use std::convert::TryFrom;
+
+    if !orifice.is_self_describing() {
+        return None;
+    }
+    let msg = orifice.decode::<serde_json::Value>(bytes).ok()?;
+    let ask_id = msg.get("ask_id").or_else(|| match &msg {
+        Value::Object(tagged) if tagged.len() == 1 => tagged.values().next()?.get("ask_id"),
+        Value::Array(tagged) if tagged.len() == 2 => tagged.last()?.get("ask_id"),
+        _ => None,
+    })?;
+    ask_id.as_u64().and_then(|id| usize::try_from(id).ok())
+}
+
+/// A short name for a kind of Ask, i.e. "Item.Hatch", for metrics and logs.
+pub fn ask_kind(ask: &hcor::Ask) -> &'static str {
+    use hcor::wormhole::{Ask::*, ItemAsk, PlantAsk};
+
+    match ask {
+        KnowledgeSnort { .. } => "KnowledgeSnort",
+        TileSummon { .. } => "TileSummon",
+        Plant(PlantAsk::Summon { .. }) => "Plant.Summon",
+        Plant(PlantAsk::Slaughter { .. }) => "Plant.Slaughter",
+        Plant(PlantAsk::Craft { .. }) => "Plant.Craft",
+        Plant(PlantAsk::Rub { .. }) => "Plant.Rub",
+        Plant(PlantAsk::Cleanse { .. }) => "Plant.Cleanse",
+        Item(ItemAsk::Spawn { .. }) => "Item.Spawn",
+        Item(ItemAsk::Throw { .. }) => "Item.Throw",
+        Item(ItemAsk::Hatch { .. }) => "Item.Hatch",
+    }
+}
+
+/// What a message costs, as a number of Asks of each kind.
+fn costs(inbound: &Inbound) -> Vec<(&'static str, usize)> {
+    let asks = match inbound {
+        Inbound::Ask(AskMessage { ask, .. }) => std::slice::from_ref(ask),
+        Inbound::Batch(BatchMessage { asks, .. }) => asks.as_slice(),
+    };
+
+    let mut costs: Vec<(&'static str, usize)> = vec![];
+    for kind in asks.iter().map(ask_kind) {
+        match costs.iter_mut().find(|(k, _)| *k == kind) {
+            Some((_, n)) => *n += 1,
+            None => costs.push((kind, 1)),
+        }
+    }
+    costs
+}
+
+/// The response to an Ask that failed before it could be handled, with this error.
+pub fn refusal(ask: &hcor::Ask, error: String) -> AskedNote {
+    use hcor::wormhole::{Ask::*, AskedNote::*, ItemAsk, PlantAsk};
+
+    match ask {
+        KnowledgeSnort { .. } => KnowledgeSnortResult(Err(error)),
+        TileSummon { .. } => TileSummonResult(Err(error)),
+        Plant(PlantAsk::Summon { .. }) => PlantSummonResult(Err(error)),
+        // crafts aren't answered with anything else, for now
+        Plant(PlantAsk::Slaughter { .. }) | Plant(PlantAsk::Craft { .. }) => {
+            PlantSlaughterResult(Err(error))
+        }
+        Plant(PlantAsk::Rub { .. }) => PlantRubStartResult(Err(error)),
+        Plant(PlantAsk::Cleanse { .. }) => PlantCleanseResult(Err(error)),
+        Item(ItemAsk::Spawn { .. }) => ItemSpawnResult(Err(error)),
+        Item(ItemAsk::Throw { .. }) => ItemThrowResult(Err(error)),
+        Item(ItemAsk::Hatch { .. }) => ItemHatchResult(Err(error)),
+    }
+}
+
 pub fn strerr<T, E: ToString>(r: Result<T, E>) -> Result<T, String> {
     r.map_err(|e| e.to_string())
 }
@@ -305,16 +1004,56 @@ pub fn strerr<T, E: ToString>(r: Result<T, E>) -> Result<T, String> {
 /// you can send them messages directly if need be.
 pub struct SessSend {
     pub pending_timers: Vec<hcor::plant::Timer>,
+    /// Timers to be stopped before they finish, i.e. for an effect that's been cleansed.
+    pub cancelled_timers: Vec<(hcor::TileId, hcor::plant::TimerKind)>,
     pub pending_notes: Vec<Note>,
     pub hackstead: Hackstead,
+    /// Why these edits are being made, for the audit log.
+    pub cause: Cause,
+    /// Dice for anything left up to chance, i.e. what an item hatches into.
+    pub rng: StdRng,
+    /// The steader's cooldowns, if they're being kept track of; `None` leaves them as they are.
+    pub cooldowns: Option<Cooldowns>,
+    /// When these edits are being made, as far as cooldowns are concerned.
+    pub now: Instant,
+    /// The rules these edits are made by, i.e. how many effects a plant may have.
+    pub config: web::Data<Config>,
 }
 impl SessSend {
     /// Create a new SessSend
     pub fn new(hackstead: Hackstead) -> Self {
         Self {
             pending_timers: vec![],
+            cancelled_timers: vec![],
             pending_notes: vec![],
             hackstead,
+            cause: Cause::Unspecified,
+            rng: StdRng::from_entropy(),
+            cooldowns: None,
+            now: Instant::now(),
+            config: web::Data::new(Config::default()),
+        }
+    }
+
+    /// Counts an Ask of this kind, made on the plant on `tile` if there is one, against its
+    /// cooldown, failing if that's already been used up.
+    pub fn cool_down(
+        &mut self,
+        kind: &'static str,
+        tile: Option<hcor::TileId>,
+    ) -> Result<(), cooldown::OnCooldown> {
+        let now = self.now;
+        match &mut self.cooldowns {
+            Some(cooldowns) => cooldowns.use_up(kind, tile, now),
+            None => Ok(()),
+        }
+    }
+
+    /// Forgets the cooldowns kept for the plant on this tile, i.e. because it's been slaughtered,
+    /// so that the next plant there doesn't inherit them.
+    pub fn forget_cooldowns(&mut self, tile: hcor::TileId) {
+        if let Some(cooldowns) = &mut self.cooldowns {
+            cooldowns.forget_plant(tile);
         }
     }
 
@@ -328,6 +1067,11 @@ impl SessSend {
         self.pending_timers.push(t);
     }
 
+    /// Schedule the timer of this kind on this tile to be stopped when this SessSend is submitted.
+    pub fn cancel_timer(&mut self, tile_id: hcor::TileId, kind: hcor::plant::TimerKind) {
+        self.cancelled_timers.push((tile_id, kind));
+    }
+
     pub async fn submit_afar(self, addr: &Addr<Session>) -> Result<(), MailboxError> {
         addr.send(ChangeStead(|ss| {
             *ss = self;
@@ -348,26 +1092,61 @@ impl SessSend {
             hackstead: mut new,
             mut pending_notes,
             pending_timers,
+            cancelled_timers,
+            cause,
+            cooldowns,
             ..
         } = self;
 
         let old = session.hackstead.clone();
         assert_eq!(new.local_version, old.local_version);
-        pending_notes.push(Note::Edit(match session.orifice {
+        let diff = serde_json::to_value(&Diff::serializable(&old, &new)).unwrap();
+        // MessagePack and CBOR have no EditNote of their own, so clients that know better are sent
+        // an Outbound::Edit once the rest of the notes are out, just as the EditNote would have been.
+        let framed = match session.orifice {
+            Orifice::MessagePack if session.version.frames_edits() => {
+                Some(FramedEdit::MessagePack(diff.clone()))
+            }
+            Orifice::Cbor if session.version.frames_edits() => Some(FramedEdit::Cbor(diff.clone())),
             Orifice::Json => {
-                EditNote::Json(serde_json::to_string(&Diff::serializable(&old, &new)).unwrap())
+                pending_notes.push(Note::Edit(EditNote::Json(
+                    serde_json::to_string(&Diff::serializable(&old, &new)).unwrap(),
+                )));
+                None
             }
-            Orifice::Bincode => {
-                EditNote::Bincode(bincode::serialize(&Diff::serializable(&old, &new)).unwrap())
+            // older clients were always sent their diffs as bytes in their own format
+            binary => {
+                pending_notes.push(Note::Edit(EditNote::Bincode(
+                    binary.encode(&Diff::serializable(&old, &new)).unwrap(),
+                )));
+                None
             }
-        }));
+        };
         new.local_version += 1;
+        audit::record(
+            &session.config,
+            new.steader_id(),
+            audit::Event::Edit {
+                cause,
+                diff,
+                local_version: new.local_version,
+            },
+        );
         session.hackstead = new;
+        if let Some(cooldowns) = cooldowns {
+            session.cooldowns = cooldowns;
+        }
 
         for n in pending_notes {
             session.send_note(ctx, &n);
         }
+        if let Some(edit) = framed {
+            session.send_outbound(ctx, &Outbound::Edit(edit));
+        }
 
+        for (tile_id, kind) in cancelled_timers {
+            session.ticker.cancel(tile_id, kind);
+        }
         for t in pending_timers {
             session.ticker.start(t);
         }
@@ -436,6 +1215,92 @@ pub struct HandledAsk {
     kind: HandledAskKind,
 }
 
+/// What handling an Ask would do to a hackstead, worked out without a Session.
+pub struct Settled {
+    /// The hackstead as it would be afterwards.
+    pub hackstead: Hackstead,
+    /// Timers that would be started.
+    pub timers: Vec<hcor::plant::Timer>,
+    /// The response to the Ask, unless it would be relinquished to the Server.
+    pub note: Option<AskedNote>,
+}
+
+/// Handles an Ask against a hackstead by the rules in `config`, deciding whether to keep the
+/// changes just as a Session would, but without sending any Notes or starting any timers.
+/// The Ask counts against `cooldowns` whenever it would count against a Session's.
+pub fn settle(
+    hackstead: &Hackstead,
+    config: &Config,
+    cooldowns: &mut Cooldowns,
+    msg: AskMessage,
+) -> Settled {
+    let mut ss = SessSend::new(hackstead.clone());
+    ss.config = web::Data::new(config.clone());
+    ss.cooldowns = Some(cooldowns.clone());
+    let handled = handle_ask(&mut ss, msg);
+    let note = match &handled.kind {
+        HandledAskKind::Direct(note) => Some(note.clone()),
+        HandledAskKind::ServerRelinquish(_) => None,
+    };
+
+    let submit = SessSendSubmit::from(handled);
+    // a throw counts against its cooldown whether or not the Server manages it
+    if let SessSendSubmit::Submit | SessSendSubmit::ServerRelinquishAsk { .. } = submit {
+        if let Some(used) = ss.cooldowns.take() {
+            *cooldowns = used;
+        }
+    }
+
+    match submit {
+        SessSendSubmit::Submit => Settled {
+            hackstead: ss.hackstead,
+            timers: ss.pending_timers,
+            note,
+        },
+        _ => Settled {
+            hackstead: hackstead.clone(),
+            timers: vec![],
+            note,
+        },
+    }
+}
+
+/// Handles each Ask in a batch in turn on the same `SessSend`, stopping at the first to fail.
+/// Returns whether they all succeeded, alongside the response to each Ask that was handled.
+/// A single `Outbound::Batched` answers for all of them, so none of their `Note::Asked`s are sent.
+fn run_batch(
+    ss: &mut SessSend,
+    BatchMessage { asks, ask_id }: BatchMessage,
+) -> (bool, Vec<AskedNote>) {
+    use hcor::wormhole::{Ask::Item, AskedNote::ItemThrowResult, ItemAsk};
+
+    let mut results = Vec::with_capacity(asks.len());
+    for ask in asks.iter().cloned() {
+        let note = match ask {
+            Item(ItemAsk::Throw { .. }) => {
+                ItemThrowResult(Err("throws can't be part of a batch".to_string()))
+            }
+            ask => match handle_ask(ss, AskMessage { ask, ask_id }).kind {
+                HandledAskKind::Direct(note) => note,
+                HandledAskKind::ServerRelinquish(_) => {
+                    unreachable!("only throws are relinquished to the server")
+                }
+            },
+        };
+        let failed = note.err().is_some();
+        results.push(note);
+        if failed {
+            break;
+        }
+    }
+
+    let committed = results.iter().all(|note| note.err().is_none());
+    ss.pending_notes
+        .retain(|note| !matches!(note, Note::Asked { .. }));
+    ss.cause = Cause::Batch { ask_id, asks };
+    (committed, results)
+}
+
 /// If the ask fails for whatever reason, the `SessSend` is not submitted,
 /// and therefore no changes are made to the user's session,
 /// in the form of hackstead mutations or set timers.
@@ -448,6 +1313,12 @@ fn handle_ask(ss: &mut SessSend, AskMessage { ask, ask_id }: AskMessage) -> Hand
         ask_id,
         ask
     );
+    let started = Instant::now();
+    let kind_name = ask_kind(&ask);
+    ss.cause = Cause::Ask {
+        ask_id,
+        ask: ask.clone(),
+    };
 
     let kind = match ask {
         KnowledgeSnort { xp } => HandledAskKind::Direct(KnowledgeSnortResult(Ok({
@@ -464,7 +1335,11 @@ fn handle_ask(ss: &mut SessSend, AskMessage { ask, ask_id }: AskMessage) -> Hand
         )))),
     };
 
+    // asks relinquished to the server are measured once the server is done with them.
     if let HandledAskKind::Direct(note) = &kind {
+        let outcome = if note.err().is_none() { "ok" } else { "err" };
+        metrics::observe_ask(kind_name, outcome, started);
+
         ss.send_note(Note::Asked {
             ask_id,
             note: note.clone(),
@@ -0,0 +1,243 @@
+//! Property tests for Ask handling.
+use super::{
+    ask_kind, cooldown::Cooldowns, decode_ask, decode_inbound, recover_ask_id, settle, Orifice,
+};
+use crate::{
+    config::{Cooldowns as CooldownRules, EffectCaps, RubStackings},
+    wormhole::{
+        protocol::{BatchMessage, Inbound},
+        version,
+    },
+    Config,
+};
+use hcor::{
+    item, plant,
+    wormhole::{Ask, AskMessage, ItemAsk, PlantAsk},
+    Hackstead, Item, ItemId, TileId,
+};
+use proptest::{
+    collection::vec,
+    prelude::*,
+    sample::{select, Index},
+    test_runner::TestRunner,
+};
+use std::time::Instant;
+
+/// A new hackstead with one of every item, so that there's something for every Ask to act on.
+fn stocked() -> Hackstead {
+    let mut hs = Hackstead::new_user(None);
+    let owner = hs.profile.steader_id;
+    for a in 0..hcor::CONFIG.possession_archetypes.len() {
+        hs.inventory
+            .push(Item::from_archetype_handle(a, owner, item::Acquisition::spawned()).unwrap());
+    }
+    hs
+}
+
+/// Asks that refer to items and tiles the hackstead has, as well as ones that it doesn't.
+/// Throws are left out; they're relinquished to the Server, and are covered in `server::throw`.
+fn asks(hs: &Hackstead) -> impl Strategy<Value = Ask> {
+    let nobody = uuid::Uuid::new_v4();
+    let items: Vec<ItemId> = hs
+        .inventory
+        .iter()
+        .map(|i| i.item_id)
+        .chain(std::iter::once(ItemId(nobody)))
+        .collect();
+    let tiles: Vec<TileId> = hs
+        .land
+        .iter()
+        .map(|t| t.tile_id)
+        .chain(std::iter::once(TileId(nobody)))
+        .collect();
+    let (item, tile) = (select(items), select(tiles));
+    let archetypes = 0..hcor::CONFIG.possession_archetypes.len() + 1;
+
+    prop_oneof![
+        (0..1000usize).prop_map(|xp| Ask::KnowledgeSnort { xp }),
+        item.clone()
+            .prop_map(|tile_redeemable_item_id| Ask::TileSummon {
+                tile_redeemable_item_id
+            }),
+        (tile.clone(), item.clone()).prop_map(|(tile_id, seed_item_id)| Ask::Plant(
+            PlantAsk::Summon {
+                tile_id,
+                seed_item_id
+            }
+        )),
+        tile.clone()
+            .prop_map(|tile_id| Ask::Plant(PlantAsk::Slaughter { tile_id })),
+        (tile.clone(), 0..4usize).prop_map(|(tile_id, recipe_index)| Ask::Plant(PlantAsk::Craft {
+            tile_id,
+            recipe_index
+        })),
+        (tile.clone(), proptest::option::of(item.clone())).prop_map(
+            move |(tile_id, cleansing_item_id)| Ask::Plant(PlantAsk::Cleanse {
+                tile_id,
+                // nothing's been rubbed onto the stead these are made for, so no effect is there;
+                // see `aim` for cleansing effects that have been rubbed on since
+                effect_id: plant::EffectId(nobody),
+                cleansing_item_id
+            })
+        ),
+        (tile, item.clone()).prop_map(|(tile_id, rub_item_id)| Ask::Plant(PlantAsk::Rub {
+            tile_id,
+            rub_item_id
+        })),
+        (archetypes, 0..4usize).prop_map(|(item_archetype_handle, amount)| Ask::Item(
+            ItemAsk::Spawn {
+                item_archetype_handle,
+                amount
+            }
+        )),
+        item.prop_map(|hatchable_item_id| Ask::Item(ItemAsk::Hatch { hatchable_item_id })),
+    ]
+}
+
+/// The rules out of the box, as well as a few others that put limits on what Asks may do.
+fn rules() -> impl Strategy<Value = Config> {
+    let caps = select(vec!["*=off", "*=1", "*=2"]);
+    let stacking = select(vec!["*=stack", "*=refresh", "*=reject"]);
+    let cooldowns = select(vec![
+        vec![],
+        vec!["Plant.Rub@plant=1/60"],
+        vec!["TileSummon=1/60", "Item.Hatch=2/60", "Plant.Rub=3/60"],
+    ]);
+    let specs = |specs: Vec<&str>| specs.into_iter().map(String::from).collect::<Vec<_>>();
+
+    prop_oneof![
+        Just(Config::default()),
+        (caps, stacking, any::<bool>(), cooldowns).prop_map(
+            move |(caps, stacking, cleansers, cooldowns)| {
+                let mut config = Config::default();
+                config.effect_caps = EffectCaps::parse(&specs(vec![caps])).unwrap();
+                config.rub_stacking = RubStackings::parse(&specs(vec![stacking])).unwrap();
+                config.cooldowns = CooldownRules::parse(&specs(cooldowns)).unwrap();
+                if cleansers {
+                    config.cleansers = hcor::CONFIG
+                        .possession_archetypes
+                        .iter()
+                        .map(|a| a.name.clone())
+                        .collect();
+                }
+                config
+            }
+        ),
+    ]
+}
+
+/// Points a Cleanse at one of the effects that have been rubbed onto `hs` so far, if there are
+/// any, since there's no knowing what they'll be called until they've been rubbed on.
+fn aim(ask: Ask, hs: &Hackstead, which: Index) -> Ask {
+    let effects: Vec<plant::EffectId> = hs
+        .land
+        .iter()
+        .filter_map(|t| t.plant.as_ref())
+        .flat_map(|p| p.effects.iter().map(|e| e.effect_id))
+        .collect();
+
+    match ask {
+        Ask::Plant(PlantAsk::Cleanse {
+            tile_id,
+            cleansing_item_id,
+            ..
+        }) if !effects.is_empty() => Ask::Plant(PlantAsk::Cleanse {
+            tile_id,
+            effect_id: *which.get(&effects),
+            cleansing_item_id,
+        }),
+        ask => ask,
+    }
+}
+
+#[test]
+/// A failed Ask never changes the hackstead or leaves timers behind, whatever the rules; successful
+/// ones may.
+fn failed_asks_change_nothing() {
+    let start = stocked();
+
+    TestRunner::default()
+        .run(
+            &(rules(), vec((asks(&start), any::<Index>()), 1..16)),
+            |(config, asks)| {
+                let mut hs = start.clone();
+                let mut cooldowns = Cooldowns::restore(
+                    config.cooldowns.clone(),
+                    Default::default(),
+                    Instant::now(),
+                );
+
+                for (ask_id, (ask, which)) in asks.into_iter().enumerate() {
+                    let ask = aim(ask, &hs, which);
+                    let settled = settle(&hs, &config, &mut cooldowns, AskMessage { ask, ask_id });
+                    let note = settled.note.expect("only throws are relinquished");
+
+                    if note.err().is_some() {
+                        prop_assert_eq!(&settled.hackstead, &hs);
+                        prop_assert!(settled.timers.is_empty());
+                    }
+                    hs = settled.hackstead;
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+}
+
+#[test]
+/// Nothing that comes through the wormhole should be able to bring down a Session.
+fn garbage_asks_are_refused() {
+    TestRunner::default()
+        .run(&vec(any::<u8>(), 0..256), |bytes| {
+            for &orifice in &Orifice::ALL {
+                let _ = decode_ask(orifice, &bytes);
+                let _ = decode_inbound(orifice, version::CURRENT, &bytes);
+            }
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+/// Every orifice gives back the same AskMessage it was given, and those that can
+/// find an `ask_id` in a message do.
+fn asks_survive_every_orifice() {
+    let hs = stocked();
+
+    TestRunner::default()
+        .run(&(asks(&hs), any::<usize>()), |(ask, ask_id)| {
+            let batch = Inbound::Batch(BatchMessage {
+                asks: vec![ask.clone()],
+                ask_id,
+            });
+            let sent = AskMessage { ask, ask_id };
+            for &orifice in &Orifice::ALL {
+                let bytes = orifice.encode(&sent).unwrap();
+                let got = decode_ask(orifice, &bytes).unwrap();
+                prop_assert_eq!(
+                    serde_json::to_value(&got).unwrap(),
+                    serde_json::to_value(&sent).unwrap()
+                );
+                if orifice.is_self_describing() {
+                    prop_assert_eq!(recover_ask_id(orifice, &bytes), Some(ask_id));
+                    let wrapped = orifice.encode(&batch).unwrap();
+                    prop_assert_eq!(recover_ask_id(orifice, &wrapped), Some(ask_id));
+                }
+            }
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+/// Rate limits are configured by the names `ask_kind` gives, so each of them must be known.
+fn ask_kinds_can_be_rate_limited() {
+    let hs = stocked();
+
+    TestRunner::default()
+        .run(&asks(&hs), |ask| {
+            prop_assert!(crate::config::ASK_KINDS.contains(&ask_kind(&ask)));
+            Ok(())
+        })
+        .unwrap();
+}
@@ -0,0 +1,244 @@
+//! Token buckets limiting how often each kind of Ask may be made, so that a misbehaving client
+//! can't keep its Session, or the Server, busy with nothing but its requests.
+//!
+//! Every Session has a `Quota` of its own, limited by `Config::conn_rate_limits`, and shares
+//! another with the steader's other Sessions, limited by `Config::rate_limits`, which the Server
+//! keeps for them so that reconnecting doesn't refill it. A message is only handled if both can
+//! afford it, and if they can't, neither is charged.
+use crate::config::{Rate, RateLimits};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A `Quota`, as shared between a steader's Sessions and the Server.
+pub type SharedQuota = Arc<Mutex<Quota>>;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let earned = now.saturating_duration_since(self.updated).as_secs_f64() * rate.per_second;
+        self.tokens = (self.tokens + earned).min(rate.burst);
+        self.updated = now;
+    }
+
+    /// How long until `n` tokens could be taken. More than a `burst` of them can only be taken
+    /// from a full bucket, which is left owing the rest.
+    fn wait(&self, rate: Rate, n: f64) -> Duration {
+        let short = n.min(rate.burst) - self.tokens;
+        if short <= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(short / rate.per_second)
+        }
+    }
+}
+
+/// How many tokens are left for each kind of Ask.
+pub struct Quota {
+    /// Whose quota this is, for metrics and logs: "steader" or "connection".
+    scope: &'static str,
+    /// Kinds of Ask without a bucket haven't been made recently enough to have spent anything.
+    buckets: HashMap<&'static str, Bucket>,
+}
+
+impl Quota {
+    pub fn new(scope: &'static str) -> Self {
+        Self {
+            scope,
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn shared(scope: &'static str) -> SharedQuota {
+        Arc::new(Mutex::new(Self::new(scope)))
+    }
+
+    /// How long until `n` Asks of this kind could be made.
+    fn wait(
+        &mut self,
+        limits: &RateLimits,
+        kind: &'static str,
+        n: usize,
+        now: Instant,
+    ) -> Duration {
+        match (limits.rate(kind), self.buckets.get_mut(kind)) {
+            (Some(rate), Some(bucket)) => {
+                bucket.refill(rate, now);
+                bucket.wait(rate, n as f64)
+            }
+            _ => Duration::from_secs(0),
+        }
+    }
+
+    fn take(&mut self, limits: &RateLimits, kind: &'static str, n: usize, now: Instant) {
+        if let Some(rate) = limits.rate(kind) {
+            let bucket = self.buckets.entry(kind).or_insert(Bucket {
+                tokens: rate.burst,
+                updated: now,
+            });
+            bucket.tokens -= n as f64;
+        }
+    }
+
+    /// Forgets the buckets that have filled back up, which are no different from no bucket.
+    pub fn refill(&mut self, limits: &RateLimits, now: Instant) {
+        self.buckets.retain(|kind, bucket| match limits.rate(kind) {
+            Some(rate) => {
+                bucket.refill(rate, now);
+                bucket.tokens < rate.burst
+            }
+            None => false,
+        })
+    }
+
+    /// Whether every bucket is full.
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
+/// Why a message couldn't be handled yet.
+#[derive(Debug)]
+pub struct Limited {
+    /// The kind of Ask there have been too many of.
+    pub kind: &'static str,
+    /// "steader" or "connection".
+    pub scope: &'static str,
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for Limited {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "couldn't handle {} ask: too many have been made through this {}, \
+             try again in {:.1} seconds",
+            self.kind,
+            self.scope,
+            self.retry_after.as_secs_f64()
+        )
+    }
+}
+
+impl std::error::Error for Limited {}
+
+impl From<Limited> for crate::ServiceError {
+    fn from(limited: Limited) -> Self {
+        crate::ServiceError::TooManyRequests {
+            reason: limited.to_string(),
+            retry_after: limited.retry_after,
+        }
+    }
+}
+
+/// Takes what a message costs, as a number of Asks of each kind, from each of the quotas,
+/// so long as every one of them can afford it. Otherwise, nothing is taken from any of them.
+pub fn charge(
+    quotas: &mut [(&mut Quota, &RateLimits)],
+    costs: &[(&'static str, usize)],
+    now: Instant,
+) -> Result<(), Limited> {
+    let mut worst: Option<Limited> = None;
+    for (quota, limits) in quotas.iter_mut() {
+        for &(kind, n) in costs {
+            let retry_after = quota.wait(limits, kind, n, now);
+            if retry_after
+                > worst
+                    .as_ref()
+                    .map_or(Duration::from_secs(0), |l| l.retry_after)
+            {
+                worst = Some(Limited {
+                    kind,
+                    scope: quota.scope,
+                    retry_after,
+                });
+            }
+        }
+    }
+    if let Some(limited) = worst {
+        return Err(limited);
+    }
+
+    for (quota, limits) in quotas.iter_mut() {
+        for &(kind, n) in costs {
+            quota.take(limits, kind, n, now);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let limits = RateLimits::everything(2.0, 4.0);
+        let mut conn = Quota::new("connection");
+        let start = Instant::now();
+        let snort = [("KnowledgeSnort", 1)];
+
+        for _ in 0..4 {
+            charge(&mut [(&mut conn, &limits)], &snort, start).unwrap();
+        }
+        let limited = charge(&mut [(&mut conn, &limits)], &snort, start).unwrap_err();
+        assert_eq!(limited.kind, "KnowledgeSnort");
+        assert_eq!(limited.retry_after, Duration::from_millis(500));
+
+        // other kinds of Ask have buckets of their own
+        charge(&mut [(&mut conn, &limits)], &[("Item.Hatch", 1)], start).unwrap();
+
+        let later = start + Duration::from_millis(500);
+        charge(&mut [(&mut conn, &limits)], &snort, later).unwrap();
+
+        // a full bucket is forgotten
+        conn.refill(&limits, later + Duration::from_secs(2));
+        assert!(conn.is_empty());
+    }
+
+    #[test]
+    fn nothing_is_taken_unless_everyone_can_afford_it() {
+        let (loose, strict) = (
+            RateLimits::everything(1.0, 10.0),
+            RateLimits::everything(1.0, 1.0),
+        );
+        let mut steader = Quota::new("steader");
+        let mut conn = Quota::new("connection");
+        let now = Instant::now();
+        let costs = [("KnowledgeSnort", 2)];
+
+        // more than a burst may be made at once, leaving the bucket in debt
+        charge(
+            &mut [(&mut steader, &loose), (&mut conn, &strict)],
+            &costs,
+            now,
+        )
+        .unwrap();
+        let limited = charge(
+            &mut [(&mut steader, &loose), (&mut conn, &strict)],
+            &costs,
+            now,
+        )
+        .unwrap_err();
+        assert_eq!(limited.scope, "connection");
+        assert_eq!(limited.retry_after, Duration::from_secs(2));
+
+        // the steader's quota wasn't charged for the message that was refused
+        let mut other_conn = Quota::new("connection");
+        for _ in 0..4 {
+            charge(
+                &mut [(&mut steader, &loose), (&mut other_conn, &loose)],
+                &costs,
+                now,
+            )
+            .unwrap();
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Tests that need a whole Session, at the other end of a wormhole.
+use super::Orifice;
+use crate::{
+    harness::Harness,
+    wormhole::protocol::{FramedEdit, Outbound},
+};
+use hcor::{serde_diff::Apply, wormhole::EditNote, Ask, Note};
+use std::time::Duration;
+
+/// Has bob snort some xp through a wormhole on `orifice`, applying the edit he's sent to his copy
+/// of his hackstead just as a client would, and checks that it comes out just like the server's.
+async fn edits_round_trip(orifice: Orifice) {
+    let h = Harness::start().await;
+    let mut bobstead = h.summon().await;
+    let mut wormhole = h.wormhole_through(&bobstead, orifice).await;
+    let wait = Duration::from_secs(5);
+
+    wormhole.ask(Ask::KnowledgeSnort { xp: 10 }).await;
+    match orifice {
+        Orifice::Json => {
+            let json = wormhole
+                .until(wait, |o: Outbound| match o {
+                    Outbound::Note(Note::Edit(EditNote::Json(json))) => Some(json),
+                    _ => None,
+                })
+                .await;
+            let mut de = serde_json::Deserializer::from_str(&json);
+            Apply::apply(&mut de, &mut bobstead).unwrap();
+        }
+        Orifice::Bincode => {
+            use bincode::Options;
+
+            let bytes = wormhole
+                .until(wait, |o: Outbound| match o {
+                    Outbound::Note(Note::Edit(EditNote::Bincode(bytes))) => Some(bytes),
+                    _ => None,
+                })
+                .await;
+            let options = bincode::options()
+                .with_fixint_encoding()
+                .allow_trailing_bytes();
+            let mut de = bincode::Deserializer::from_slice(&bytes, options);
+            Apply::apply(&mut de, &mut bobstead).unwrap();
+        }
+        Orifice::MessagePack | Orifice::Cbor => {
+            let diff = wormhole
+                .until(wait, |o: Outbound| match (orifice, o) {
+                    (Orifice::MessagePack, Outbound::Edit(FramedEdit::MessagePack(diff)))
+                    | (Orifice::Cbor, Outbound::Edit(FramedEdit::Cbor(diff))) => Some(diff),
+                    (_, Outbound::Note(Note::Edit(e))) => {
+                        panic!("{} orifice sent an EditNote: {:?}", orifice.name(), e)
+                    }
+                    _ => None,
+                })
+                .await;
+            Apply::apply(diff, &mut bobstead).unwrap();
+        }
+    }
+    bobstead.local_version += 1;
+
+    assert_eq!(h.spy(&bobstead).await.unwrap(), bobstead);
+    h.stop().await;
+}
+
+#[actix_rt::test]
+async fn json_edits_round_trip() {
+    edits_round_trip(Orifice::Json).await
+}
+
+#[actix_rt::test]
+async fn bincode_edits_round_trip() {
+    edits_round_trip(Orifice::Bincode).await
+}
+
+#[actix_rt::test]
+async fn messagepack_edits_round_trip() {
+    edits_round_trip(Orifice::MessagePack).await
+}
+
+#[actix_rt::test]
+async fn cbor_edits_round_trip() {
+    edits_round_trip(Orifice::Cbor).await
+}
+
+#[actix_rt::test]
+async fn asks_are_rate_limited() {
+    use crate::{config::RateLimits, wormhole::protocol::ProtocolNote, World};
+    use hcor::IdentifiesSteader;
+
+    let h = Harness::start_with(World::real(), |c| {
+        c.rate_limits = RateLimits::everything(0.01, 3.0);
+        c.conn_rate_limits = RateLimits::everything(0.01, 2.0);
+    })
+    .await;
+    let bobstead = h.summon().await;
+
+    let mut wormhole = h.wormhole(&bobstead).await;
+    let first = wormhole.ask(Ask::KnowledgeSnort { xp: 10 }).await;
+    let answer = wormhole.asked(first).await;
+    let ask_id = wormhole.ask(Ask::KnowledgeSnort { xp: 10 }).await;
+    wormhole.asked(ask_id).await;
+
+    let ask_id = wormhole.ask(Ask::KnowledgeSnort { xp: 10 }).await;
+    match wormhole.protocol_note().await {
+        ProtocolNote::RateLimited {
+            ask_id: id,
+            ask,
+            retry_after,
+        } => {
+            assert_eq!(id, ask_id);
+            assert_eq!(ask, "KnowledgeSnort");
+            assert!(retry_after > 0.0);
+        }
+        other => panic!("expected to be rate limited, got {:?}", other),
+    }
+
+    // resends are answered from memory, even with no quota left, and cost nothing
+    wormhole.resend(first, Ask::KnowledgeSnort { xp: 10 }).await;
+    assert_eq!(wormhole.asked(first).await, answer);
+
+    // the steader has one ask left, which can be made without the wormhole
+    let snort = || {
+        awc::Client::new()
+            .post(format!("{}/api/knowledge/snort", h.url))
+            .send_json(&serde_json::json!({ "steader_id": bobstead.steader_id(), "xp": 10 }))
+    };
+    assert_eq!(snort().await.unwrap().status(), 200);
+    let refused = snort().await.unwrap();
+    assert_eq!(refused.status(), 429);
+    assert!(refused.headers().contains_key("Retry-After"));
+
+    assert_eq!(
+        h.spy(&bobstead).await.unwrap().profile.xp,
+        bobstead.profile.xp + 30
+    );
+    h.stop().await;
+}
+
+#[actix_rt::test]
+async fn batches_are_all_or_nothing() {
+    use hcor::wormhole::ItemAsk;
+
+    let h = Harness::start().await;
+    let bobstead = h.summon().await;
+    let mut wormhole = h.wormhole(&bobstead).await;
+
+    let snort = |xp| Ask::KnowledgeSnort { xp };
+    let ask_id = wormhole.batch(vec![snort(10), snort(5)]).await;
+    let (committed, results) = wormhole.batched(ask_id).await;
+    assert!(committed);
+    assert_eq!(results.len(), 2);
+
+    // nobody has this item, so hatching it fails, and the snort before it is undone
+    let hatch = Ask::Item(ItemAsk::Hatch {
+        hatchable_item_id: hcor::ItemId(uuid::Uuid::new_v4()),
+    });
+    let ask_id = wormhole.batch(vec![snort(10), hatch, snort(5)]).await;
+    let (committed, results) = wormhole.batched(ask_id).await;
+    assert!(!committed);
+    assert_eq!(results.len(), 2);
+    assert!(results[1].err().is_some());
+
+    let stored = h.spy(&bobstead).await.unwrap();
+    assert_eq!(stored.profile.xp, bobstead.profile.xp + 15);
+    assert_eq!(stored.local_version, bobstead.local_version + 1);
+    h.stop().await;
+}
+
+#[actix_rt::test]
+async fn asks_sent_twice_are_done_once() {
+    let h = Harness::start().await;
+    let bobstead = h.summon().await;
+
+    let mut wormhole = h.wormhole(&bobstead).await;
+    let ask_id = wormhole.ask(Ask::KnowledgeSnort { xp: 10 }).await;
+    let first = wormhole.asked(ask_id).await;
+
+    // as if the connection had dropped before the client heard back
+    drop(wormhole);
+    while h
+        .wormhole
+        .send(crate::wormhole::server::Census)
+        .await
+        .unwrap()
+        > 0
+    {
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+    let mut wormhole = h.wormhole(&bobstead).await;
+    wormhole
+        .resend(ask_id, Ask::KnowledgeSnort { xp: 10 })
+        .await;
+    assert_eq!(wormhole.asked(ask_id).await, first);
+
+    // the same ask_id with a different Ask is handled as usual
+    wormhole.resend(ask_id, Ask::KnowledgeSnort { xp: 5 }).await;
+    wormhole.asked(ask_id).await;
+
+    let stored = h.spy(&bobstead).await.unwrap();
+    assert_eq!(stored.profile.xp, bobstead.profile.xp + 15);
+    h.stop().await;
+}
@@ -1,4 +1,8 @@
-use super::SessionContext;
+use crate::wormhole::session::{
+    level::{self, LevelUp},
+    modifiers,
+};
+use crate::Config;
 use hcor::{
     id,
     plant::{Timer, TimerKind},
@@ -27,28 +31,48 @@ impl fmt::Display for Error {
     }
 }
 
+/// Applies whatever a timer does when it finishes, returning a note describing it, and the level
+/// the plant reached if that was enough xp for it to level up. Yields and crafts are modified by
+/// the effects on the plant as they finish, before any effect that wears off with them is gone.
 pub fn finish_timer(
     hs: &mut Hackstead,
-    _: &mut SessionContext,
+    config: &Config,
     Timer { tile_id, kind, .. }: Timer,
-) -> Result<RudeNote, Error> {
+) -> Result<(RudeNote, Option<LevelUp>), Error> {
     use TimerKind::*;
 
     let plant = hs.plant_mut(tile_id)?;
+    let modifiers = modifiers::of(config, plant);
     Ok(match kind {
-        Yield => RudeNote::YieldFinish {
-            items: vec![],
-            xp: 0,
-            tile_id,
-        },
-        Craft { recipe_index } => RudeNote::CraftFinish {
-            items: vec![],
-            xp: 0,
-            tile_id,
-        },
-        Rub { effect_id } => RudeNote::RubEffectFinish {
-            effect: plant.take_effect(effect_id)?,
-            tile_id,
-        },
+        Yield => {
+            let xp = modifiers.xp(config.yield_xp);
+            (
+                RudeNote::YieldFinish {
+                    // yields don't produce any items of their own yet
+                    items: vec![],
+                    xp,
+                    tile_id,
+                },
+                level::earn_xp(config, plant, xp),
+            )
+        }
+        Craft { .. } => {
+            let xp = modifiers.xp(config.craft_xp);
+            (
+                RudeNote::CraftFinish {
+                    items: vec![],
+                    xp,
+                    tile_id,
+                },
+                level::earn_xp(config, plant, xp),
+            )
+        }
+        Rub { effect_id } => (
+            RudeNote::RubEffectFinish {
+                effect: plant.take_effect(effect_id)?,
+                tile_id,
+            },
+            None,
+        ),
     })
 }
@@ -1,55 +1,224 @@
+use super::modifiers;
 use super::{Session, SessionContext};
-use hcor::{plant, Hackstead, Note};
+use crate::{audit, metrics};
+use hcor::{plant, wormhole::RudeNote, Hackstead, IdentifiesSteader, Note, UPDATE_INTERVAL};
+use std::time::Instant;
 
 mod finish;
 use finish::finish_timer;
 
-#[cfg(all(test, feature = "hcor_client"))]
+#[cfg(test)]
 mod test;
 
 #[derive(Default)]
 pub struct Ticker {
     pub timers: Vec<plant::Timer>,
     complete_timers: Vec<(usize, plant::timer::Lifecycle)>,
+    /// When we last caught up with the clock.
+    last_tick: Option<Instant>,
 }
 
 impl Ticker {
-    pub fn new(hs: &mut Hackstead) -> Self {
+    pub fn new(hs: &mut Hackstead, now: Instant) -> Self {
+        let timers: Vec<plant::Timer> = hs.timers.drain(..).collect();
+        metrics::TIMERS_OUTSTANDING.add(metrics::count(timers.len()));
+
         Self {
-            timers: hs.timers.drain(..).collect(),
+            timers,
             complete_timers: vec![],
+            last_tick: Some(now),
         }
     }
 
     pub fn start(&mut self, timer: plant::Timer) {
-        self.timers.push(timer);
+        if set(&mut self.timers, timer) {
+            metrics::TIMERS_OUTSTANDING.inc();
+        }
+    }
+
+    /// Stops the timer of this kind on this tile, if there is one, without finishing it.
+    pub fn cancel(&mut self, tile_id: hcor::TileId, kind: plant::TimerKind) {
+        let cancelled = cancel(&mut self.timers, tile_id, kind);
+        metrics::TIMERS_OUTSTANDING.sub(metrics::count(cancelled));
     }
 
+    /// Moves every timer forward by however many updates' worth of time has passed since the
+    /// last tick, sped up or slowed down by the effects on its plant, and finishes those that
+    /// are done.
     pub fn tick(&mut self, ses: &mut Session, ctx: &mut SessionContext) {
+        let now = ses.world.now();
+        let updates = match self.last_tick.replace(now) {
+            Some(last) => {
+                now.saturating_duration_since(last).as_secs_f32() / UPDATE_INTERVAL.as_secs_f32()
+            }
+            None => 0.0,
+        };
+        if updates <= 0.0 {
+            return;
+        }
+
         for (i, t) in &mut self.timers.iter_mut().enumerate() {
-            t.until_finish -= 1.0;
+            t.until_finish -= updates * speed(ses, t) as f32;
 
             if t.until_finish <= 0.0 {
                 self.complete_timers.push((i, t.lifecycle));
             }
         }
 
-        for (i, lifecycle) in self.complete_timers.drain(..) {
+        // backwards, so that removing one timer doesn't move any we have yet to get to
+        for (i, lifecycle) in self.complete_timers.drain(..).rev() {
             use plant::timer::Lifecycle;
 
             let timmy = match lifecycle {
                 Lifecycle::Perennial { duration } => {
+                    // whatever time we overshot by counts towards the next go around
                     let t = self.timers.get_mut(i).unwrap();
-                    t.until_finish = duration;
+                    t.until_finish += duration;
                     *t
                 }
-                Lifecycle::Annual => self.timers.swap_remove(i),
+                Lifecycle::Annual => {
+                    metrics::TIMERS_OUTSTANDING.dec();
+                    self.timers.swap_remove(i)
+                }
             };
 
-            match finish_timer(&mut ses.hackstead, ctx, timmy) {
-                Ok(n) => ses.send_note(ctx, &Note::Rude(n)),
-                Err(e) => log::error!("error finishing timer {:#?}: {}", timmy, e),
+            let before = ses.hackstead.clone();
+            let res = finish_timer(&mut ses.hackstead, &ses.config, timmy);
+            metrics::TIMER_COMPLETIONS
+                .with_label_values(&[timer_kind(&timmy.kind), metrics::outcome(&res)])
+                .inc();
+            audit::record(
+                &ses.config,
+                ses.hackstead.steader_id(),
+                audit::Event::Timer {
+                    timer: timmy,
+                    outcome: res
+                        .as_ref()
+                        .map(|(note, _)| note.clone())
+                        .map_err(ToString::to_string),
+                    local_version: ses.hackstead.local_version,
+                    diff: res.as_ref().ok().and_then(|_| {
+                        use hcor::serde_diff::Diff;
+                        serde_json::to_value(&Diff::serializable(&before, &ses.hackstead)).ok()
+                    }),
+                },
+            );
+
+            let level_up = match res {
+                Ok((n, level_up)) => {
+                    ses.send_note(ctx, &Note::Rude(n));
+                    level_up
+                }
+                Err(e) => {
+                    log::error!("error finishing timer {:#?}: {}", timmy, e);
+                    None
+                }
+            };
+
+            if let Some(up) = level_up {
+                metrics::PLANT_LEVEL_UPS
+                    .with_label_values(&[&up.level.to_string()])
+                    .inc();
+                self.speed_up_yield(timmy.tile_id, up.yield_duration);
+                ses.send_note(
+                    ctx,
+                    &Note::Rude(RudeNote::PlantLevelUp {
+                        tile_id: timmy.tile_id,
+                        level: up.level,
+                        yield_duration: up.yield_duration,
+                    }),
+                );
+            }
+        }
+    }
+}
+
+impl Ticker {
+    /// Has the yield timer for the plant on this tile take `duration` updates from now on,
+    /// without making the yield in progress take any longer than it would have.
+    fn speed_up_yield(&mut self, tile_id: hcor::TileId, duration: Option<f32>) {
+        use plant::timer::Lifecycle;
+
+        let duration = match duration {
+            Some(d) => d,
+            None => return,
+        };
+        for t in &mut self.timers {
+            if t.tile_id == tile_id && matches!(t.kind, plant::TimerKind::Yield) {
+                t.lifecycle = Lifecycle::Perennial { duration };
+                t.until_finish = t.until_finish.min(duration);
             }
         }
     }
 }
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        metrics::TIMERS_OUTSTANDING.sub(metrics::count(self.timers.len()));
+    }
+}
+
+/// Adds a timer to `timers`, unless there's already one for the same thing, i.e. the same effect
+/// on the same plant, in which case the new timer takes its place. Returns whether it was added.
+fn set(timers: &mut Vec<plant::Timer>, timer: plant::Timer) -> bool {
+    match timers
+        .iter_mut()
+        .find(|t| same_purpose(t, timer.tile_id, &timer.kind))
+    {
+        Some(t) => {
+            *t = timer;
+            false
+        }
+        None => {
+            timers.push(timer);
+            true
+        }
+    }
+}
+
+/// Removes the timer of this kind on this tile from `timers`, if there is one, i.e. the timer for
+/// an effect on a plant. Returns how many were removed.
+fn cancel(timers: &mut Vec<plant::Timer>, tile_id: hcor::TileId, kind: plant::TimerKind) -> usize {
+    let before = timers.len();
+    timers.retain(|t| !same_purpose(t, tile_id, &kind));
+    before - timers.len()
+}
+
+/// Whether a timer is for the same thing as a timer of this kind on this tile would be.
+fn same_purpose(t: &plant::Timer, tile_id: hcor::TileId, kind: &plant::TimerKind) -> bool {
+    use plant::TimerKind::*;
+
+    t.tile_id == tile_id
+        && match (&t.kind, kind) {
+            (Yield, Yield) | (Craft { .. }, Craft { .. }) => true,
+            (Rub { effect_id: a }, Rub { effect_id: b }) => a == b,
+            _ => false,
+        }
+}
+
+/// How many updates a timer moves forward by for every update that passes, as modified by the
+/// effects on its plant.
+fn speed(ses: &Session, timer: &plant::Timer) -> f64 {
+    use plant::TimerKind::*;
+
+    let plant = match ses.hackstead.plant(timer.tile_id) {
+        Ok(plant) => plant,
+        Err(_) => return 1.0,
+    };
+    match timer.kind {
+        Yield => modifiers::of(&ses.config, plant).yield_speed,
+        Craft { .. } => modifiers::of(&ses.config, plant).craft_speed,
+        Rub { .. } => 1.0,
+    }
+}
+
+/// A short name for a kind of timer, i.e. "Yield", for metrics and logs.
+pub fn timer_kind(kind: &plant::TimerKind) -> &'static str {
+    use plant::TimerKind::*;
+
+    match kind {
+        Yield => "Yield",
+        Craft { .. } => "Craft",
+        Rub { .. } => "Rub",
+    }
+}
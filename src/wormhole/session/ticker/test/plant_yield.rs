@@ -1,49 +1,39 @@
 #[actix_rt::test]
-async fn plant_yield() -> hcor::ClientResult<()> {
-    use super::true_or_timeout;
-    use hcor::{wormhole::RudeNote::*, Hackstead, IdentifiesTile, CONFIG};
+async fn plant_yield() {
+    use super::{stopped_clock, updates};
+    use hcor::{wormhole::RudeNote::*, CONFIG};
     use log::*;
 
-    // attempt to establish logging, do nothing if it fails
-    // (it probably fails because it's already been established in another test)
-    drop(pretty_env_logger::try_init());
-
-    let (seed_arch, yield_duration) = hcor::CONFIG
-        .seeds()
-        .filter_map(|(seed, seed_arch)| {
-            Some((
-                seed_arch,
-                CONFIG
-                    .find_plant(&seed.grows_into)
-                    .ok()?
-                    .base_yield_duration?,
-            ))
+    let (seed_arch, yield_duration) = CONFIG
+        .possession_archetypes
+        .iter()
+        .enumerate()
+        .filter_map(|(handle, arch)| {
+            let plant = CONFIG.find_plant(&arch.seed.as_ref()?.grows_into).ok()?;
+            Some((handle, plant.base_yield_duration?))
         })
         .min_by_key(|(_, yd)| *yd as usize)
         .expect("no seeds in config that yield?");
 
     // create bob's stead!
-    let bobstead = Hackstead::register().await?;
+    let (h, clock) = stopped_clock().await;
+    let bobstead = h.summon().await;
+    let mut wormhole = h.wormhole(&bobstead).await;
 
     // make plant
-    let plant = bobstead
-        .free_tile()
-        .unwrap()
-        .plant_seed(&seed_arch.spawn().await?)
-        .await?;
-    let tid = plant.tile_id();
+    let tid = bobstead.free_tiles().next().unwrap().tile_id;
+    wormhole.plant(tid, seed_arch).await;
 
     for i in 0..2 {
-        true_or_timeout("yield", yield_duration, move |n| match n {
-            &YieldFinish { tile_id, .. } => return tile_id == tid,
-            _ => false,
-        })
-        .await;
+        h.pass(&clock, &bobstead, updates(yield_duration)).await;
+        wormhole
+            .rude(|n| match n {
+                YieldFinish { tile_id, .. } if tile_id == tid => Some(()),
+                _ => None,
+            })
+            .await;
         info!("yield {} completed!", i + 1);
     }
 
-    // cleanup
-    bobstead.slaughter().await?;
-
-    Ok(())
+    h.stop().await;
 }
@@ -1,62 +1,69 @@
 #[actix_rt::test]
-/// NOTE: relies on plant/new, item/spawn, plant/apply!
-async fn plant_rub_wear_off() -> hcor::ClientResult<()> {
-    use super::true_or_timeout;
-    use futures::{stream, StreamExt};
-    use hcor::{wormhole::RudeNote::*, Hackstead};
+/// NOTE: relies on plant/summon, item/spawn, plant/rub!
+async fn plant_rub_wear_off() {
+    use super::{stopped_clock, updates};
+    use crate::harness::rubbable;
+    use hcor::{
+        wormhole::{AskedNote, PlantAsk, RudeNote::*},
+        Ask,
+    };
     use log::*;
+    use std::collections::HashSet;
 
-    // attempt to establish logging, do nothing if it fails
-    // (it probably fails because it's already been established in another test)
-    drop(pretty_env_logger::try_init());
-
-    let (seed_arch, rub_wear_off_arch) = hcor::CONFIG
-        .seeds()
-        .find_map(|(seed, seed_arch)| {
-            Some((
-                seed_arch,
-                hcor::CONFIG.possession_archetypes.iter().find(|a| {
-                    a.rub_effects_for_plant(&seed.grows_into)
-                        .any(|e| e.duration.is_some())
-                })?,
-            ))
-        })
-        .expect("no seeds in config that grow into plants we can rub with effects that wear off?");
+    let (seed_arch, rub_wear_off_arch) = rubbable(true);
 
     // create bob's stead!
-    let bobstead = Hackstead::register().await?;
+    let (h, clock) = stopped_clock().await;
+    let bobstead = h.summon().await;
+    let mut wormhole = h.wormhole(&bobstead).await;
 
     // make plant
-    info!("spawning first item");
-    let seed_item = seed_arch.spawn().await?;
-    info!("seed item spawn");
-    let tile = bobstead.free_tile().expect("new hackstead no open tiles");
-    let plant = tile.plant_seed(&seed_item).await?;
+    let tile_id = bobstead
+        .free_tiles()
+        .next()
+        .expect("new hackstead no open tiles")
+        .tile_id;
+    wormhole.plant(tile_id, seed_arch).await;
 
-    // rub 2 items, simultaneously
+    // rub 2 items, one after the other
     for i in 0..2 {
-        let rub_item = rub_wear_off_arch.spawn().await?;
-        let effects = plant.rub_with(&rub_item).await?;
-
-        stream::iter(
-            effects
-                .clone()
-                .into_iter()
-                .filter_map(|e| Some((e, e.duration?))),
-        )
-        .for_each_concurrent(None, |(e, d)| {
-            true_or_timeout("effect wear off", d, move |n| match n {
-                RubEffectFinish { effect, .. } => return effect.effect_id == e.effect_id,
-                _ => false,
-            })
-        })
-        .await;
+        let rub_item_id = wormhole.spawn(rub_wear_off_arch, 1).await[0].item_id;
+        let ask_id = wormhole
+            .ask(Ask::Plant(PlantAsk::Rub {
+                tile_id,
+                rub_item_id,
+            }))
+            .await;
+        let effects = match wormhole.asked(ask_id).await {
+            AskedNote::PlantRubStartResult(Ok(effects)) => effects,
+            other => panic!("couldn't rub plant: {:?}", other),
+        };
+
+        let mut wearing_off: HashSet<_> = effects
+            .iter()
+            .filter(|e| e.duration.is_some())
+            .map(|e| e.effect_id)
+            .collect();
+        let longest = effects
+            .iter()
+            .filter_map(|e| e.duration)
+            .fold(0.0, f32::max);
+
+        h.pass(&clock, &bobstead, updates(longest)).await;
+        while !wearing_off.is_empty() {
+            let worn_off = wormhole
+                .rude(|n| match n {
+                    RubEffectFinish { effect, .. } if wearing_off.contains(&effect.effect_id) => {
+                        Some(effect.effect_id)
+                    }
+                    _ => None,
+                })
+                .await;
+            wearing_off.remove(&worn_off);
+        }
 
         info!("effect {} wore off!", i + 1);
     }
 
-    // cleanup
-    bobstead.slaughter().await?;
-
-    Ok(())
+    h.stop().await;
 }
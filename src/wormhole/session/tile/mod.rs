@@ -1,4 +1,4 @@
-use super::{strerr, SessSend};
+use super::{cooldown::OnCooldown, strerr, SessSend};
 use hcor::{id, Item, ItemId, Tile};
 use std::fmt;
 
@@ -9,6 +9,7 @@ pub enum Error {
     NoSuch(id::NoSuch),
     NotConfigured(Item),
     Ineligible,
+    Cooldown(OnCooldown),
 }
 use Error::*;
 
@@ -18,6 +19,12 @@ impl From<id::NoSuch> for Error {
     }
 }
 
+impl From<OnCooldown> for Error {
+    fn from(oc: OnCooldown) -> Error {
+        Error::Cooldown(oc)
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "couldn't provide new tile: ")?;
@@ -29,11 +36,13 @@ impl fmt::Display for Error {
                 item.name, item.archetype_handle,
             ),
             Ineligible => write!(f, "you aren't eligible to unlock more land."),
+            Cooldown(oc) => write!(f, "{}", oc),
         }
     }
 }
 
 pub fn summon(ss: &mut SessSend, item_id: ItemId) -> Result<Tile, Error> {
+    ss.cool_down("TileSummon", None)?;
     let item = ss.take_item(item_id)?;
     let land_unlock = item
         .unlocks_land
@@ -54,36 +63,35 @@ pub fn summon(ss: &mut SessSend, item_id: ItemId) -> Result<Tile, Error> {
     }
 }
 
-#[cfg(all(feature = "hcor_client", test))]
+#[cfg(test)]
 mod test {
     #[actix_rt::test]
-    /// NOTE: relies on item/spawn!
-    async fn summon() -> hcor::ClientResult<()> {
-        use hcor::Hackstead;
+    async fn summon() {
+        use crate::harness::{Harness, Wormhole};
+        use hcor::{wormhole::AskedNote, Ask, Hackstead};
         use log::*;
 
-        // attempt to establish logging, do nothing if it fails
-        // (it probably fails because it's already been established in another test)
-        drop(pretty_env_logger::try_init());
-
-        let requires_xp_arch = hcor::CONFIG
-            .land_unlockers()
-            .find(|(ul, _)| ul.requires_xp)
-            .expect("no items in config that unlock land and require xp to do so?")
-            .1;
-        let no_requires_xp_arch = hcor::CONFIG
-            .land_unlockers()
-            .find(|(ul, _)| !ul.requires_xp)
-            .expect("no items in config that unlock land and don't require xp to do so?")
-            .1;
+        let unlocker = |requires_xp| {
+            hcor::CONFIG.possession_archetypes.iter().position(|x| {
+                x.unlocks_land
+                    .as_ref()
+                    .map_or(false, |ul| ul.requires_xp == requires_xp)
+            })
+        };
+        let requires_xp_arch =
+            unlocker(true).expect("no items in config that unlock land and require xp to do so?");
+        let no_requires_xp_arch = unlocker(false)
+            .expect("no items in config that unlock land and don't require xp to do so?");
         let non_land_redeemable_arch = hcor::CONFIG
             .possession_archetypes
             .iter()
-            .find(|x| x.unlocks_land.is_none())
+            .position(|x| x.unlocks_land.is_none())
             .expect("no items in config that don't unlock land?");
 
         debug!("create bob's stead!");
-        let mut bobstead = Hackstead::register().await?;
+        let h = Harness::start().await;
+        let bobstead = h.summon().await;
+        let mut wormhole = h.wormhole(&bobstead).await;
         let starting_tile_count = bobstead.land.len();
 
         struct NewTileAssumptions {
@@ -93,24 +101,31 @@ mod test {
         }
 
         async fn new_tile_assuming(
-            bobstead: &mut Hackstead,
+            h: &Harness,
+            bobstead: &Hackstead,
+            wormhole: &mut Wormhole,
             item: &hcor::Item,
             assumptions: NewTileAssumptions,
-        ) -> hcor::ClientResult<()> {
-            let requested_tile = item.redeem_for_tile().await;
-
-            match (assumptions.expected_success, requested_tile) {
-                (true, Ok(tile)) => assert_eq!(
+        ) {
+            let ask_id = wormhole
+                .ask(Ask::TileSummon {
+                    tile_redeemable_item_id: item.item_id,
+                })
+                .await;
+
+            match (assumptions.expected_success, wormhole.asked(ask_id).await) {
+                (true, AskedNote::TileSummonResult(Ok(tile))) => assert_eq!(
                     tile.owner_id, bobstead.profile.steader_id,
                     "tile spawned for bob doesn't belong to bob: {:#?}",
                     tile
                 ),
-                (false, Err(e)) => log::info!("/tile/new failed as expected: {}", e),
-                (true, Err(e)) => panic!("/tile/new unexpectedly failed: {}", e),
-                (false, Ok(tile)) => panic!("/tile/new unexpectedly returned tile: {:#?}", tile),
+                (false, AskedNote::TileSummonResult(Err(e))) => {
+                    info!("tile summon failed as expected: {}", e)
+                }
+                (_, other) => panic!("unexpected response to tile summon: {:?}", other),
             };
 
-            *bobstead = Hackstead::fetch(&*bobstead).await?;
+            let bobstead = h.spy(bobstead).await.unwrap();
 
             assert_eq!(
                 bobstead.land.len(),
@@ -128,16 +143,16 @@ mod test {
                     "consumed"
                 }
             );
-
-            Ok(())
         }
 
         debug!("spawn bob an item he can redeem for a tile if he has enough xp");
-        let requires_xp_item = requires_xp_arch.spawn().await?;
+        let requires_xp_item = wormhole.spawn(requires_xp_arch, 1).await.remove(0);
 
         debug!("try and redeem this item bob doesn't have enough xp to redeem for land");
         new_tile_assuming(
-            &mut bobstead,
+            &h,
+            &bobstead,
+            &mut wormhole,
             &requires_xp_item,
             NewTileAssumptions {
                 expected_success: false,
@@ -145,14 +160,16 @@ mod test {
                 expected_tiles: starting_tile_count,
             },
         )
-        .await?;
+        .await;
 
         debug!("spawn an item bob can redeem for land without having enough xp");
-        let no_requires_xp_item = no_requires_xp_arch.spawn().await?;
+        let no_requires_xp_item = wormhole.spawn(no_requires_xp_arch, 1).await.remove(0);
 
         debug!("try and redeem that item, this should actually work");
         new_tile_assuming(
-            &mut bobstead,
+            &h,
+            &bobstead,
+            &mut wormhole,
             &no_requires_xp_item,
             NewTileAssumptions {
                 expected_success: true,
@@ -160,15 +177,21 @@ mod test {
                 expected_tiles: starting_tile_count + 1,
             },
         )
-        .await?;
+        .await;
 
         // give bob enough xp to unlock the next level (hopefully)
-        let now_xp = bobstead.knowledge_snort(std::usize::MAX).await?;
-        debug!("bob's xp now set to {}", now_xp);
+        let ask_id = wormhole
+            .ask(Ask::KnowledgeSnort {
+                xp: std::usize::MAX,
+            })
+            .await;
+        debug!("bob's xp now: {:?}", wormhole.asked(ask_id).await);
 
         debug!("try and redeem the first item that does require xp to work, should work now.");
         new_tile_assuming(
-            &mut bobstead,
+            &h,
+            &bobstead,
+            &mut wormhole,
             &requires_xp_item,
             NewTileAssumptions {
                 expected_success: true,
@@ -176,11 +199,13 @@ mod test {
                 expected_tiles: starting_tile_count + 2,
             },
         )
-        .await?;
+        .await;
 
         debug!("try and redeem those items we've already used up");
         new_tile_assuming(
-            &mut bobstead,
+            &h,
+            &bobstead,
+            &mut wormhole,
             &requires_xp_item,
             NewTileAssumptions {
                 expected_success: false,
@@ -188,9 +213,11 @@ mod test {
                 expected_tiles: starting_tile_count + 2,
             },
         )
-        .await?;
+        .await;
         new_tile_assuming(
-            &mut bobstead,
+            &h,
+            &bobstead,
+            &mut wormhole,
             &no_requires_xp_item,
             NewTileAssumptions {
                 expected_success: false,
@@ -198,12 +225,14 @@ mod test {
                 expected_tiles: starting_tile_count + 2,
             },
         )
-        .await?;
+        .await;
 
         debug!("try to redeem the non-land-redeemable item for land");
-        let non_land_redeemable_item = non_land_redeemable_arch.spawn().await?;
+        let non_land_redeemable_item = wormhole.spawn(non_land_redeemable_arch, 1).await.remove(0);
         new_tile_assuming(
-            &mut bobstead,
+            &h,
+            &bobstead,
+            &mut wormhole,
             &non_land_redeemable_item,
             NewTileAssumptions {
                 expected_success: false,
@@ -211,11 +240,8 @@ mod test {
                 expected_tiles: starting_tile_count + 2,
             },
         )
-        .await?;
-
-        debug!("kill bob so he's not left in the database");
-        bobstead.slaughter().await?;
+        .await;
 
-        Ok(())
+        h.stop().await;
     }
 }
@@ -0,0 +1,162 @@
+use super::SessSend;
+use hcor::{id, plant, wormhole::RudeNote, Item, ItemId, Note, TileId};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    NoSuch(id::NoSuch),
+    NotACleanser(Item),
+    NeedsCleanser,
+}
+use Error::*;
+
+impl From<id::NoSuch> for Error {
+    fn from(ns: id::NoSuch) -> Error {
+        Error::NoSuch(ns)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "couldn't cleanse effect from plant: ")?;
+        match self {
+            NoSuch(ns) => write!(f, "{}", ns),
+            NotACleanser(item) => write!(
+                f,
+                "item {}[{}] isn't configured to cleanse effects from plants",
+                item.name, item.archetype_handle
+            ),
+            NeedsCleanser => write!(
+                f,
+                "cleansing requires an item, and none was given to use up"
+            ),
+        }
+    }
+}
+
+/// Takes an effect off of a plant, along with the timer it would have worn off with, using up the
+/// cleansing item if one is called for. The steader is sent a `RubEffectFinish` for it, just as
+/// if it'd worn off.
+pub fn cleanse(
+    ss: &mut SessSend,
+    tile_id: TileId,
+    effect_id: plant::EffectId,
+    cleansing_item_id: Option<ItemId>,
+) -> Result<plant::Effect, Error> {
+    match cleansing_item_id {
+        Some(item_id) => {
+            let item = ss.take_item(item_id)?;
+            if !ss.config.cleansers.contains(&item.name) {
+                return Err(NotACleanser(item));
+            }
+        }
+        None if !ss.config.cleansers.is_empty() => return Err(NeedsCleanser),
+        None => {}
+    }
+
+    let effect = ss.plant_mut(tile_id)?.take_effect(effect_id)?;
+    ss.cancel_timer(tile_id, plant::TimerKind::Rub { effect_id });
+    ss.send_note(Note::Rude(RudeNote::RubEffectFinish {
+        effect: effect.clone(),
+        tile_id,
+    }));
+
+    Ok(effect)
+}
+
+#[cfg(test)]
+mod test {
+    #[actix_rt::test]
+    /// NOTE: relies on plant/summon, item/spawn, plant/rub!
+    async fn cleanse() {
+        use crate::{
+            harness::{rubbable, Harness},
+            World,
+        };
+        use hcor::{
+            plant::TimerKind,
+            wormhole::{AskedNote, PlantAsk, RudeNote::*},
+            Ask, IdentifiesSteader, CONFIG,
+        };
+        use serde_json::json;
+
+        let (seed_arch, rub_arch) = rubbable(false);
+
+        // the item rubbed on is also what cleanses its effects
+        let h = Harness::start_with(World::real(), |c| {
+            c.cleansers = vec![CONFIG.possession_archetypes[rub_arch].name.clone()];
+        })
+        .await;
+        let bobstead = h.summon().await;
+        let mut wormhole = h.wormhole(&bobstead).await;
+
+        let tile_id = bobstead.land[0].tile_id;
+        wormhole.plant(tile_id, seed_arch).await;
+        let rubs = wormhole.spawn(rub_arch, 2).await;
+        let ask_id = wormhole
+            .ask(Ask::Plant(PlantAsk::Rub {
+                tile_id,
+                rub_item_id: rubs[0].item_id,
+            }))
+            .await;
+        let effect = match wormhole.asked(ask_id).await {
+            AskedNote::PlantRubStartResult(Ok(effects)) => effects[0].clone(),
+            other => panic!("couldn't rub plant: {:?}", other),
+        };
+
+        // cleansers are configured, so one has to be used up
+        let ask_id = wormhole
+            .ask(Ask::Plant(PlantAsk::Cleanse {
+                tile_id,
+                effect_id: effect.effect_id,
+                cleansing_item_id: None,
+            }))
+            .await;
+        match wormhole.asked(ask_id).await {
+            AskedNote::PlantCleanseResult(Err(e)) => {
+                assert!(e.contains("requires an item"), "unexpected error: {}", e)
+            }
+            other => panic!("cleansed without a cleanser: {:?}", other),
+        }
+
+        // like any other Ask, it can be made without a wormhole
+        let note: AskedNote = h
+            .post(
+                "plant/cleanse",
+                &json!({
+                    "steader_id": bobstead.steader_id(),
+                    "tile_id": tile_id,
+                    "effect_id": effect.effect_id,
+                    "cleansing_item_id": rubs[1].item_id,
+                }),
+            )
+            .await
+            .unwrap();
+        match note {
+            AskedNote::PlantCleanseResult(Ok(cleansed)) => assert_eq!(cleansed, effect),
+            other => panic!("couldn't cleanse effect: {:?}", other),
+        }
+        let finished = wormhole
+            .rude(|n| match n {
+                RubEffectFinish { effect, .. } => Some(effect),
+                _ => None,
+            })
+            .await;
+        assert_eq!(finished, effect);
+
+        // the effect, its timer and the cleanser are all gone
+        let stored = h.spy(&bobstead).await.unwrap();
+        let plant = stored.land[0].plant.as_ref().unwrap();
+        assert!(plant
+            .effects
+            .iter()
+            .all(|e| e.effect_id != effect.effect_id));
+        assert!(!stored.timers.iter().any(|t| matches!(
+            t.kind,
+            TimerKind::Rub { effect_id } if effect_id == effect.effect_id
+        )));
+        assert!(!stored.has_item(&rubs[1]));
+
+        h.stop().await;
+    }
+}
@@ -0,0 +1,99 @@
+//! Plants earn xp each time they yield or finish crafting something, and level up once they've
+//! earned as much as `Config::plant_levels` says they need to, yielding faster with each level.
+use crate::Config;
+use hcor::Plant;
+
+/// A plant reaching a new level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelUp {
+    pub level: usize,
+    /// How many updates the plant now takes to yield, if it yields at all.
+    pub yield_duration: Option<f32>,
+}
+
+/// What level this plant is at, starting from 0.
+pub fn level(config: &Config, plant: &Plant) -> usize {
+    config.plant_levels.level(&plant.name, plant.xp)
+}
+
+/// How many updates this plant takes to yield at its level, if it yields at all.
+pub fn yield_duration(config: &Config, plant: &Plant) -> Option<f32> {
+    let speedup = (1.0 - config.level_yield_speedup).powi(level(config, plant) as i32);
+    plant
+        .base_yield_duration
+        .map(|duration| duration * speedup as f32)
+}
+
+/// Gives a plant `xp`, and says what level it reached if that was enough for it to level up.
+pub fn earn_xp(config: &Config, plant: &mut Plant, xp: usize) -> Option<LevelUp> {
+    let before = level(config, plant);
+    plant.xp += xp;
+    let after = level(config, plant);
+
+    if after > before {
+        Some(LevelUp {
+            level: after,
+            yield_duration: yield_duration(config, plant),
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[actix_rt::test]
+    /// NOTE: relies on plant/summon, item/spawn!
+    async fn plants_level_up_from_yielding() {
+        use crate::{config::PlantLevels, harness::Harness, world::ManualClock, World};
+        use hcor::{wormhole::RudeNote, CONFIG};
+        use std::sync::Arc;
+
+        let clock = Arc::new(ManualClock::new());
+        let h = Harness::start_with(World::deterministic(clock.clone(), 0), |c| {
+            c.plant_levels = PlantLevels::everything(vec![10]);
+            c.yield_xp = 10;
+            c.level_yield_speedup = 0.5;
+        })
+        .await;
+        let bobstead = h.summon().await;
+        let mut wormhole = h.wormhole(&bobstead).await;
+
+        let (seed, yield_duration) = CONFIG
+            .possession_archetypes
+            .iter()
+            .enumerate()
+            .find_map(|(handle, arch)| {
+                let plant = CONFIG.find_plant(&arch.seed.as_ref()?.grows_into).ok()?;
+                Some((handle, plant.base_yield_duration?))
+            })
+            .expect("no seeds in config that yield?");
+        let tile_id = bobstead.free_tiles().next().unwrap().tile_id;
+        wormhole.plant(tile_id, seed).await;
+
+        h.pass(&clock, &bobstead, yield_duration.ceil() as usize)
+            .await;
+        let (level, faster) = wormhole
+            .rude(|n| match n {
+                RudeNote::PlantLevelUp {
+                    tile_id: id,
+                    level,
+                    yield_duration,
+                } if id == tile_id => Some((level, yield_duration)),
+                _ => None,
+            })
+            .await;
+        assert_eq!(level, 1);
+        assert_eq!(faster, Some(yield_duration * 0.5));
+
+        let plant = h
+            .spy(&bobstead)
+            .await
+            .unwrap()
+            .plant(tile_id)
+            .unwrap()
+            .clone();
+        assert_eq!(plant.xp, 10);
+        h.stop().await;
+    }
+}
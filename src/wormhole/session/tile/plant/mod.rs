@@ -10,15 +10,27 @@ use summon::summon;
 mod rub;
 use rub::rub;
 
+mod cleanse;
+use cleanse::cleanse;
+
 mod slaughter;
 
+pub mod level;
+pub mod modifiers;
+
 pub fn handle_ask(ss: &mut SessSend, ask: PlantAsk) -> AskedNote {
     match ask {
         Summon {
             tile_id,
             seed_item_id,
         } => PlantSummonResult(strerr(summon(ss, tile_id, seed_item_id))),
-        Slaughter { tile_id } => PlantSlaughterResult(strerr(ss.take_plant(tile_id))),
+        Slaughter { tile_id } => {
+            let slain = ss.take_plant(tile_id);
+            if slain.is_ok() {
+                ss.forget_cooldowns(tile_id);
+            }
+            PlantSlaughterResult(strerr(slain))
+        }
         Craft {
             tile_id,
             recipe_index,
@@ -27,6 +39,11 @@ pub fn handle_ask(ss: &mut SessSend, ask: PlantAsk) -> AskedNote {
             tile_id,
             rub_item_id,
         } => PlantRubStartResult(strerr(rub(ss, tile_id, rub_item_id))),
+        Cleanse {
+            tile_id,
+            effect_id,
+            cleansing_item_id,
+        } => PlantCleanseResult(strerr(cleanse(ss, tile_id, effect_id, cleansing_item_id))),
     }
 }
 /*
@@ -0,0 +1,191 @@
+//! What the effects rubbed onto a plant do to it, as configured for each effect archetype by
+//! `Config::rub_modifiers`.
+//!
+//! Nothing here is stored on the plant; it's worked out from whichever effects the plant has
+//! whenever it's needed, so an effect wearing off takes what it did with it right away.
+use crate::{config::Modifier, Config};
+use hcor::Plant;
+
+/// What every effect on a plant, taken together, multiplies each `Modifier` by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Modifiers {
+    pub yield_speed: f64,
+    pub xp: f64,
+    pub craft_speed: f64,
+}
+
+impl Default for Modifiers {
+    /// A plant without any effects.
+    fn default() -> Self {
+        Self {
+            yield_speed: 1.0,
+            xp: 1.0,
+            craft_speed: 1.0,
+        }
+    }
+}
+
+impl Modifiers {
+    /// How much xp a plant earns in place of `xp`.
+    pub fn xp(&self, xp: usize) -> usize {
+        (xp as f64 * self.xp).round() as usize
+    }
+}
+
+/// What the effects on this plant do to it; each effect counts once, for its own archetype.
+pub fn of(config: &Config, plant: &Plant) -> Modifiers {
+    let factors = |modifier: Modifier| {
+        plant
+            .effects
+            .iter()
+            .filter_map(|e| {
+                let item = hcor::CONFIG
+                    .possession_archetypes
+                    .get(e.item_archetype_handle)?;
+                Some(
+                    config
+                        .rub_modifiers
+                        .of(&item.name, e.effect_archetype_handle),
+                )
+            })
+            .flatten()
+            .filter(move |(m, _)| *m == modifier)
+            .map(|(_, factor)| *factor)
+    };
+    let combined = |modifier| config.modifier_stacking.combine(factors(modifier));
+
+    Modifiers {
+        yield_speed: combined(Modifier::YieldSpeed),
+        xp: combined(Modifier::Xp),
+        craft_speed: combined(Modifier::CraftSpeed),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{harness::Harness, world::ManualClock};
+    use hcor::{Hackstead, TileId};
+
+    /// How far the yield timer of the plant on this tile gets in one update.
+    async fn yield_step(h: &Harness, clock: &ManualClock, hs: &Hackstead, tile_id: TileId) -> f32 {
+        use hcor::plant::{timer::Lifecycle, TimerKind};
+
+        let until_finish = || async {
+            let t = h
+                .spy(hs)
+                .await
+                .unwrap()
+                .timers
+                .into_iter()
+                .find(|t| t.tile_id == tile_id && matches!(t.kind, TimerKind::Yield))
+                .expect("no yield timer");
+            match t.lifecycle {
+                Lifecycle::Perennial { duration } => (t.until_finish, duration),
+                Lifecycle::Annual => panic!("yield timer isn't perennial"),
+            }
+        };
+
+        let (before, duration) = until_finish().await;
+        h.pass(clock, hs, 1).await;
+        let (after, _) = until_finish().await;
+        // a yield finishing in between starts the timer over
+        (before - after).rem_euclid(duration)
+    }
+
+    #[actix_rt::test]
+    /// NOTE: relies on plant/summon, item/spawn, plant/rub!
+    async fn yield_speed_recovers_once_an_effect_wears_off() {
+        use crate::{config::RubModifiers, World};
+        use hcor::{
+            wormhole::{AskedNote, PlantAsk, RudeNote::*},
+            Ask, CONFIG,
+        };
+        use std::sync::Arc;
+
+        // a seed that grows into a plant that yields, and an effect that wears off of it
+        let (seed_arch, rub_arch, rub_name, effect_index, effect_duration) = CONFIG
+            .possession_archetypes
+            .iter()
+            .enumerate()
+            .find_map(|(seed, arch)| {
+                let grows_into = &arch.seed.as_ref()?.grows_into;
+                CONFIG.find_plant(grows_into).ok()?.base_yield_duration?;
+                CONFIG
+                    .possession_archetypes
+                    .iter()
+                    .enumerate()
+                    .find_map(|(rub, arch)| {
+                        let (i, e) = arch
+                            .rub_effects_for_plant_indexed(grows_into)
+                            .find(|(_, e)| e.duration.is_some())?;
+                        Some((seed, rub, arch.name.clone(), i, e.duration?))
+                    })
+            })
+            .expect("no seeds in config that yield and have effects that wear off?");
+
+        // that effect halves how quickly the plant yields, and nothing else changes it
+        let clock = Arc::new(ManualClock::new());
+        let h = Harness::start_with(World::deterministic(clock.clone(), 0), |c| {
+            c.rub_modifiers =
+                RubModifiers::parse(&[format!("{}#{}=yield_speed*0.5", rub_name, effect_index)])
+                    .unwrap();
+            c.yield_xp = 0;
+        })
+        .await;
+        let bobstead = h.summon().await;
+        let mut wormhole = h.wormhole(&bobstead).await;
+
+        let tile_id = bobstead.free_tiles().next().unwrap().tile_id;
+        wormhole.plant(tile_id, seed_arch).await;
+
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-3;
+
+        let rub_item_id = wormhole.spawn(rub_arch, 1).await[0].item_id;
+        let ask_id = wormhole
+            .ask(Ask::Plant(PlantAsk::Rub {
+                tile_id,
+                rub_item_id,
+            }))
+            .await;
+        let effect_id = match wormhole.asked(ask_id).await {
+            AskedNote::PlantRubStartResult(Ok(effects)) => {
+                effects
+                    .iter()
+                    .find(|e| e.effect_archetype_handle == effect_index)
+                    .expect("rubbing didn't impart the effect")
+                    .effect_id
+            }
+            other => panic!("couldn't rub plant: {:?}", other),
+        };
+
+        let slowed = yield_step(&h, &clock, &bobstead, tile_id).await;
+        assert!(
+            close(slowed, 0.5),
+            "yield ran at {} with the effect",
+            slowed
+        );
+
+        // the rest of the effect's duration, less the update already passed
+        h.pass(
+            &clock,
+            &bobstead,
+            (effect_duration.ceil() as usize).max(1) - 1,
+        )
+        .await;
+        wormhole
+            .rude(|n| match n {
+                RubEffectFinish { effect, .. } if effect.effect_id == effect_id => Some(()),
+                _ => None,
+            })
+            .await;
+
+        let recovered = yield_step(&h, &clock, &bobstead, tile_id).await;
+        assert!(
+            close(recovered, 1.0),
+            "yield ran at {} once the effect wore off",
+            recovered
+        );
+
+        h.stop().await;
+    }
+}
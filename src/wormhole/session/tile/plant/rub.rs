@@ -1,4 +1,5 @@
 use super::SessSend;
+use crate::{config::RubStacking, wormhole::session::cooldown::OnCooldown};
 use hcor::{id, plant, Item, ItemId, Plant, TileId};
 use std::fmt;
 
@@ -6,6 +7,9 @@ use std::fmt;
 pub enum Error {
     NoSuch(id::NoSuch),
     NoEffect(Option<Plant>, Item),
+    AlreadyImparted(Plant, Item),
+    TooManyEffects(Plant, Item, usize),
+    Cooldown(OnCooldown),
 }
 use Error::*;
 
@@ -15,6 +19,12 @@ impl From<id::NoSuch> for Error {
     }
 }
 
+impl From<OnCooldown> for Error {
+    fn from(oc: OnCooldown) -> Error {
+        Error::Cooldown(oc)
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "couldn't rub item on plant: ")?;
@@ -30,6 +40,18 @@ impl fmt::Display for Error {
                 "rubbing item {}[{}] on plant {}[{}] would have no effects",
                 item.name, item.archetype_handle, plant.name, plant.archetype_handle
             ),
+            AlreadyImparted(plant, item) => write!(
+                f,
+                "plant {}[{}] already has the effects of item {}[{}], \
+                 which can't be rubbed on again until they wear off",
+                plant.name, plant.archetype_handle, item.name, item.archetype_handle
+            ),
+            TooManyEffects(plant, item, cap) => write!(
+                f,
+                "rubbing item {}[{}] on plant {}[{}] would leave it with more than {} effects",
+                item.name, item.archetype_handle, plant.name, plant.archetype_handle, cap
+            ),
+            Cooldown(oc) => write!(f, "{}", oc),
         }
     }
 }
@@ -39,90 +61,137 @@ pub fn rub(
     tile_id: TileId,
     item_id: ItemId,
 ) -> Result<Vec<plant::Effect>, Error> {
+    ss.cool_down("Plant.Rub", Some(tile_id))?;
     let item = ss.take_item(item_id)?;
     if item.plant_rub_effects.is_empty() {
         return Err(NoEffect(None, item));
     }
 
-    let plant = ss.plant(tile_id)?;
-    let plant_name = plant.name.clone();
+    let plant = ss.plant(tile_id)?.clone();
+    let effect_confs: Vec<_> = item.rub_effects_for_plant_indexed(&plant.name).collect();
+    if effect_confs.is_empty() {
+        return Err(NoEffect(Some(plant), item));
+    }
+
+    // the effect of this kind that this item has already imparted to this plant, if any
+    let imparted = |i: usize| {
+        plant
+            .effects
+            .iter()
+            .find(|e| {
+                e.item_archetype_handle == item.archetype_handle && e.effect_archetype_handle == i
+            })
+            .cloned()
+    };
+    let policy = ss.config.rub_stacking.policy(&item.name);
+    if policy == RubStacking::Reject && effect_confs.iter().any(|(i, _)| imparted(*i).is_some()) {
+        return Err(AlreadyImparted(plant, item));
+    }
 
-    let mut effect_confs = item.rub_effects_for_plant_indexed(&plant_name).peekable();
-    if effect_confs.peek().is_none() {
-        return Err(NoEffect(Some(plant.clone()), item.clone()));
+    let refreshed = |i: usize| {
+        if policy == RubStacking::Refresh {
+            imparted(i)
+        } else {
+            None
+        }
+    };
+    let added = effect_confs
+        .iter()
+        .filter(|(i, _)| refreshed(*i).is_none())
+        .count();
+    if let Some(cap) = ss.config.effect_caps.cap(&plant.name) {
+        if plant.effects.len() + added > cap {
+            return Err(TooManyEffects(plant, item, cap));
+        }
     }
 
     let effects: Vec<plant::Effect> = effect_confs
         .into_iter()
         .map(|(i, a)| {
-            let effect_id = plant::EffectId(uuid::Uuid::new_v4());
+            let effect = refreshed(i).unwrap_or_else(|| plant::Effect {
+                effect_id: plant::EffectId(crate::world::roll_uuid(&mut ss.rng)),
+                item_archetype_handle: item.archetype_handle,
+                effect_archetype_handle: i,
+            });
 
-            // register any timers we'll need for the effects that'll wear off
+            // register any timers we'll need for the effects that'll wear off;
+            // a refreshed effect's timer takes the place of the one it had
             if let Some(until_finish) = a.duration {
                 ss.set_timer(plant::Timer {
                     until_finish,
                     tile_id,
                     lifecycle: plant::timer::Lifecycle::Annual,
-                    kind: plant::TimerKind::Rub { effect_id },
+                    kind: plant::TimerKind::Rub {
+                        effect_id: effect.effect_id,
+                    },
                 })
             }
 
-            plant::Effect {
-                effect_id,
-                item_archetype_handle: item.archetype_handle,
-                effect_archetype_handle: i,
-            }
+            effect
         })
         .collect();
 
-    ss.plant_mut(tile_id)?.effects.append(&mut effects.clone());
+    let plant = ss.plant_mut(tile_id)?;
+    plant.lifetime_rubs += 1;
+    for effect in &effects {
+        if !plant
+            .effects
+            .iter()
+            .any(|e| e.effect_id == effect.effect_id)
+        {
+            plant.effects.push(effect.clone());
+        }
+    }
     Ok(effects)
 }
 
-#[cfg(all(feature = "hcor_client", test))]
+#[cfg(test)]
 mod test {
     #[actix_rt::test]
-    /// NOTE: relies on plant/new, item/spawn!
-    async fn rub() -> hcor::ClientResult<()> {
-        use hcor::Hackstead;
-
-        // attempt to establish logging, do nothing if it fails
-        // (it probably fails because it's already been established in another test)
-        drop(pretty_env_logger::try_init());
-
-        let (seed_arch, rub_arch) = hcor::CONFIG
-            .seeds()
-            .find_map(|(seed, seed_arch)| {
-                Some((
-                    seed_arch,
-                    hcor::CONFIG
-                        .possession_archetypes
-                        .iter()
-                        .find(|a| a.rub_effects_for_plant(&seed.grows_into).count() > 0)?,
-                ))
-            })
-            .expect("no seeds in config that grow into plants we can rub with effects?");
+    /// NOTE: relies on plant/summon, item/spawn!
+    async fn rub() {
+        use crate::harness::{rubbable, Harness};
+        use hcor::{
+            wormhole::{AskedNote, PlantAsk},
+            Ask,
+        };
+
+        let (seed_arch, rub_arch) = rubbable(false);
 
         // create bob's stead!
-        let mut bobstead = Hackstead::register().await?;
+        let h = Harness::start().await;
+        let bobstead = h.summon().await;
+        let mut wormhole = h.wormhole(&bobstead).await;
 
         // make plant
-        let seed_item = seed_arch.spawn().await?;
-        let tile = bobstead.free_tile().expect("new hackstead no open tiles");
-        let mut plant = tile.plant_seed(&seed_item).await?;
+        let tile_id = bobstead
+            .free_tiles()
+            .next()
+            .expect("new hackstead no open tiles")
+            .tile_id;
+        let plant = wormhole.plant(tile_id, seed_arch).await;
 
         // rub item
-        let rub_item = rub_arch.spawn().await?;
-        let effects = plant.rub_with(&rub_item).await?;
+        let rub_item_id = wormhole.spawn(rub_arch, 1).await[0].item_id;
+        let ask_id = wormhole
+            .ask(Ask::Plant(PlantAsk::Rub {
+                tile_id,
+                rub_item_id,
+            }))
+            .await;
+        let effects = match wormhole.asked(ask_id).await {
+            AskedNote::PlantRubStartResult(Ok(effects)) => effects,
+            other => panic!("couldn't rub plant: {:?}", other),
+        };
 
-        bobstead = Hackstead::fetch(&bobstead).await?;
-        plant = bobstead.plant(&plant).unwrap().clone();
+        let bobstead = h.spy(&bobstead).await.unwrap();
+        let plant = bobstead.plant(&plant).unwrap().clone();
         assert_eq!(
             plant.effects, effects,
             "brand new plant has more effects than those from the item that was just rubbed on",
         );
         assert!(
-            rub_arch
+            hcor::CONFIG.possession_archetypes[rub_arch]
                 .rub_effects_for_plant(&plant.name)
                 .enumerate()
                 .all(|(i, _)| {
@@ -133,8 +202,65 @@ mod test {
             "the effects of this item we just rubbed on can't be found on this plant"
         );
 
-        bobstead.slaughter().await?;
+        h.stop().await;
+    }
+
+    #[actix_rt::test]
+    /// NOTE: relies on plant/summon, item/spawn!
+    async fn rubs_beyond_the_effect_cap_are_refused() {
+        use crate::{
+            config::EffectCaps,
+            harness::{rubbable, Harness},
+            World,
+        };
+        use hcor::{wormhole::PlantAsk, Ask, CONFIG};
+
+        let (seed_arch, rub_arch) = rubbable(false);
+        let grows_into = &CONFIG.possession_archetypes[seed_arch]
+            .seed
+            .as_ref()
+            .unwrap()
+            .grows_into;
+        let effects = CONFIG.possession_archetypes[rub_arch]
+            .rub_effects_for_plant(grows_into)
+            .count();
+
+        // room enough for one rub's worth of effects, and no more
+        let h = Harness::start_with(World::real(), |c| {
+            c.effect_caps = EffectCaps::everything(effects);
+        })
+        .await;
+        let bobstead = h.summon().await;
+        let mut wormhole = h.wormhole(&bobstead).await;
+
+        let tile_id = bobstead.free_tiles().next().unwrap().tile_id;
+        wormhole.plant(tile_id, seed_arch).await;
+
+        let rubs = wormhole.spawn(rub_arch, 2).await;
+        let rub_with = |item: &hcor::Item| {
+            Ask::Plant(PlantAsk::Rub {
+                tile_id,
+                rub_item_id: item.item_id,
+            })
+        };
+        let ask_id = wormhole.ask(rub_with(&rubs[0])).await;
+        assert!(wormhole.asked(ask_id).await.err().is_none());
+        let ask_id = wormhole.ask(rub_with(&rubs[1])).await;
+        let error = match wormhole.asked(ask_id).await.err() {
+            Some(e) => e.to_string(),
+            None => panic!("rubbed a plant beyond its effect cap"),
+        };
+        assert!(error.contains("more than"), "unexpected error: {}", error);
 
-        Ok(())
+        // the item that couldn't be rubbed on wasn't used up, and only the one rub was counted
+        let stored = h.spy(&bobstead).await.unwrap();
+        assert!(stored
+            .inventory
+            .iter()
+            .any(|i| i.item_id == rubs[1].item_id));
+        let plant = stored.plant(tile_id).unwrap();
+        assert_eq!(plant.lifetime_rubs, 1);
+        assert_eq!(plant.effects.len(), effects);
+        h.stop().await;
     }
 }
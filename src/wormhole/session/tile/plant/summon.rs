@@ -62,37 +62,45 @@ pub fn summon(ss: &mut SessSend, tile_id: TileId, item_id: ItemId) -> Result<Pla
     }
 
     tile.plant = Some(plant.clone());
+    // whatever was planted here before took its cooldowns with it
+    ss.forget_cooldowns(tile_id);
     Ok(plant)
 }
 
-#[cfg(all(feature = "hcor_client", test))]
+#[cfg(test)]
 mod test {
-    #[actix_rt::test]
-    /// NOTE: relies on item/spawn!
-    async fn summon() -> hcor::ClientResult<()> {
-        use hcor::{Hackstead, Item, Tile};
-        use log::*;
-
-        // attempt to establish logging, do nothing if it fails
-        // (it probably fails because it's already been established in another test)
-        drop(pretty_env_logger::try_init());
+    use crate::harness::{Harness, Wormhole};
+    use hcor::{
+        wormhole::{AskedNote, PlantAsk},
+        Ask, Hackstead, Item, TileId,
+    };
+    use log::*;
 
-        let (_, seed_arch) = hcor::CONFIG
-            .seeds()
-            .next()
+    #[actix_rt::test]
+    async fn summon() {
+        let seed_arch = hcor::CONFIG
+            .possession_archetypes
+            .iter()
+            .position(|a| a.seed.is_some())
             .expect("no items in config that are seeds?");
         let not_seed_arch = hcor::CONFIG
             .possession_archetypes
             .iter()
-            .find(|a| a.seed.is_none())
+            .position(|a| a.seed.is_none())
             .expect("no items in config that aren't seeds?");
 
         // create bob's stead!
-        let mut bobstead = Hackstead::register().await?;
-
-        let seed_item = seed_arch.spawn().await?;
-        let not_seed_item = not_seed_arch.spawn().await?;
-        let open_tile = bobstead.free_tile().expect("fresh hackstead no open land?");
+        let h = Harness::start().await;
+        let bobstead = h.summon().await;
+        let mut wormhole = h.wormhole(&bobstead).await;
+
+        let seed_item = wormhole.spawn(seed_arch, 1).await.remove(0);
+        let not_seed_item = wormhole.spawn(not_seed_arch, 1).await.remove(0);
+        let open_tile = bobstead
+            .free_tiles()
+            .next()
+            .expect("fresh hackstead no open land?")
+            .tile_id;
 
         struct NewPlantAssumptions {
             expected_success: bool,
@@ -100,17 +108,24 @@ mod test {
         }
 
         async fn new_plant_assuming(
-            bobstead: &mut Hackstead,
-            tile: &Tile,
+            h: &Harness,
+            bobstead: &Hackstead,
+            wormhole: &mut Wormhole,
+            tile_id: TileId,
             seed_item: &Item,
             assumptions: NewPlantAssumptions,
-        ) -> hcor::ClientResult<()> {
-            let requested_plant = tile.plant_seed(seed_item).await;
-
-            match (assumptions.expected_success, requested_plant) {
-                (true, Ok(plant)) => {
+        ) {
+            let ask_id = wormhole
+                .ask(Ask::Plant(PlantAsk::Summon {
+                    tile_id,
+                    seed_item_id: seed_item.item_id,
+                }))
+                .await;
+
+            match (assumptions.expected_success, wormhole.asked(ask_id).await) {
+                (true, AskedNote::PlantSummonResult(Ok(plant))) => {
                     assert_eq!(
-                        plant.tile_id, tile.tile_id,
+                        plant.tile_id, tile_id,
                         "plant planted for bob is on a different tile than expected: {:#?}",
                         plant
                     );
@@ -120,13 +135,13 @@ mod test {
                         "seed grew into unexpected type of plant"
                     );
                 }
-                (false, Err(e)) => info!("/plant/new failed as expected: {}", e),
-                (true, Err(e)) => panic!("/plant/new unexpectedly failed: {}", e),
-                (false, Ok(tile)) => panic!("/plant/new unexpectedly returned plant: {:#?}", tile),
+                (false, AskedNote::PlantSummonResult(Err(e))) => {
+                    info!("plant summon failed as expected: {}", e)
+                }
+                (_, other) => panic!("unexpected response to plant summon: {:?}", other),
             };
 
-            *bobstead = Hackstead::fetch(&*bobstead).await?;
-
+            let bobstead = h.spy(bobstead).await.unwrap();
             assert_eq!(
                 assumptions.item_consumed,
                 !bobstead.has_item(seed_item),
@@ -137,49 +152,50 @@ mod test {
                     "consumed"
                 }
             );
-
-            Ok(())
         }
 
         // try to plant this non-seed item
         new_plant_assuming(
-            &mut bobstead,
-            &open_tile,
+            &h,
+            &bobstead,
+            &mut wormhole,
+            open_tile,
             &not_seed_item,
             NewPlantAssumptions {
                 expected_success: false,
                 item_consumed: false,
             },
         )
-        .await?;
+        .await;
 
         // try and redeem an item that's actually a seed, this should actually work
         new_plant_assuming(
-            &mut bobstead,
-            &open_tile,
+            &h,
+            &bobstead,
+            &mut wormhole,
+            open_tile,
             &seed_item,
             NewPlantAssumptions {
                 expected_success: true,
                 item_consumed: true,
             },
         )
-        .await?;
+        .await;
 
         // try and redeem the item that's already been consumed
         new_plant_assuming(
-            &mut bobstead,
-            &open_tile,
+            &h,
+            &bobstead,
+            &mut wormhole,
+            open_tile,
             &seed_item,
             NewPlantAssumptions {
                 expected_success: false,
                 item_consumed: true,
             },
         )
-        .await?;
-
-        // kill bob so he's not left
-        bobstead.slaughter().await?;
+        .await;
 
-        Ok(())
+        h.stop().await;
     }
 }
@@ -0,0 +1,151 @@
+//! Which version of the wormhole protocol a client speaks.
+//!
+//! Clients share [`Note`](hcor::Note) and [`Ask`](hcor::Ask) with us through `hcor`, so a change
+//! to either would otherwise go unnoticed until an older client choked on a message, which over
+//! the Bincode orifice usually means garbage rather than an error. Instead, clients say which
+//! version they speak in the `WormholeVersion` header when they connect, and we either speak it
+//! back to them or turn them away before the wormhole is ever opened.
+//!
+//! Clients that don't send the header at all predate it, and are spoken to in version 1.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The newest version of the protocol, spoken by clients built against the current `hcor`.
+pub const CURRENT: Version = Version::V4;
+/// The oldest version of the protocol we still have a compatibility layer for.
+pub const OLDEST: Version = Version::V1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub enum Version {
+    /// Before version negotiation; clients only know of [`Note`](hcor::Note)s, so anything
+    /// else is left out of the wormhole, and on the Bincode orifice, no text messages are sent.
+    V1,
+    /// [`ProtocolNote`](super::protocol::ProtocolNote)s are sent alongside
+    /// [`Note`](hcor::Note)s, always as JSON text.
+    V2,
+    /// What's sent each way is wrapped in an [`Inbound`](super::protocol::Inbound) or
+    /// [`Outbound`](super::protocol::Outbound), which makes room for batches.
+    V3,
+    /// Edits sent through the MessagePack and CBOR orifices come as an
+    /// [`Outbound::Edit`](super::protocol::Outbound::Edit), rather than as an
+    /// [`EditNote`](hcor::wormhole::EditNote) meant for Bincode.
+    V4,
+}
+
+impl Version {
+    pub fn number(self) -> u32 {
+        match self {
+            Version::V1 => 1,
+            Version::V2 => 2,
+            Version::V3 => 3,
+            Version::V4 => 4,
+        }
+    }
+
+    /// Whether clients speaking this version know what to do with a `ProtocolNote`.
+    pub fn knows_protocol_notes(self) -> bool {
+        self >= Version::V2
+    }
+
+    /// Whether messages to and from clients speaking this version are wrapped in an
+    /// `Inbound` or `Outbound`.
+    pub fn wraps_messages(self) -> bool {
+        self >= Version::V3
+    }
+
+    /// Whether clients speaking this version are sent edits as an `Outbound::Edit` when their
+    /// orifice has no `EditNote` of its own.
+    pub fn frames_edits(self) -> bool {
+        self >= Version::V4
+    }
+}
+
+impl Default for Version {
+    /// The version spoken by clients that don't tell us which they speak.
+    fn default() -> Self {
+        Version::V1
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.number())
+    }
+}
+
+impl From<Version> for u32 {
+    fn from(v: Version) -> u32 {
+        v.number()
+    }
+}
+
+impl std::convert::TryFrom<u32> for Version {
+    type Error = Error;
+
+    fn try_from(n: u32) -> Result<Self, Error> {
+        match n {
+            1 => Ok(Version::V1),
+            2 => Ok(Version::V2),
+            3 => Ok(Version::V3),
+            4 => Ok(Version::V4),
+            n => Err(Unsupported(n)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The client asked for a version we don't have a compatibility layer for.
+    Unsupported(u32),
+}
+use Error::*;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "couldn't agree on a wormhole protocol version: ")?;
+        match self {
+            Unsupported(n) if *n > CURRENT.number() => write!(
+                f,
+                "you speak version {}, but this server only knows versions {} through {}; \
+                 it's older than your client, so try again once it's been updated",
+                n, OLDEST, CURRENT
+            ),
+            Unsupported(n) => write!(
+                f,
+                "you speak version {}, but this server only knows versions {} through {}; \
+                 please update your client",
+                n, OLDEST, CURRENT
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod test {
+    use super::{Version, CURRENT, OLDEST};
+
+    #[test]
+    fn negotiation() {
+        for n in OLDEST.number()..=CURRENT.number() {
+            let v: Version = serde_json::from_str(&n.to_string()).unwrap();
+            assert_eq!(v.number(), n);
+            assert_eq!(serde_json::to_string(&v).unwrap(), n.to_string());
+        }
+
+        for n in &[0, CURRENT.number() + 1] {
+            let e = serde_json::from_str::<Version>(&n.to_string()).unwrap_err();
+            assert!(e.to_string().contains("versions 1 through"), "{}", e);
+        }
+
+        assert_eq!(Version::default(), OLDEST);
+        assert!(!Version::V1.knows_protocol_notes());
+        assert!(CURRENT.knows_protocol_notes());
+        assert!(!Version::V2.wraps_messages());
+        assert!(CURRENT.wraps_messages());
+        assert!(!Version::V3.frames_edits());
+        assert!(CURRENT.frames_edits());
+    }
+}